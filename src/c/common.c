@@ -13,3 +13,21 @@ void write_term(char term[32]) {
     term[0] = -128;  // shabal message termination bit
     memset(&term[1], 0, 31);
 }
+
+void write_nonce_seed(char *gendata, uint64_t numeric_id, uint64_t nonce) {
+    numeric_id = bswap_64(numeric_id);
+    nonce = bswap_64(nonce);
+    memmove(&gendata[NONCE_SIZE], &numeric_id, 8);
+    memmove(&gendata[NONCE_SIZE + 8], &nonce, 8);
+}
+
+void finish_nonce(char *gendata, const char *final_hash, char *dst) {
+    for (size_t i = 0; i < NONCE_SIZE; i++) gendata[i] ^= final_hash[i % HASH_SIZE];
+    // PoC2: the second hash of every scoop is swapped with the one of its mirror scoop
+    for (size_t scoop = 0; scoop < NUM_SCOOPS; scoop++) {
+        size_t mirror = NUM_SCOOPS - 1 - scoop;
+        memmove(&dst[scoop * SCOOP_SIZE], &gendata[scoop * SCOOP_SIZE], HASH_SIZE);
+        memmove(&dst[scoop * SCOOP_SIZE + HASH_SIZE], &gendata[mirror * SCOOP_SIZE + HASH_SIZE],
+                HASH_SIZE);
+    }
+}
@@ -58,6 +58,12 @@ void write_seed(char seed[32], uint64_t numeric_id);
 
 void write_term(char term[32]);
 
+// writes the numeric id and the nonce behind the NONCE_SIZE bytes of gendata
+void write_nonce_seed(char *gendata, uint64_t numeric_id, uint64_t nonce);
+
+// xors the hashes of gendata with the final hash and writes them to dst in PoC2 layout
+void finish_nonce(char *gendata, const char *final_hash, char *dst);
+
 #define SET_BEST_DEADLINE(d, o) \
     if ((d) < *best_deadline) { \
         *best_deadline = (d);   \
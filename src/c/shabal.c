@@ -10,4 +10,25 @@ void find_best_deadline_sph(char *scoops, uint64_t nonce_count, char *gensig,
 		sph_shabal_deadline_fast(&scoops[i * 64], gensig, &dl);
         SET_BEST_DEADLINE(dl, i);
     }
-}
\ No newline at end of file
+}
+void noncegen_sph(char *cache, uint64_t numeric_id, uint64_t start_nonce, uint64_t nonces) {
+    char *gendata = malloc(NONCE_SIZE + 16);
+    char final_hash[HASH_SIZE];
+    sph_shabal_context x;
+
+    for (uint64_t n = 0; n < nonces; n++) {
+        write_nonce_seed(gendata, numeric_id, start_nonce + n);
+        for (size_t i = NONCE_SIZE; i > 0; i -= HASH_SIZE) {
+            size_t len = NONCE_SIZE + 16 - i;
+            if (len > HASH_CAP) len = HASH_CAP;
+            sph_shabal256_init(&x);
+            sph_shabal256(&x, (unsigned char *)&gendata[i], len);
+            sph_shabal256_close(&x, &gendata[i - HASH_SIZE]);
+        }
+        sph_shabal256_init(&x);
+        sph_shabal256(&x, (unsigned char *)gendata, NONCE_SIZE + 16);
+        sph_shabal256_close(&x, final_hash);
+        finish_nonce(gendata, final_hash, &cache[n * NONCE_SIZE]);
+    }
+    free(gendata);
+}
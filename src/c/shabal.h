@@ -5,3 +5,5 @@
 
 void find_best_deadline_sph(char *scoops, uint64_t nonce_count, char *gensig,
                              uint64_t *best_deadline, uint64_t *best_offset);
+
+void noncegen_sph(char *cache, uint64_t numeric_id, uint64_t start_nonce, uint64_t nonces);
@@ -2,6 +2,7 @@
 #include <immintrin.h>
 #include <string.h>
 #include "common.h"
+#include "shabal.h"
 #include "mshabal_128_avx.h"
 #include "sph_shabal.h"
 
@@ -73,3 +74,37 @@ void find_best_deadline_avx(char *scoops, uint64_t nonce_count, char *gensig,
         }
     }
 }
+
+void noncegen_avx(char *cache, uint64_t numeric_id, uint64_t start_nonce, uint64_t nonces) {
+    char *gendata = malloc(4 * (NONCE_SIZE + 16));
+    char *g[4];
+    char final_hash[4][HASH_SIZE];
+    for (int j = 0; j < 4; j++) g[j] = &gendata[j * (NONCE_SIZE + 16)];
+
+    // fresh context, copied for every hash
+    mshabal128_context init, x;
+    mshabal_init_avx(&init, 256);
+
+    uint64_t n = 0;
+    for (; n + 4 <= nonces; n += 4) {
+        for (int j = 0; j < 4; j++) write_nonce_seed(g[j], numeric_id, start_nonce + n + j);
+        for (size_t i = NONCE_SIZE; i > 0; i -= HASH_SIZE) {
+            size_t len = NONCE_SIZE + 16 - i;
+            if (len > HASH_CAP) len = HASH_CAP;
+            memcpy(&x, &init, sizeof(init));
+            mshabal_avx(&x, g[0] + i, g[1] + i, g[2] + i, g[3] + i, len);
+            mshabal_close_avx(&x, 0, 0, 0, 0, 0, g[0] + i - HASH_SIZE, g[1] + i - HASH_SIZE,
+                g[2] + i - HASH_SIZE, g[3] + i - HASH_SIZE);
+        }
+        memcpy(&x, &init, sizeof(init));
+        mshabal_avx(&x, g[0], g[1], g[2], g[3], NONCE_SIZE + 16);
+        mshabal_close_avx(&x, 0, 0, 0, 0, 0, final_hash[0], final_hash[1], final_hash[2],
+            final_hash[3]);
+        for (int j = 0; j < 4; j++)
+            finish_nonce(g[j], final_hash[j], &cache[(n + j) * NONCE_SIZE]);
+    }
+    free(gendata);
+
+    // the remainder doesn't fill all lanes
+    noncegen_sph(&cache[n * NONCE_SIZE], numeric_id, start_nonce + n, nonces - n);
+}
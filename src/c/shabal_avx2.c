@@ -2,6 +2,7 @@
 #include <immintrin.h>
 #include <string.h>
 #include "common.h"
+#include "shabal.h"
 #include "mshabal_256_avx2.h"
 #include "sph_shabal.h"
 
@@ -93,3 +94,41 @@ void find_best_deadline_avx2(char *scoops, uint64_t nonce_count, char *gensig,
         }
     }
 }
+
+void noncegen_avx2(char *cache, uint64_t numeric_id, uint64_t start_nonce, uint64_t nonces) {
+    char *gendata = malloc(8 * (NONCE_SIZE + 16));
+    char *g[8];
+    char final_hash[8][HASH_SIZE];
+    for (int j = 0; j < 8; j++) g[j] = &gendata[j * (NONCE_SIZE + 16)];
+
+    // fresh context, copied for every hash
+    mshabal256_context init, x;
+    mshabal_init_avx2(&init, 256);
+
+    uint64_t n = 0;
+    for (; n + 8 <= nonces; n += 8) {
+        for (int j = 0; j < 8; j++) write_nonce_seed(g[j], numeric_id, start_nonce + n + j);
+        for (size_t i = NONCE_SIZE; i > 0; i -= HASH_SIZE) {
+            size_t len = NONCE_SIZE + 16 - i;
+            if (len > HASH_CAP) len = HASH_CAP;
+            memcpy(&x, &init, sizeof(init));
+            mshabal_avx2(&x, g[0] + i, g[1] + i, g[2] + i, g[3] + i, g[4] + i, g[5] + i, g[6] + i,
+                g[7] + i, len);
+            mshabal_close_avx2(&x, 0, 0, 0, 0, 0, 0, 0, 0, 0, g[0] + i - HASH_SIZE,
+                g[1] + i - HASH_SIZE, g[2] + i - HASH_SIZE, g[3] + i - HASH_SIZE,
+                g[4] + i - HASH_SIZE, g[5] + i - HASH_SIZE, g[6] + i - HASH_SIZE,
+                g[7] + i - HASH_SIZE);
+        }
+        memcpy(&x, &init, sizeof(init));
+        mshabal_avx2(&x, g[0], g[1], g[2], g[3], g[4], g[5], g[6], g[7], NONCE_SIZE + 16);
+        mshabal_close_avx2(&x, 0, 0, 0, 0, 0, 0, 0, 0, 0, final_hash[0], final_hash[1],
+            final_hash[2], final_hash[3], final_hash[4], final_hash[5], final_hash[6],
+            final_hash[7]);
+        for (int j = 0; j < 8; j++)
+            finish_nonce(g[j], final_hash[j], &cache[(n + j) * NONCE_SIZE]);
+    }
+    free(gendata);
+
+    // the remainder doesn't fill all lanes
+    noncegen_sph(&cache[n * NONCE_SIZE], numeric_id, start_nonce + n, nonces - n);
+}
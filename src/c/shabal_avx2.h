@@ -7,3 +7,5 @@ void init_shabal_avx2();
 
 void find_best_deadline_avx2(char *scoops, uint64_t nonce_count, char *gensig,
                              uint64_t *best_deadline, uint64_t *best_offset);
+
+void noncegen_avx2(char *cache, uint64_t numeric_id, uint64_t start_nonce, uint64_t nonces);
@@ -2,6 +2,7 @@
 #include <immintrin.h>
 #include <string.h>
 #include "common.h"
+#include "shabal.h"
 #include "mshabal_512_avx512f.h"
 #include "sph_shabal.h"
 
@@ -136,3 +137,47 @@ void find_best_deadline_avx512f(char *scoops, uint64_t nonce_count, char *gensig
         }
     }
 }
+
+void noncegen_avx512f(char *cache, uint64_t numeric_id, uint64_t start_nonce, uint64_t nonces) {
+    char *gendata = malloc(16 * (NONCE_SIZE + 16));
+    char *g[16];
+    char final_hash[16][HASH_SIZE];
+    for (int j = 0; j < 16; j++) g[j] = &gendata[j * (NONCE_SIZE + 16)];
+
+    // fresh context, copied for every hash
+    mshabal512_context init, x;
+    mshabal_init_avx512f(&init, 256);
+
+    uint64_t n = 0;
+    for (; n + 16 <= nonces; n += 16) {
+        for (int j = 0; j < 16; j++) write_nonce_seed(g[j], numeric_id, start_nonce + n + j);
+        for (size_t i = NONCE_SIZE; i > 0; i -= HASH_SIZE) {
+            size_t len = NONCE_SIZE + 16 - i;
+            if (len > HASH_CAP) len = HASH_CAP;
+            memcpy(&x, &init, sizeof(init));
+            mshabal_avx512f(&x, g[0] + i, g[1] + i, g[2] + i, g[3] + i, g[4] + i, g[5] + i,
+                g[6] + i, g[7] + i, g[8] + i, g[9] + i, g[10] + i, g[11] + i, g[12] + i, g[13] + i,
+                g[14] + i, g[15] + i, len);
+            mshabal_close_avx512f(&x, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                g[0] + i - HASH_SIZE, g[1] + i - HASH_SIZE, g[2] + i - HASH_SIZE,
+                g[3] + i - HASH_SIZE, g[4] + i - HASH_SIZE, g[5] + i - HASH_SIZE,
+                g[6] + i - HASH_SIZE, g[7] + i - HASH_SIZE, g[8] + i - HASH_SIZE,
+                g[9] + i - HASH_SIZE, g[10] + i - HASH_SIZE, g[11] + i - HASH_SIZE,
+                g[12] + i - HASH_SIZE, g[13] + i - HASH_SIZE, g[14] + i - HASH_SIZE,
+                g[15] + i - HASH_SIZE);
+        }
+        memcpy(&x, &init, sizeof(init));
+        mshabal_avx512f(&x, g[0], g[1], g[2], g[3], g[4], g[5], g[6], g[7], g[8], g[9], g[10],
+            g[11], g[12], g[13], g[14], g[15], NONCE_SIZE + 16);
+        mshabal_close_avx512f(&x, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, final_hash[0],
+            final_hash[1], final_hash[2], final_hash[3], final_hash[4], final_hash[5],
+            final_hash[6], final_hash[7], final_hash[8], final_hash[9], final_hash[10],
+            final_hash[11], final_hash[12], final_hash[13], final_hash[14], final_hash[15]);
+        for (int j = 0; j < 16; j++)
+            finish_nonce(g[j], final_hash[j], &cache[(n + j) * NONCE_SIZE]);
+    }
+    free(gendata);
+
+    // the remainder doesn't fill all lanes
+    noncegen_sph(&cache[n * NONCE_SIZE], numeric_id, start_nonce + n, nonces - n);
+}
@@ -1,7 +1,9 @@
-use bytes::Buf;
-use reqwest::r#async::Chunk;
+use crate::com::compat::Incompatibility;
+use crate::com::round::RoundSchedule;
 use serde::de::{self, DeserializeOwned};
 use std::fmt;
+use std::time::Duration;
+use std::u64;
 use codec::{
     Encode,
 };
@@ -47,7 +49,66 @@ pub struct MiningInfoResponse {
 
     pub target_deadline: u64,
 
+    /// Milliseconds since the last block was mined, `u64::MAX` if the chain doesn't tell.
     pub duration_from_last_mining: u64,
+
+    pub round_schedule: RoundSchedule,
+}
+
+/// Mining info as returned by Burst-style Pools/Proxies/Wallets.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpMiningInfoResponse {
+    pub generation_signature: String,
+
+    #[serde(deserialize_with = "from_str_or_int")]
+    pub base_target: u64,
+
+    #[serde(deserialize_with = "from_str_or_int")]
+    pub height: u64,
+
+    #[serde(
+        default = "default_target_deadline",
+        deserialize_with = "from_str_or_int"
+    )]
+    pub target_deadline: u64,
+}
+
+impl HttpMiningInfoResponse {
+    pub fn into_mining_info(self) -> Result<MiningInfoResponse, PoolError> {
+        let mut generation_signature = [0u8; 32];
+        match hex::decode(&self.generation_signature) {
+            Ok(ref gensig) if gensig.len() == 32 => generation_signature.copy_from_slice(gensig),
+            _ => {
+                return Err(PoolError {
+                    code: 0,
+                    message: format!(
+                        "invalid generation signature: {}",
+                        self.generation_signature
+                    ),
+                })
+            }
+        }
+        Ok(MiningInfoResponse {
+            generation_signature,
+            base_target: self.base_target,
+            height: self.height,
+            target_deadline: self.target_deadline,
+            duration_from_last_mining: u64::MAX,
+            round_schedule: RoundSchedule::per_block(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpSubmitNonceResponse {
+    #[serde(deserialize_with = "from_str_or_int")]
+    pub deadline: u64,
+}
+
+fn default_target_deadline() -> u64 {
+    u64::MAX
 }
 
 #[derive(Debug, Deserialize)]
@@ -67,13 +128,59 @@ pub struct PoolError {
 pub enum FetchError {
     Http(reqwest::Error),
     Pool(PoolError),
+    /// Transport or rpc error of the node.
     Substrate(substrate_subxt::Error),
+    /// Storage item or event that doesn't decode.
     Pcodec(codec::Error),
+    /// Storage item the miner can't do without, e.g. `Timestamp::Now`.
+    MissingStorage(String),
+    /// The node doesn't know its best block.
+    MissingBlock,
+    /// The node is behind a block another node had already reported.
+    Lagging { height: u64, best: u64 },
+    /// The node didn't answer within the timeout.
+    Timeout(Duration),
+    /// The node runs an unexpected runtime or network, retrying won't help.
+    Incompatible(Vec<Incompatibility>),
+    /// The round of a submission is over.
+    Expired { height: u64, current: u64 },
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FetchError::Http(e) => write!(f, "http: {}", e),
+            FetchError::Pool(e) => write!(f, "pool: code={}, message={}", e.code, e.message),
+            FetchError::Substrate(e) => write!(f, "substrate: {}", e),
+            FetchError::Pcodec(e) => write!(f, "decode: {}", e),
+            FetchError::MissingStorage(item) => write!(f, "missing storage: {}", item),
+            FetchError::MissingBlock => write!(f, "missing best block"),
+            FetchError::Lagging { height, best } => {
+                write!(f, "node lags behind: height={}, best={}", height, best)
+            }
+            FetchError::Timeout(timeout) => {
+                write!(f, "node didn't answer within {}ms", timeout.as_millis())
+            }
+            FetchError::Incompatible(incompatibilities) => {
+                let incompatibilities: Vec<String> =
+                    incompatibilities.iter().map(|i| i.to_string()).collect();
+                write!(f, "incompatible node: {}", incompatibilities.join("; "))
+            }
+            FetchError::Expired { height, current } => write!(
+                f,
+                "submission expired: height={}, current height={}",
+                height, current
+            ),
+        }
+    }
 }
 
 impl From<substrate_subxt::Error> for FetchError {
     fn from(err: substrate_subxt::Error) -> FetchError {
-        FetchError::Substrate(err)
+        match err {
+            substrate_subxt::Error::Codec(err) => FetchError::Pcodec(err),
+            err => FetchError::Substrate(err),
+        }
     }
 }
 
@@ -94,3 +201,78 @@ impl From<PoolError> for FetchError {
         FetchError::Pool(err)
     }
 }
+
+// pools and wallets disagree on whether numbers are sent as strings
+fn from_str_or_int<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    struct StringOrIntVisitor;
+
+    impl<'de> de::Visitor<'de> for StringOrIntVisitor {
+        type Value = u64;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("string or int")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+            value.parse::<u64>().map_err(de::Error::custom)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+            Ok(value)
+        }
+    }
+
+    deserializer.deserialize_any(StringOrIntVisitor)
+}
+
+pub fn parse_json_result<T: DeserializeOwned>(body: &[u8]) -> Result<T, PoolError> {
+    match serde_json::from_slice(body) {
+        Ok(x) => Ok(x),
+        _ => match serde_json::from_slice::<PoolErrorWrapper>(body) {
+            Ok(x) => Err(x.error),
+            _ => Err(PoolError {
+                code: 0,
+                message: String::from_utf8_lossy(body).to_string(),
+            }),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mining_info() {
+        let body = br#"{"generationSignature":"ef9b7b6e8b0ba3a4fd2f5d7fea31ce2b2c0ec5c4dcf2c2a3e0d8a9f5e7a4b1c2",
+                "baseTarget":"70312","height":"452134","targetDeadline":31536000}"#;
+        let mining_info = parse_json_result::<HttpMiningInfoResponse>(body)
+            .unwrap()
+            .into_mining_info()
+            .unwrap();
+        assert_eq!(mining_info.base_target, 70312);
+        assert_eq!(mining_info.height, 452134);
+        assert_eq!(mining_info.target_deadline, 31536000);
+        assert_eq!(mining_info.generation_signature[0], 0xef);
+
+        let body = br#"{"generationSignature":"ef9b","baseTarget":1,"height":2}"#;
+        let mining_info = parse_json_result::<HttpMiningInfoResponse>(body).unwrap();
+        assert_eq!(mining_info.target_deadline, u64::MAX);
+        assert!(mining_info.into_mining_info().is_err());
+    }
+
+    #[test]
+    fn test_parse_pool_error() {
+        let body = br#"{"error":{"code":1008,"message":"deadline exceeds limit"}}"#;
+        let err = parse_json_result::<HttpSubmitNonceResponse>(body).err().unwrap();
+        assert_eq!(err.code, 1008);
+        assert_eq!(err.message, "deadline exceeds limit");
+
+        let body = b"502 Bad Gateway";
+        let err = parse_json_result::<HttpSubmitNonceResponse>(body).err().unwrap();
+        assert_eq!(err.message, "502 Bad Gateway");
+    }
+}
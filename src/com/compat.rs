@@ -0,0 +1,376 @@
+//! Checks that a node runs a runtime the miner can talk to.
+//!
+//! The storage items, the `mining` call and the `VerifyDeadline` event in the metadata of the
+//! node are compared with the ones of the runtime the miner is built against. A changed layout
+//! then stops the miner on connect instead of failing to decode mid-round.
+
+use codec::{Decode, Encode};
+use frame_support::metadata::{
+    DecodeDifferent, DecodeDifferentStr, EventMetadata, FunctionMetadata, ModuleMetadata,
+    RuntimeMetadata, RuntimeMetadataPrefixed, RuntimeMetadataV11, StorageEntryMetadata,
+    StorageEntryType,
+};
+use std::fmt;
+
+pub const POC_MODULE: &str = "PoC";
+pub const TS_MODULE: &str = "Timestamp";
+
+pub const MINING: &str = "mining";
+pub const VERIFY_DEADLINE: &str = "VerifyDeadline";
+
+/// Storage items read by the miner.
+const STORAGE: &[(&str, &str)] = &[
+    (POC_MODULE, "DlInfo"),
+    (POC_MODULE, "TargetInfo"),
+    (POC_MODULE, "LastMiningTs"),
+    (TS_MODULE, "Now"),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Incompatibility {
+    /// Metadata of a version the miner doesn't understand.
+    UnsupportedMetadata(u8),
+    Missing(String),
+    Mismatch {
+        item: String,
+        node: String,
+        miner: String,
+    },
+    WrongGenesis {
+        node: String,
+        expected: String,
+    },
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Incompatibility::UnsupportedMetadata(version) => {
+                write!(f, "unsupported metadata version {}", version)
+            }
+            Incompatibility::Missing(item) => write!(f, "{} is missing", item),
+            Incompatibility::Mismatch { item, node, miner } => write!(
+                f,
+                "{} differs, node has '{}', miner expects '{}'",
+                item, node, miner
+            ),
+            Incompatibility::WrongGenesis { node, expected } => write!(
+                f,
+                "wrong network, genesis hash is {}, expected {}",
+                node, expected
+            ),
+        }
+    }
+}
+
+/// Metadata of the runtime the miner is built against.
+pub fn expected_metadata() -> RuntimeMetadataPrefixed {
+    // the compiled in metadata only references static data, decoding it again makes it
+    // comparable with the one of the node
+    let encoded = sub_runtime::Runtime::metadata().encode();
+    RuntimeMetadataPrefixed::decode(&mut &encoded[..])
+        .expect("metadata of the compiled runtime decodes")
+}
+
+/// Compares everything the miner uses in the `node` metadata with the `expected` one.
+pub fn check_metadata(
+    node: &RuntimeMetadataPrefixed,
+    expected: &RuntimeMetadataPrefixed,
+) -> Vec<Incompatibility> {
+    let (node, expected) = match (v11(node), v11(expected)) {
+        (Ok(node), Ok(expected)) => (node, expected),
+        (Err(version), _) | (_, Err(version)) => {
+            return vec![Incompatibility::UnsupportedMetadata(version)]
+        }
+    };
+
+    let mut items = Vec::new();
+    for (module, name) in STORAGE {
+        items.push((
+            format!("storage {}::{}", module, name),
+            storage_entry(node, module, name).map(storage_signature),
+            storage_entry(expected, module, name).map(storage_signature),
+        ));
+    }
+    items.push((
+        format!("call {}::{}", POC_MODULE, MINING),
+        call(node, POC_MODULE, MINING).map(call_signature),
+        call(expected, POC_MODULE, MINING).map(call_signature),
+    ));
+    items.push((
+        format!("event {}::{}", POC_MODULE, VERIFY_DEADLINE),
+        event(node, POC_MODULE, VERIFY_DEADLINE).map(event_signature),
+        event(expected, POC_MODULE, VERIFY_DEADLINE).map(event_signature),
+    ));
+
+    items
+        .into_iter()
+        .filter_map(|(item, node, miner)| match (node, miner) {
+            (None, _) => Some(Incompatibility::Missing(item)),
+            (Some(node), Some(ref miner)) if node == *miner => None,
+            (Some(node), miner) => Some(Incompatibility::Mismatch {
+                item,
+                node,
+                miner: miner.unwrap_or_else(|| "nothing".to_owned()),
+            }),
+        })
+        .collect()
+}
+
+/// `expected` is the hex encoded hash, with or without `0x`.
+pub fn check_genesis(genesis: &[u8], expected: &str) -> Result<(), Incompatibility> {
+    let node = hex::encode(genesis);
+    let expected = expected.trim().trim_start_matches("0x").to_lowercase();
+    if node == expected {
+        Ok(())
+    } else {
+        Err(Incompatibility::WrongGenesis {
+            node: format!("0x{}", node),
+            expected: format!("0x{}", expected),
+        })
+    }
+}
+
+// the version is the index of the enum variant
+fn v11(metadata: &RuntimeMetadataPrefixed) -> Result<&RuntimeMetadataV11, u8> {
+    match metadata.1 {
+        RuntimeMetadata::V11(ref metadata) => Ok(metadata),
+        ref other => Err(other.encode()[0]),
+    }
+}
+
+// only decoded metadata is compared, statically referenced data counts as missing
+fn decoded<B: 'static, O: 'static>(value: &DecodeDifferent<B, O>) -> Option<&O> {
+    match value {
+        DecodeDifferent::Decoded(value) => Some(value),
+        DecodeDifferent::Encode(_) => None,
+    }
+}
+
+fn string(value: &DecodeDifferentStr) -> &str {
+    decoded(value).map(String::as_str).unwrap_or("")
+}
+
+fn module<'a>(metadata: &'a RuntimeMetadataV11, name: &str) -> Option<&'a ModuleMetadata> {
+    decoded(&metadata.modules)?
+        .iter()
+        .find(|module| string(&module.name) == name)
+}
+
+fn storage_entry<'a>(
+    metadata: &'a RuntimeMetadataV11,
+    module_name: &str,
+    name: &str,
+) -> Option<&'a StorageEntryMetadata> {
+    let storage = decoded(module(metadata, module_name)?.storage.as_ref()?)?;
+    decoded(&storage.entries)?
+        .iter()
+        .find(|entry| string(&entry.name) == name)
+}
+
+fn call<'a>(
+    metadata: &'a RuntimeMetadataV11,
+    module_name: &str,
+    name: &str,
+) -> Option<&'a FunctionMetadata> {
+    decoded(module(metadata, module_name)?.calls.as_ref()?)?
+        .iter()
+        .find(|call| string(&call.name) == name)
+}
+
+fn event<'a>(
+    metadata: &'a RuntimeMetadataV11,
+    module_name: &str,
+    name: &str,
+) -> Option<&'a EventMetadata> {
+    decoded(module(metadata, module_name)?.event.as_ref()?)?
+        .iter()
+        .find(|event| string(&event.name) == name)
+}
+
+/// Module and call index of `module_name::name`, modules without calls aren't counted.
+#[cfg(test)]
+pub fn call_index(
+    metadata: &RuntimeMetadataPrefixed,
+    module_name: &str,
+    name: &str,
+) -> Option<(u8, u8)> {
+    let modules = decoded(&v11(metadata).ok()?.modules)?;
+    let (module_index, calls) = modules
+        .iter()
+        .filter_map(|module| Some((string(&module.name), decoded(module.calls.as_ref()?)?)))
+        .enumerate()
+        .find(|(_, (module, _))| *module == module_name)
+        .map(|(i, (_, calls))| (i, calls))?;
+    let call_index = calls.iter().position(|call| string(&call.name) == name)?;
+    Some((module_index as u8, call_index as u8))
+}
+
+/// Module and event index of `module_name::name`, modules without events aren't counted.
+#[cfg(test)]
+pub fn event_index(
+    metadata: &RuntimeMetadataPrefixed,
+    module_name: &str,
+    name: &str,
+) -> Option<(u8, u8)> {
+    let modules = decoded(&v11(metadata).ok()?.modules)?;
+    let (module_index, events) = modules
+        .iter()
+        .filter_map(|module| Some((string(&module.name), decoded(module.event.as_ref()?)?)))
+        .enumerate()
+        .find(|(_, (module, _))| *module == module_name)
+        .map(|(i, (_, events))| (i, events))?;
+    let event_index = events.iter().position(|event| string(&event.name) == name)?;
+    Some((module_index as u8, event_index as u8))
+}
+
+fn storage_signature(entry: &StorageEntryMetadata) -> String {
+    let ty = match entry.ty {
+        StorageEntryType::Plain(ref value) => string(value).to_owned(),
+        StorageEntryType::Map {
+            ref key, ref value, ..
+        } => format!("map {} => {}", string(key), string(value)),
+        StorageEntryType::DoubleMap {
+            ref key1,
+            ref key2,
+            ref value,
+            ..
+        } => format!(
+            "double_map {}, {} => {}",
+            string(key1),
+            string(key2),
+            string(value)
+        ),
+    };
+    format!("{:?} {}", entry.modifier, ty)
+}
+
+fn call_signature(call: &FunctionMetadata) -> String {
+    decoded(&call.arguments)
+        .map(|args| {
+            args.iter()
+                .map(|arg| format!("{}: {}", string(&arg.name), string(&arg.ty)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .unwrap_or_default()
+}
+
+fn event_signature(event: &EventMetadata) -> String {
+    decoded(&event.arguments)
+        .map(|args| args.join(", "))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frame_support::metadata::{
+        ExtrinsicMetadata, FunctionArgumentMetadata, StorageEntryModifier, StorageMetadata,
+    };
+
+    fn s(value: &str) -> DecodeDifferentStr {
+        DecodeDifferent::Decoded(value.to_owned())
+    }
+
+    fn plain(name: &str, ty: &str) -> StorageEntryMetadata {
+        StorageEntryMetadata {
+            name: s(name),
+            modifier: StorageEntryModifier::Optional,
+            ty: StorageEntryType::Plain(s(ty)),
+            default: DecodeDifferent::Decoded(vec![]),
+            documentation: DecodeDifferent::Decoded(vec![]),
+        }
+    }
+
+    fn module(
+        name: &str,
+        storage: Vec<StorageEntryMetadata>,
+        calls: Vec<FunctionMetadata>,
+        events: Vec<EventMetadata>,
+    ) -> ModuleMetadata {
+        ModuleMetadata {
+            name: s(name),
+            storage: Some(DecodeDifferent::Decoded(StorageMetadata {
+                prefix: s(name),
+                entries: DecodeDifferent::Decoded(storage),
+            })),
+            calls: Some(DecodeDifferent::Decoded(calls)),
+            event: Some(DecodeDifferent::Decoded(events)),
+            constants: DecodeDifferent::Decoded(vec![]),
+            errors: DecodeDifferent::Decoded(vec![]),
+        }
+    }
+
+    fn metadata(height_ty: &str, with_event: bool) -> RuntimeMetadataPrefixed {
+        let mining = FunctionMetadata {
+            name: s(MINING),
+            arguments: DecodeDifferent::Decoded(vec![
+                FunctionArgumentMetadata {
+                    name: s("account_id"),
+                    ty: s("u64"),
+                },
+                FunctionArgumentMetadata {
+                    name: s("height"),
+                    ty: s(height_ty),
+                },
+            ]),
+            documentation: DecodeDifferent::Decoded(vec![]),
+        };
+        let verify_deadline = EventMetadata {
+            name: s(VERIFY_DEADLINE),
+            arguments: DecodeDifferent::Decoded(vec!["AccountId".to_owned(), "bool".to_owned()]),
+            documentation: DecodeDifferent::Decoded(vec![]),
+        };
+        let poc = module(
+            POC_MODULE,
+            vec![
+                plain("DlInfo", "Vec<MiningInfo<T::AccountId>>"),
+                plain("TargetInfo", "Vec<Difficulty>"),
+                plain("LastMiningTs", "u64"),
+            ],
+            vec![mining],
+            if with_event {
+                vec![verify_deadline]
+            } else {
+                vec![]
+            },
+        );
+        let timestamp = module(TS_MODULE, vec![plain("Now", "T::Moment")], vec![], vec![]);
+        RuntimeMetadataPrefixed(
+            0x6174_656d,
+            RuntimeMetadata::V11(RuntimeMetadataV11 {
+                modules: DecodeDifferent::Decoded(vec![poc, timestamp]),
+                extrinsic: ExtrinsicMetadata {
+                    version: 4,
+                    signed_extensions: vec![],
+                },
+            }),
+        )
+    }
+
+    #[test]
+    fn test_check_metadata() {
+        let expected = metadata("u64", true);
+        assert!(check_metadata(&metadata("u64", true), &expected).is_empty());
+        assert_eq!(
+            check_metadata(&metadata("u32", false), &expected),
+            vec![
+                Incompatibility::Mismatch {
+                    item: "call PoC::mining".to_owned(),
+                    node: "account_id: u64, height: u32".to_owned(),
+                    miner: "account_id: u64, height: u64".to_owned(),
+                },
+                Incompatibility::Missing("event PoC::VerifyDeadline".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_genesis() {
+        let genesis = [0xabu8; 32];
+        assert!(check_genesis(&genesis, &format!("0x{}", "AB".repeat(32))).is_ok());
+        assert!(check_genesis(&genesis, &"ab".repeat(32)).is_ok());
+        assert!(check_genesis(&genesis, &"cd".repeat(32)).is_err());
+    }
+}
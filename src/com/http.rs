@@ -0,0 +1,233 @@
+use crate::com::api::*;
+use crate::com::client::{
+    ChainBackend, FetchFuture, HeadStream, ProxyDetails, SubmissionParameters,
+};
+use crate::future::interval::Interval;
+use futures::stream::Stream;
+use futures::Future;
+use reqwest::header::{HeaderMap, HeaderName};
+use reqwest::r#async::Client as InnerClient;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use url::form_urlencoded::byte_serialize;
+use url::Url;
+
+/// Backend talking to a Burst-style Pool/Proxy/Wallet.
+#[derive(Clone)]
+pub struct HttpClient {
+    inner: InnerClient,
+    account_id_to_secret_phrase: Arc<HashMap<u64, String>>,
+    base_uri: Url,
+    headers: Arc<HeaderMap>,
+    get_mining_info_interval: u64,
+}
+
+impl HttpClient {
+    fn ua() -> String {
+        "Scavenger/".to_owned() + crate_version!()
+    }
+
+    /// Create a new client communicating with Pool/Proxy/Wallet.
+    pub fn new(
+        base_uri: Url,
+        mut secret_phrases: HashMap<u64, String>,
+        get_mining_info_interval: u64,
+        timeout: u64,
+        total_size_gb: usize,
+        proxy_details: ProxyDetails,
+        additional_headers: HashMap<String, String>,
+    ) -> Self {
+        for secret_phrase in secret_phrases.values_mut() {
+            *secret_phrase = byte_serialize(secret_phrase.as_bytes()).collect();
+        }
+
+        let ua = Self::ua();
+        let mut headers = HeaderMap::new();
+        headers.insert("User-Agent", ua.parse().unwrap());
+        if proxy_details == ProxyDetails::Enabled {
+            let hostname = hostname::get_hostname().unwrap_or_else(|| "".to_owned());
+            headers.insert("X-Capacity", total_size_gb.to_string().parse().unwrap());
+            headers.insert("X-Miner", ua.parse().unwrap());
+            headers.insert("X-Minername", hostname.parse().unwrap());
+            headers.insert(
+                "X-Plotfile",
+                ("ScavengerProxy/".to_owned() + &hostname).parse().unwrap(),
+            );
+        }
+
+        for (key, value) in additional_headers {
+            let header_name = HeaderName::from_bytes(&key.into_bytes()).unwrap();
+            headers.insert(header_name, value.parse().unwrap());
+        }
+
+        let client = InnerClient::builder()
+            .timeout(Duration::from_millis(timeout))
+            .build()
+            .unwrap();
+
+        Self {
+            inner: client,
+            account_id_to_secret_phrase: Arc::new(secret_phrases),
+            base_uri,
+            headers: Arc::new(headers),
+            get_mining_info_interval,
+        }
+    }
+
+    fn uri_for(&self, path: &str) -> Url {
+        let mut url = self.base_uri.clone();
+        url.path_segments_mut()
+            .map_err(|_| "cannot be base")
+            .unwrap()
+            .pop_if_empty()
+            .push(path);
+        url
+    }
+
+    fn submit_nonce_query(&self, submission_data: &SubmissionParameters) -> String {
+        let empty = "".to_owned();
+        let secret_phrase = self
+            .account_id_to_secret_phrase
+            .get(&submission_data.account_id)
+            .unwrap_or(&empty);
+
+        let mut query = format!(
+            "requestType=submitNonce&accountId={}&nonce={}&secretPhrase={}&blockheight={}",
+            submission_data.account_id,
+            submission_data.nonce,
+            secret_phrase,
+            submission_data.height
+        );
+
+        // Without a secret phrase we most likely talk to a pool or a proxy. Both rank the
+        // deadlines without validating them and legacy proxies expect the unadjusted ones.
+        if secret_phrase.is_empty() {
+            query += &format!("&deadline={}", submission_data.deadline_unadjusted);
+        }
+        query
+    }
+}
+
+impl ChainBackend for HttpClient {
+    /// Get current mining info.
+    fn get_mining_info(&self) -> FetchFuture<MiningInfoResponse> {
+        Box::new(
+            self.inner
+                .get(self.uri_for("burst"))
+                .headers((*self.headers).clone())
+                .query(&GetMiningInfoRequest {
+                    request_type: "getMiningInfo",
+                })
+                .send()
+                .and_then(|res| res.into_body().concat2())
+                .from_err::<FetchError>()
+                .and_then(|body| -> Result<MiningInfoResponse, FetchError> {
+                    let mining_info = parse_json_result::<HttpMiningInfoResponse>(&body)?;
+                    Ok(mining_info.into_mining_info()?)
+                }),
+        )
+    }
+
+    /// Submit nonce to Pool/Proxy/Wallet.
+    fn submit_nonce(
+        &self,
+        submission_data: &SubmissionParameters,
+    ) -> FetchFuture<SubmitNonceResponse> {
+        let mut uri = self.uri_for("burst");
+        uri.set_query(Some(&self.submit_nonce_query(submission_data)));
+
+        let mut headers = (*self.headers).clone();
+        headers.insert(
+            "X-Deadline",
+            submission_data.deadline.to_string().parse().unwrap(),
+        );
+
+        let submission_data = submission_data.clone();
+        Box::new(
+            self.inner
+                .post(uri)
+                .headers(headers)
+                .send()
+                .and_then(|res| res.into_body().concat2())
+                .from_err::<FetchError>()
+                .and_then(move |body| -> Result<SubmitNonceResponse, FetchError> {
+                    let res = parse_json_result::<HttpSubmitNonceResponse>(&body)?;
+                    if res.deadline != submission_data.deadline {
+                        warn!(
+                            "submit: deadlines mismatch, height={}, account={}, nonce={}, \
+                             deadline_miner={}, deadline_pool={}",
+                            submission_data.height,
+                            submission_data.account_id,
+                            submission_data.nonce,
+                            submission_data.deadline,
+                            res.deadline
+                        );
+                    }
+                    Ok(SubmitNonceResponse {
+                        verify_result: res.deadline == submission_data.deadline,
+                    })
+                }),
+        )
+    }
+
+    /// Pools and wallets can't push blocks, so they are polled.
+    fn new_heads(&self) -> HeadStream {
+        Box::new(
+            Interval::new_interval(Duration::from_millis(self.get_mining_info_interval))
+                .map(|_| ())
+                .map_err(|e| error!("interval errored: err={:?}", e)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submission(account_id: u64) -> SubmissionParameters {
+        SubmissionParameters {
+            account_id,
+            nonce: 12,
+            height: 111,
+            block: 0,
+            deadline_unadjusted: 7123,
+            deadline: 1193,
+            gen_sig: [0; 32],
+        }
+    }
+
+    #[test]
+    fn test_submit_nonce_query() {
+        let mut secret_phrases = HashMap::new();
+        secret_phrases.insert(1337, "my secret & phrase".to_owned());
+        let client = HttpClient::new(
+            "http://wallet.example.com:8125/".parse().unwrap(),
+            secret_phrases,
+            3000,
+            3000,
+            12,
+            ProxyDetails::Enabled,
+            HashMap::new(),
+        );
+
+        assert_eq!(
+            client.uri_for("burst").as_str(),
+            "http://wallet.example.com:8125/burst"
+        );
+        assert!(client.headers.contains_key("X-Capacity"));
+
+        // solo: url-encoded secret phrase, the wallet computes the deadline itself
+        assert_eq!(
+            client.submit_nonce_query(&submission(1337)),
+            "requestType=submitNonce&accountId=1337&nonce=12\
+             &secretPhrase=my+secret+%26+phrase&blockheight=111"
+        );
+        // pool: no secret phrase, unadjusted deadline
+        assert_eq!(
+            client.submit_nonce_query(&submission(42)),
+            "requestType=submitNonce&accountId=42&nonce=12&secretPhrase=&blockheight=111\
+             &deadline=7123"
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Password encrypted key files, one per mining account.
+//!
+//! Each file holds the secret uri (same syntax as `account_id_to_secret_phrase`) encrypted with
+//! ChaCha20-Poly1305. The key is derived from the password with PBKDF2-HMAC-SHA256. The address
+//! is stored in clear so that keys can be listed without the password.
+
+use crate::com::signer::Signer;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::Hmac;
+use rand::prelude::*;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const KDF: &str = "pbkdf2-hmac-sha256";
+const CIPHER: &str = "chacha20poly1305";
+const DEFAULT_ROUNDS: u32 = 100_000;
+const FILE_EXTENSION: &str = "json";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyFile {
+    pub account_id: u64,
+    pub address: String,
+    kdf: String,
+    rounds: u32,
+    salt: String,
+    cipher: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    InvalidKey(u64, String),
+    WrongPassword(u64),
+    Exists(u64),
+    NotFound(u64),
+    NoPassword(String),
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeystoreError::Io(e) => write!(f, "keystore io: {}", e),
+            KeystoreError::Format(e) => write!(f, "malformed key file: {}", e),
+            KeystoreError::InvalidKey(id, e) => write!(f, "account {}: {}", id, e),
+            KeystoreError::WrongPassword(id) => {
+                write!(f, "account {}: wrong password or corrupt key file", id)
+            }
+            KeystoreError::Exists(id) => write!(f, "account {}: key already exists", id),
+            KeystoreError::NotFound(id) => write!(f, "account {}: no key in keystore", id),
+            KeystoreError::NoPassword(e) => write!(f, "no keystore password: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for KeystoreError {
+    fn from(err: io::Error) -> KeystoreError {
+        KeystoreError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for KeystoreError {
+    fn from(err: serde_json::Error) -> KeystoreError {
+        KeystoreError::Format(err)
+    }
+}
+
+/// Directory of encrypted key files.
+pub struct Keystore {
+    path: PathBuf,
+    rounds: u32,
+}
+
+impl Keystore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Keystore {
+            path: path.as_ref().to_path_buf(),
+            rounds: DEFAULT_ROUNDS,
+        }
+    }
+
+    /// Encrypts `secret` for `account_id` and returns the address of the key.
+    pub fn add(
+        &self,
+        account_id: u64,
+        secret: &str,
+        password: &str,
+    ) -> Result<String, KeystoreError> {
+        let signer = Signer::from_secret(secret)
+            .map_err(|e| KeystoreError::InvalidKey(account_id, e))?;
+        let file_path = self.file_path(account_id);
+        if file_path.exists() {
+            return Err(KeystoreError::Exists(account_id));
+        }
+
+        let mut rng = thread_rng();
+        let mut salt = [0u8; 32];
+        let mut nonce = [0u8; 12];
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+
+        let cipher = ChaCha20Poly1305::new(&derive_key(password, &salt, self.rounds));
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), secret.trim().as_bytes())
+            .expect("encryption failed");
+
+        let key_file = KeyFile {
+            account_id,
+            address: signer.address(),
+            kdf: KDF.to_owned(),
+            rounds: self.rounds,
+            salt: hex::encode(&salt),
+            cipher: CIPHER.to_owned(),
+            nonce: hex::encode(&nonce),
+            ciphertext: hex::encode(&ciphertext),
+        };
+
+        fs::create_dir_all(&self.path)?;
+        let mut fh = create_private(&file_path)?;
+        fh.write_all(serde_json::to_string_pretty(&key_file)?.as_bytes())?;
+        Ok(key_file.address)
+    }
+
+    /// Lists all key files without decrypting them.
+    pub fn list(&self) -> Result<Vec<KeyFile>, KeystoreError> {
+        let mut key_files = Vec::new();
+        if !self.path.is_dir() {
+            return Ok(key_files);
+        }
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(FILE_EXTENSION) {
+                continue;
+            }
+            key_files.push(serde_json::from_str(&fs::read_to_string(&path)?)?);
+        }
+        key_files.sort_by_key(|k: &KeyFile| k.account_id);
+        Ok(key_files)
+    }
+
+    pub fn remove(&self, account_id: u64) -> Result<(), KeystoreError> {
+        let file_path = self.file_path(account_id);
+        if !file_path.exists() {
+            return Err(KeystoreError::NotFound(account_id));
+        }
+        Ok(fs::remove_file(file_path)?)
+    }
+
+    /// Decrypts all keys of the keystore.
+    pub fn unlock(&self, password: &str) -> Result<HashMap<u64, Signer>, KeystoreError> {
+        self.list()?
+            .iter()
+            .map(|key_file| {
+                let secret = decrypt(key_file, password)?;
+                Signer::from_secret(&secret)
+                    .map(|signer| (key_file.account_id, signer))
+                    .map_err(|e| KeystoreError::InvalidKey(key_file.account_id, e))
+            })
+            .collect()
+    }
+
+    fn file_path(&self, account_id: u64) -> PathBuf {
+        self.path
+            .join(format!("{}.{}", account_id, FILE_EXTENSION))
+    }
+}
+
+/// Reads the keystore password from `password_file` if given, otherwise from the environment
+/// variable `password_env`.
+pub fn read_password(
+    password_file: &Option<PathBuf>,
+    password_env: &str,
+) -> Result<String, KeystoreError> {
+    match password_file {
+        Some(password_file) => {
+            let password = fs::read_to_string(password_file).map_err(|e| {
+                KeystoreError::NoPassword(format!(
+                    "can't read {}: {}",
+                    password_file.to_str().unwrap(),
+                    e
+                ))
+            })?;
+            Ok(password.trim_end_matches(|c| c == '\r' || c == '\n').to_owned())
+        }
+        None => env::var(password_env).map_err(|_| {
+            KeystoreError::NoPassword(format!(
+                "neither a password file nor ${} is set",
+                password_env
+            ))
+        }),
+    }
+}
+
+fn decrypt(key_file: &KeyFile, password: &str) -> Result<String, KeystoreError> {
+    let id = key_file.account_id;
+    if key_file.kdf != KDF || key_file.cipher != CIPHER {
+        return Err(KeystoreError::InvalidKey(
+            id,
+            format!("unsupported kdf/cipher {}/{}", key_file.kdf, key_file.cipher),
+        ));
+    }
+    let decode = |s: &str| {
+        hex::decode(s).map_err(|e| KeystoreError::InvalidKey(id, format!("invalid hex: {}", e)))
+    };
+    let salt = decode(&key_file.salt)?;
+    let nonce = decode(&key_file.nonce)?;
+    let ciphertext = decode(&key_file.ciphertext)?;
+    if nonce.len() != 12 {
+        return Err(KeystoreError::InvalidKey(id, "invalid nonce length".to_owned()));
+    }
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(password, &salt, key_file.rounds));
+    let secret = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| KeystoreError::WrongPassword(id))?;
+    String::from_utf8(secret).map_err(|_| KeystoreError::WrongPassword(id))
+}
+
+fn derive_key(password: &str, salt: &[u8], rounds: u32) -> Key {
+    let mut key = Key::default();
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, rounds, &mut key);
+    key
+}
+
+cfg_if! {
+    if #[cfg(unix)] {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        fn create_private(path: &Path) -> io::Result<fs::File> {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)
+        }
+    } else {
+        fn create_private(path: &Path) -> io::Result<fs::File> {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn test_keystore() {
+        let path = test_dir("keystore");
+        let keystore = Keystore {
+            path: path.clone(),
+            rounds: 10,
+        };
+
+        let address = keystore.add(1337, "//Alice", "secret").unwrap();
+        assert_eq!(address, "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY");
+        keystore.add(42, "ed25519://Bob", "secret").unwrap();
+        match keystore.add(42, "//Bob", "secret") {
+            Err(KeystoreError::Exists(42)) => (),
+            _ => panic!("expected existing key"),
+        }
+
+        let content = fs::read_to_string(path.join("1337.json")).unwrap();
+        assert!(!content.contains("Alice"));
+
+        let ids: Vec<u64> = keystore.list().unwrap().iter().map(|k| k.account_id).collect();
+        assert_eq!(ids, vec![42, 1337]);
+
+        let signers = keystore.unlock("secret").unwrap();
+        assert_eq!(signers[&1337].address(), address);
+        match keystore.unlock("wrong") {
+            Err(KeystoreError::WrongPassword(_)) => (),
+            _ => panic!("expected wrong password"),
+        }
+
+        keystore.remove(42).unwrap();
+        assert_eq!(keystore.list().unwrap().len(), 1);
+        assert!(keystore.remove(42).is_err());
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,482 @@
+//! A Substrate node in a box for tests.
+//!
+//! Speaks just enough JSON-RPC over websocket for `SubstrateClient`: metadata, runtime version,
+//! block hashes, headers and bodies, storage, head and storage subscriptions and watched
+//! extrinsics. Every `PoC::mining` extrinsic is recorded, imported in a block of its own and
+//! answered with a `VerifyDeadline` event, unless the inclusion of the account is held back or
+//! submissions are rejected.
+//! Signatures aren't checked and every block is final right away.
+
+use crate::com::compat::{
+    call_index, event_index, expected_metadata, MINING, POC_MODULE, TS_MODULE, VERIFY_DEADLINE,
+};
+use codec::{Compact, Decode, Encode};
+use serde_json::{json, Value};
+use sp_core::{blake2_256, twox_128};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+use url::Url;
+
+const BLOCK_TIME: u64 = 3000;
+const GENESIS_TS: u64 = 1_585_000_000_000;
+const EXTRINSIC_UPDATE: &str = "author_extrinsicUpdate";
+
+/// A `PoC::mining` call as received by the node.
+#[derive(Clone, Debug, PartialEq, Decode)]
+pub struct Mining {
+    pub account_id: u64,
+    pub height: u64,
+    pub sig: [u8; 32],
+    pub nonce: u64,
+    pub deadline: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Topic {
+    NewHeads,
+    FinalizedHeads,
+    Storage,
+}
+
+struct Subscription {
+    id: u64,
+    topic: Topic,
+    tx: Sender<String>,
+}
+
+// a submitted extrinsic that waits for its account to be released
+struct Held {
+    account_id: u64,
+    extrinsic: String,
+    id: u64,
+    tx: Sender<String>,
+}
+
+struct Block {
+    hash: [u8; 32],
+    header: Value,
+    extrinsics: Vec<String>,
+}
+
+struct Chain {
+    metadata: String,
+    mining_call: (u8, u8),
+    verify_deadline_event: (u8, u8),
+    blocks: Vec<Block>,
+    storage: HashMap<Vec<u8>, Vec<u8>>,
+    subscriptions: Vec<Subscription>,
+    next_id: u64,
+    minings: Vec<Mining>,
+    holding: HashSet<u64>,
+    held: Vec<Held>,
+    rejecting: bool,
+}
+
+pub struct MockNode {
+    url: Url,
+    chain: Arc<Mutex<Chain>>,
+}
+
+impl MockNode {
+    /// Listens on a free local port. The chain starts with the genesis block and block 1.
+    pub fn start() -> MockNode {
+        let metadata = expected_metadata();
+        let mut chain = Chain {
+            metadata: to_hex(&metadata.encode()),
+            mining_call: call_index(&metadata, POC_MODULE, MINING).expect("PoC::mining"),
+            verify_deadline_event: event_index(&metadata, POC_MODULE, VERIFY_DEADLINE)
+                .expect("PoC::VerifyDeadline"),
+            blocks: Vec::new(),
+            storage: HashMap::new(),
+            subscriptions: Vec::new(),
+            next_id: 0,
+            minings: Vec::new(),
+            holding: HashSet::new(),
+            held: Vec::new(),
+            rejecting: false,
+        };
+        chain.import(Vec::new());
+        chain.import(Vec::new());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let chain = Arc::new(Mutex::new(chain));
+        let node_chain = chain.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    let chain = node_chain.clone();
+                    thread::spawn(move || serve(chain, stream));
+                }
+            }
+        });
+        MockNode { url, chain }
+    }
+
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    /// Sets a plain storage item to the encoded `value`.
+    pub fn set_storage(&self, module: &str, item: &str, value: Vec<u8>) {
+        let mut chain = self.chain.lock().unwrap();
+        chain.storage.insert(storage_key(module, item), value);
+    }
+
+    /// Imports an empty block and returns its height.
+    pub fn new_block(&self) -> u64 {
+        let mut chain = self.chain.lock().unwrap();
+        chain.import(Vec::new());
+        chain.best_height()
+    }
+
+    pub fn block_hash(&self, height: u64) -> [u8; 32] {
+        self.chain.lock().unwrap().blocks[height as usize].hash
+    }
+
+    /// Keeps the `PoC::mining` extrinsics of `account_id` in the pool until `release`.
+    pub fn hold(&self, account_id: u64) {
+        self.chain.lock().unwrap().holding.insert(account_id);
+    }
+
+    /// Includes the extrinsics of `account_id` held back so far, each in a block of its own.
+    pub fn release(&self, account_id: u64) {
+        let mut chain = self.chain.lock().unwrap();
+        chain.holding.remove(&account_id);
+        let (released, held): (Vec<Held>, Vec<Held>) = chain
+            .held
+            .drain(..)
+            .partition(|held| held.account_id == account_id);
+        chain.held = held;
+        for held in released {
+            chain.include(held.extrinsic, held.id, &held.tx);
+        }
+    }
+
+    /// Answers `PoC::mining` extrinsics with an error instead of including them.
+    pub fn reject_submissions(&self, reject: bool) {
+        self.chain.lock().unwrap().rejecting = reject;
+    }
+
+    /// All `PoC::mining` calls received so far.
+    pub fn minings(&self) -> Vec<Mining> {
+        self.chain.lock().unwrap().minings.clone()
+    }
+}
+
+impl Chain {
+    fn best_height(&self) -> u64 {
+        self.blocks.len() as u64 - 1
+    }
+
+    fn import(&mut self, extrinsics: Vec<String>) -> [u8; 32] {
+        let height = self.blocks.len() as u64;
+        let parent_hash = self.blocks.last().map(|b| b.hash).unwrap_or([0; 32]);
+        let mut preimage = parent_hash.to_vec();
+        preimage.extend(&height.to_le_bytes());
+        preimage.extend(extrinsics.concat().as_bytes());
+        let hash = blake2_256(&preimage);
+        let header = json!({
+            "parentHash": to_hex(&parent_hash),
+            "number": format!("0x{:x}", height),
+            "stateRoot": to_hex(&[0u8; 32]),
+            "extrinsicsRoot": to_hex(&[0u8; 32]),
+            "digest": { "logs": [] },
+        });
+
+        self.storage.insert(
+            storage_key(TS_MODULE, "Now"),
+            (GENESIS_TS + height * BLOCK_TIME).encode(),
+        );
+        let events = self.events(extrinsics.len() as u32);
+        self.storage
+            .insert(storage_key("System", "Events"), events.clone());
+        self.blocks.push(Block {
+            hash,
+            header: header.clone(),
+            extrinsics,
+        });
+
+        self.notify(Topic::NewHeads, header.clone());
+        self.notify(Topic::FinalizedHeads, header);
+        self.notify(
+            Topic::Storage,
+            json!({
+                "block": to_hex(&hash),
+                "changes": [[to_hex(&storage_key("System", "Events")), to_hex(&events)]],
+            }),
+        );
+        hash
+    }
+
+    // one verified deadline per extrinsic, the signer isn't known so the account is zero
+    fn events(&self, extrinsic_count: u32) -> Vec<u8> {
+        let mut events = Compact(extrinsic_count).encode();
+        for i in 0..extrinsic_count {
+            // Phase::ApplyExtrinsic(i)
+            0u8.encode_to(&mut events);
+            i.encode_to(&mut events);
+            events.push(self.verify_deadline_event.0);
+            events.push(self.verify_deadline_event.1);
+            [0u8; 32].encode_to(&mut events);
+            true.encode_to(&mut events);
+            // topics
+            Vec::<[u8; 32]>::new().encode_to(&mut events);
+        }
+        events
+    }
+
+    fn notify(&mut self, topic: Topic, result: Value) {
+        self.subscriptions.retain(|sub| {
+            sub.topic != topic
+                || sub
+                    .tx
+                    .send(notification(topic.method(), sub.id, &result))
+                    .is_ok()
+        });
+    }
+
+    fn subscribe(&mut self, topic: Topic, tx: &Sender<String>) -> u64 {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.subscriptions.push(Subscription {
+            id,
+            topic,
+            tx: tx.clone(),
+        });
+        // like a real node, new subscribers get the current head right away
+        if topic != Topic::Storage {
+            let header = self.blocks.last().unwrap().header.clone();
+            tx.send(notification(topic.method(), id, &header)).ok();
+        }
+        id
+    }
+
+    fn block(&self, at: Option<&Value>) -> Result<Option<&Block>, String> {
+        match at {
+            None | Some(Value::Null) => Ok(self.blocks.last()),
+            Some(Value::Number(n)) => Ok(n.as_u64().and_then(|n| self.blocks.get(n as usize))),
+            Some(Value::String(s)) if s.len() == 66 => {
+                let hash = from_hex(s)?;
+                Ok(self.blocks.iter().find(|block| block.hash[..] == hash[..]))
+            }
+            Some(Value::String(s)) => {
+                let n = u64::from_str_radix(s.trim_start_matches("0x"), 16)
+                    .map_err(|e| e.to_string())?;
+                Ok(self.blocks.get(n as usize))
+            }
+            Some(other) => Err(format!("invalid block: {}", other)),
+        }
+    }
+
+    fn submit(&mut self, params: &[Value], tx: &Sender<String>) -> Result<Value, String> {
+        let extrinsic = params
+            .get(0)
+            .and_then(Value::as_str)
+            .ok_or("missing extrinsic")?;
+        let mining = self
+            .decode_mining(&from_hex(extrinsic)?)
+            .ok_or("not a PoC::mining extrinsic")?;
+        let account_id = mining.account_id;
+        self.minings.push(mining);
+        if self.rejecting {
+            return Err("Invalid Transaction".to_owned());
+        }
+
+        self.next_id += 1;
+        let id = self.next_id;
+        tx.send(notification(EXTRINSIC_UPDATE, id, &json!("ready")))
+            .ok();
+        if self.holding.contains(&account_id) {
+            self.held.push(Held {
+                account_id,
+                extrinsic: extrinsic.to_owned(),
+                id,
+                tx: tx.clone(),
+            });
+        } else {
+            self.include(extrinsic.to_owned(), id, tx);
+        }
+        Ok(json!(id))
+    }
+
+    fn include(&mut self, extrinsic: String, id: u64, tx: &Sender<String>) {
+        let hash = self.import(vec![extrinsic]);
+        tx.send(notification(
+            EXTRINSIC_UPDATE,
+            id,
+            &json!({ "inBlock": to_hex(&hash) }),
+        ))
+        .ok();
+    }
+
+    // the call comes last and its arguments have a fixed size, so the signed part with its
+    // variable length fields can be skipped
+    fn decode_mining(&self, extrinsic: &[u8]) -> Option<Mining> {
+        let call_len = 2 + 8 + 8 + 32 + 8 + 8;
+        if extrinsic.len() < call_len {
+            return None;
+        }
+        let call = &extrinsic[extrinsic.len() - call_len..];
+        if (call[0], call[1]) != self.mining_call {
+            return None;
+        }
+        Mining::decode(&mut &call[2..]).ok()
+    }
+
+    fn call(&mut self, request: &str, tx: &Sender<String>) -> String {
+        let request: Value = serde_json::from_str(request).unwrap_or(Value::Null);
+        let params = match request["params"] {
+            Value::Array(ref params) => params.clone(),
+            _ => Vec::new(),
+        };
+        let method = request["method"].as_str().unwrap_or("");
+        match self.handle(method, &params, tx) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+            Err(message) => json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": { "code": -32601, "message": message },
+            }),
+        }
+        .to_string()
+    }
+
+    fn handle(
+        &mut self,
+        method: &str,
+        params: &[Value],
+        tx: &Sender<String>,
+    ) -> Result<Value, String> {
+        match method {
+            "state_getMetadata" => Ok(json!(self.metadata)),
+            "state_getRuntimeVersion" => Ok(json!({
+                "specName": "mock",
+                "implName": "mock",
+                "authoringVersion": 1,
+                "specVersion": 1,
+                "implVersion": 1,
+                "apis": [],
+                "transactionVersion": 1,
+            })),
+            "system_properties" => Ok(json!({})),
+            "system_accountNextIndex" => Ok(json!(0)),
+            "chain_getBlockHash" | "chain_getFinalizedHead" => Ok(self
+                .block(params.get(0))?
+                .map(|block| json!(to_hex(&block.hash)))
+                .unwrap_or(Value::Null)),
+            "chain_getHeader" => Ok(self
+                .block(params.get(0))?
+                .map(|block| block.header.clone())
+                .unwrap_or(Value::Null)),
+            "chain_getBlock" => Ok(self
+                .block(params.get(0))?
+                .map(|block| {
+                    json!({
+                        "block": { "header": block.header, "extrinsics": block.extrinsics },
+                        "justification": null,
+                    })
+                })
+                .unwrap_or(Value::Null)),
+            "state_getStorage" => {
+                let key = from_hex(params.get(0).and_then(Value::as_str).unwrap_or(""))?;
+                Ok(self
+                    .storage
+                    .get(&key)
+                    .map(|value| json!(to_hex(value)))
+                    .unwrap_or(Value::Null))
+            }
+            "chain_subscribeNewHeads" => Ok(json!(self.subscribe(Topic::NewHeads, tx))),
+            "chain_subscribeFinalizedHeads" => {
+                Ok(json!(self.subscribe(Topic::FinalizedHeads, tx)))
+            }
+            // only `System::Events` is ever watched
+            "state_subscribeStorage" => Ok(json!(self.subscribe(Topic::Storage, tx))),
+            "chain_unsubscribeNewHeads"
+            | "chain_unsubscribeFinalizedHeads"
+            | "state_unsubscribeStorage"
+            | "author_unwatchExtrinsic" => {
+                let id = params.get(0).and_then(Value::as_u64);
+                self.subscriptions.retain(|sub| Some(sub.id) != id);
+                Ok(json!(true))
+            }
+            "author_submitAndWatchExtrinsic" => self.submit(params, tx),
+            _ => Err(format!("Method not found: {}", method)),
+        }
+    }
+}
+
+impl Topic {
+    fn method(self) -> &'static str {
+        match self {
+            Topic::NewHeads => "chain_newHead",
+            Topic::FinalizedHeads => "chain_finalizedHead",
+            Topic::Storage => "state_storage",
+        }
+    }
+}
+
+// Answers requests in order and forwards notifications in between. The short read timeout keeps
+// notifications flowing while the client is quiet.
+fn serve(chain: Arc<Mutex<Chain>>, stream: TcpStream) {
+    let mut ws: WebSocket<TcpStream> = match tungstenite::server::accept(stream) {
+        Ok(ws) => ws,
+        Err(_) => return,
+    };
+    ws.get_ref()
+        .set_read_timeout(Some(Duration::from_millis(20)))
+        .unwrap();
+    let (tx, rx) = channel();
+    loop {
+        match ws.read_message() {
+            Ok(Message::Text(request)) => {
+                let response = chain.lock().unwrap().call(&request, &tx);
+                if ws.write_message(Message::Text(response)).is_err() {
+                    return;
+                }
+            }
+            Ok(Message::Close(_)) => return,
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(ref e))
+                if e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::TimedOut => {}
+            Err(_) => return,
+        }
+        while let Ok(notification) = rx.try_recv() {
+            if ws.write_message(Message::Text(notification)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn notification(method: &str, id: u64, result: &Value) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": { "subscription": id, "result": result },
+    })
+    .to_string()
+}
+
+fn storage_key(module: &str, item: &str) -> Vec<u8> {
+    let mut key = twox_128(module.as_bytes()).to_vec();
+    key.extend(&twox_128(item.as_bytes()));
+    key
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+    hex::decode(s.trim_start_matches("0x")).map_err(|e| e.to_string())
+}
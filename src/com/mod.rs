@@ -1,3 +1,11 @@
 pub(crate) mod api;
 pub(crate) mod client;
+pub(crate) mod compat;
+pub(crate) mod http;
+pub(crate) mod keystore;
+#[cfg(test)]
+pub(crate) mod mock_node;
+pub(crate) mod round;
 pub(crate) mod runtimes;
+pub(crate) mod signer;
+pub(crate) mod substrate;
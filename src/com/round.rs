@@ -0,0 +1,105 @@
+//! How the chain groups blocks into mining rounds.
+//!
+//! The PoC module accepts one best deadline per round of `epoch_length` blocks. Deadlines found
+//! after the `mining_window` of a round are too late to be included.
+
+use std::cmp::max;
+
+/// Blocks per round if the runtime doesn't expose it.
+pub const DEFAULT_EPOCH_LENGTH: u64 = 3;
+/// Mining window in ms if the runtime doesn't expose it.
+pub const DEFAULT_MINING_WINDOW: u64 = 9000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoundSchedule {
+    /// Blocks per round.
+    pub epoch_length: u64,
+    /// Milliseconds after the start of a round during which deadlines are submitted, 0 means
+    /// no limit.
+    pub mining_window: u64,
+}
+
+/// Values from the config replacing the ones of the chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoundScheduleOverride {
+    pub epoch_length: Option<u64>,
+    pub mining_window: Option<u64>,
+}
+
+impl RoundSchedule {
+    pub fn new(epoch_length: u64, mining_window: u64) -> Self {
+        Self {
+            epoch_length: max(1, epoch_length),
+            mining_window,
+        }
+    }
+
+    /// Every block is a round of its own, as with Burst-style pools and wallets.
+    pub fn per_block() -> Self {
+        Self::new(1, 0)
+    }
+
+    pub fn with_override(self, o: &RoundScheduleOverride) -> Self {
+        Self::new(
+            o.epoch_length.unwrap_or(self.epoch_length),
+            o.mining_window.unwrap_or(self.mining_window),
+        )
+    }
+
+    /// Index of the round containing `height`, the first block has height 1.
+    pub fn round(&self, height: u64) -> u64 {
+        height.saturating_sub(1) / self.epoch_length
+    }
+
+    pub fn same_round(&self, height: u64, other_height: u64) -> bool {
+        self.round(height) == self.round(other_height)
+    }
+
+    /// A deadline is still accepted during the round after the one it was found for.
+    pub fn is_expired(&self, submission_height: u64, height: u64) -> bool {
+        self.round(height) > self.round(submission_height) + 1
+    }
+
+    /// Whether a round that started `elapsed` ms ago is over for the miner.
+    pub fn window_closed(&self, elapsed: u64) -> bool {
+        self.mining_window != 0 && elapsed > self.mining_window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounds() {
+        let schedule = RoundSchedule::new(3, 9000);
+        assert_eq!(schedule.round(1), 0);
+        assert_eq!(schedule.round(3), 0);
+        assert_eq!(schedule.round(4), 1);
+        assert!(schedule.same_round(4, 6));
+        assert!(!schedule.same_round(3, 4));
+
+        assert!(!schedule.is_expired(4, 6));
+        assert!(!schedule.is_expired(4, 9));
+        assert!(schedule.is_expired(4, 10));
+
+        assert!(!schedule.window_closed(9000));
+        assert!(schedule.window_closed(9001));
+        assert!(!RoundSchedule::per_block().window_closed(u64::max_value()));
+
+        let schedule = RoundSchedule::per_block();
+        assert!(!schedule.same_round(7, 8));
+        assert!(schedule.is_expired(7, 9));
+    }
+
+    #[test]
+    fn test_override() {
+        let schedule = RoundSchedule::new(3, 9000);
+        assert_eq!(schedule.with_override(&RoundScheduleOverride::default()), schedule);
+        let o = RoundScheduleOverride {
+            epoch_length: Some(0),
+            mining_window: Some(0),
+        };
+        assert_eq!(schedule.with_override(&o), RoundSchedule::new(1, 0));
+    }
+}
@@ -0,0 +1,131 @@
+use sp_core::crypto::{Pair, Ss58Codec};
+use sp_core::{ed25519, sr25519};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+const SR25519_PREFIX: &str = "sr25519:";
+const ED25519_PREFIX: &str = "ed25519:";
+const KEYSTORE_PREFIX: &str = "keystore:";
+
+/// Key used to sign the `PoC::mining` extrinsic of one plot account.
+#[derive(Clone)]
+pub enum Signer {
+    Sr25519(sr25519::Pair),
+    Ed25519(ed25519::Pair),
+}
+
+#[derive(Debug)]
+pub struct KeyError {
+    pub account_id: u64,
+    pub message: String,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "account {}: {}", self.account_id, self.message)
+    }
+}
+
+impl Signer {
+    /// Parses a secret of the form `[sr25519:|ed25519:]<secret uri>` where the secret uri is
+    /// a mnemonic, a `0x` prefixed hex seed (both with optional `//derivation` junctions) or
+    /// `keystore:<path>` pointing to a substrate keystore file. Defaults to sr25519.
+    pub fn from_secret(secret: &str) -> Result<Signer, String> {
+        let secret = secret.trim();
+        let (ed25519, secret) = if secret.starts_with(ED25519_PREFIX) {
+            (true, &secret[ED25519_PREFIX.len()..])
+        } else if secret.starts_with(SR25519_PREFIX) {
+            (false, &secret[SR25519_PREFIX.len()..])
+        } else {
+            (false, secret)
+        };
+
+        let suri = if secret.starts_with(KEYSTORE_PREFIX) {
+            read_keystore_file(&secret[KEYSTORE_PREFIX.len()..])?
+        } else {
+            secret.to_owned()
+        };
+
+        if ed25519 {
+            ed25519::Pair::from_string(&suri, None)
+                .map(Signer::Ed25519)
+                .map_err(|e| format!("invalid ed25519 secret: {:?}", e))
+        } else {
+            sr25519::Pair::from_string(&suri, None)
+                .map(Signer::Sr25519)
+                .map_err(|e| format!("invalid sr25519 secret: {:?}", e))
+        }
+    }
+
+    /// SS58 address of the signing account.
+    pub fn address(&self) -> String {
+        match self {
+            Signer::Sr25519(pair) => pair.public().to_ss58check(),
+            Signer::Ed25519(pair) => pair.public().to_ss58check(),
+        }
+    }
+}
+
+// substrate keystore files contain the secret uri as json string
+fn read_keystore_file(path: &str) -> Result<String, String> {
+    let content = fs::read_to_string(path.trim())
+        .map_err(|e| format!("can't read keystore file {}: {}", path, e))?;
+    Ok(serde_json::from_str::<String>(&content).unwrap_or_else(|_| content.trim().to_owned()))
+}
+
+/// Builds one signer per configured account.
+pub fn load_signers(secrets: &HashMap<u64, String>) -> Result<HashMap<u64, Signer>, KeyError> {
+    secrets
+        .iter()
+        .map(|(&account_id, secret)| {
+            Signer::from_secret(secret)
+                .map(|signer| (account_id, signer))
+                .map_err(|message| KeyError {
+                    account_id,
+                    message,
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHRASE: &str =
+        "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+
+    #[test]
+    fn test_from_secret() {
+        let dev = Signer::from_secret(&format!("{}//Alice", PHRASE)).unwrap();
+        assert_eq!(
+            dev.address(),
+            "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+        );
+
+        let seed = "0x9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+        match Signer::from_secret(&format!("ed25519:{}", seed)).unwrap() {
+            Signer::Ed25519(_) => (),
+            _ => panic!("expected ed25519 signer"),
+        }
+        match Signer::from_secret(PHRASE).unwrap() {
+            Signer::Sr25519(_) => (),
+            _ => panic!("expected sr25519 signer"),
+        }
+
+        assert!(Signer::from_secret("not a mnemonic").is_err());
+        assert!(Signer::from_secret("keystore:does/not/exist").is_err());
+    }
+
+    #[test]
+    fn test_load_signers() {
+        let mut secrets = HashMap::new();
+        secrets.insert(1, PHRASE.to_owned());
+        assert_eq!(load_signers(&secrets).unwrap().len(), 1);
+
+        secrets.insert(2, "0x1234".to_owned());
+        let err = load_signers(&secrets).err().unwrap();
+        assert_eq!(err.account_id, 2);
+    }
+}
@@ -0,0 +1,651 @@
+use crate::com::api::*;
+use crate::com::client::{ChainBackend, FetchFuture, HeadStream, SubmissionParameters};
+use crate::com::compat::{
+    check_genesis, check_metadata, expected_metadata, MINING, POC_MODULE, TS_MODULE,
+    VERIFY_DEADLINE,
+};
+use crate::com::round::{
+    RoundSchedule, RoundScheduleOverride, DEFAULT_EPOCH_LENGTH, DEFAULT_MINING_WINDOW,
+};
+use crate::com::runtimes::{PocRuntime, Timestamp};
+use crate::com::signer::Signer;
+use futures::sync::mpsc;
+use futures03::future::TryFutureExt;
+use log::info;
+use std::cmp::max;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+
+pub use substrate_subxt::{
+    system::System,
+    ExtrinsicSuccess,
+    Call,
+    Error as SubError,
+    Client as SubClient,
+    //DefaultNodeRuntime as Runtime,
+    ClientBuilder,
+};
+use codec::Decode;
+use frame_support::metadata::RuntimeMetadataPrefixed;
+use jsonrpsee::common::Params;
+use sp_core::{storage::StorageKey, twox_128, Bytes};
+use sp_runtime::traits::{Header};
+use sub_runtime::poc::{Difficulty, MiningInfo};
+
+type Runtime = PocRuntime;
+type AccountId = <Runtime as System>::AccountId;
+type Moment = <Runtime as Timestamp>::Moment;
+
+// constants of the PoC module, the defaults are used while a runtime doesn't declare them
+const EPOCH_LENGTH: &str = "EpochLength";
+const MINING_WINDOW: &str = "MiningWindow";
+
+// a dropped websocket doesn't end a subscription, it just stays silent
+const HEAD_TIMEOUT: Duration = Duration::from_secs(60);
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+// while on a fallback node, how often the preferred ones are checked
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// highest block seen on any node of the chain with `genesis`, nodes behind it are not used
+#[derive(Default)]
+struct BestHeight {
+    genesis: Vec<u8>,
+    height: u64,
+}
+
+impl BestHeight {
+    // never follow a node back to a lower height, unless it is on another chain
+    fn check(&mut self, genesis: &[u8], height: u64) -> Result<(), FetchError> {
+        if self.genesis != genesis {
+            if !self.genesis.is_empty() {
+                warn!("genesis changed, following the new chain from block {}", height);
+            }
+            self.genesis = genesis.to_vec();
+        } else if height < self.height {
+            return Err(FetchError::Lagging {
+                height,
+                best: self.height,
+            });
+        }
+        self.height = height;
+        Ok(())
+    }
+}
+
+/// Connection to one of the configured nodes.
+#[derive(Clone)]
+struct Connection {
+    client: SubClient<Runtime>,
+    endpoint: usize,
+    // unique per connect, subscriptions use it to notice a switch to another node
+    id: u64,
+}
+
+/// Backend talking to Substrate nodes running the PoC module.
+#[derive(Clone)]
+pub struct SubstrateClient {
+    connection: Arc<Mutex<Option<Connection>>>,
+    connects: Arc<Mutex<u64>>,
+    best_height: Arc<Mutex<BestHeight>>,
+    account_id_to_signer: Arc<HashMap<u64, Signer>>,
+    endpoints: Arc<Vec<Url>>,
+    finalized_heads: bool,
+    round_schedule_override: RoundScheduleOverride,
+    genesis_hash: Option<String>,
+    expected_metadata: Arc<RuntimeMetadataPrefixed>,
+    timeout: Duration,
+}
+
+impl SubstrateClient {
+    /// Client for the nodes at `endpoints`, in order of preference. `signers` sign the
+    /// submissions of each account. The connection is established by the first request and
+    /// fails over to the next healthy node. If `genesis_hash` is given, nodes of other networks
+    /// are refused. A node that doesn't answer a request within `timeout` counts as failed.
+    pub fn new(
+        endpoints: Vec<Url>,
+        signers: HashMap<u64, Signer>,
+        finalized_heads: bool,
+        round_schedule_override: RoundScheduleOverride,
+        genesis_hash: Option<String>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(None)),
+            connects: Arc::new(Mutex::new(0)),
+            best_height: Arc::new(Mutex::new(BestHeight::default())),
+            account_id_to_signer: Arc::new(signers),
+            endpoints: Arc::new(endpoints),
+            finalized_heads,
+            round_schedule_override,
+            genesis_hash,
+            expected_metadata: Arc::new(expected_metadata()),
+            timeout,
+        }
+    }
+
+    // connects on first use and again after a request failed
+    async fn connection(&self) -> Result<Connection, FetchError> {
+        let connection = self.connection.lock().unwrap().clone();
+        if let Some(connection) = connection {
+            return Ok(connection);
+        }
+
+        let mut errors = match self.connect().await {
+            Ok(connection) => return Ok(connection),
+            Err(errors) => errors,
+        };
+        // all nodes behind the best block seen means the chain was reset, follow it back
+        if let Some(height) = lagging_height(&errors) {
+            warn!("all nodes are behind the best block seen, following them back to {}", height);
+            self.best_height.lock().unwrap().height = height;
+            errors = match self.connect().await {
+                Ok(connection) => return Ok(connection),
+                Err(errors) => errors,
+            };
+        }
+        // incompatible nodes are fatal unless another node might come back
+        match errors.iter().position(|e| match e {
+            FetchError::Incompatible(_) => false,
+            _ => true,
+        }) {
+            Some(i) => Err(errors.swap_remove(i)),
+            None => Err(errors.swap_remove(0)),
+        }
+    }
+
+    // connects to the first healthy node, in order of preference
+    async fn connect(&self) -> Result<Connection, Vec<FetchError>> {
+        let mut errors = Vec::new();
+        for endpoint in 0..self.endpoints.len() {
+            match self.probe(endpoint).await {
+                Ok(client) => return Ok(self.activate(endpoint, client)),
+                Err(e) => {
+                    warn!("node unavailable: url={}, err={}", self.endpoints[endpoint], e);
+                    errors.push(e);
+                }
+            }
+        }
+        Err(errors)
+    }
+
+    // connects to the node and checks that it is fit for mining
+    async fn probe(&self, endpoint: usize) -> Result<SubClient<Runtime>, FetchError> {
+        let url = &self.endpoints[endpoint];
+        debug!("connecting to {}", url);
+        let client = self
+            .with_timeout(
+                ClientBuilder::<Runtime>::new()
+                    .set_url(url.as_str())
+                    .build(),
+            )
+            .await?;
+        // on every connect, the node might have been upgraded or replaced in the meantime
+        self.check_compatibility(url, &client).await?;
+        let height = self.get_current_height(&client).await?;
+        self.check_height(&client, height)?;
+        Ok(client)
+    }
+
+    fn activate(&self, endpoint: usize, client: SubClient<Runtime>) -> Connection {
+        let id = {
+            let mut connects = self.connects.lock().unwrap();
+            *connects += 1;
+            *connects
+        };
+        let schedule = self.round_schedule(&client);
+        info!(
+            "active node: url={}, epoch_length={} blocks, mining_window={}ms",
+            self.endpoints[endpoint], schedule.epoch_length, schedule.mining_window
+        );
+        let connection = Connection {
+            client,
+            endpoint,
+            id,
+        };
+        *self.connection.lock().unwrap() = Some(connection.clone());
+        connection
+    }
+
+    // switches back to a preferred node once it is healthy again
+    async fn fail_back(&self, active: usize) -> bool {
+        for endpoint in 0..active {
+            match self.probe(endpoint).await {
+                Ok(client) => {
+                    self.activate(endpoint, client);
+                    return true;
+                }
+                Err(e) => debug!("node still unavailable: url={}, err={}", self.endpoints[endpoint], e),
+            }
+        }
+        false
+    }
+
+    fn is_active(&self, connection: &Connection) -> bool {
+        match *self.connection.lock().unwrap() {
+            Some(ref active) => active.id == connection.id,
+            None => false,
+        }
+    }
+
+    // the next request connects to the best node again, storage that is missing or fails to
+    // decode won't be fixed by it
+    fn disconnect_on_node_error<T>(&self, connection: &Connection, res: &Result<T, FetchError>) {
+        match res {
+            Err(FetchError::Substrate(_))
+            | Err(FetchError::Lagging { .. })
+            | Err(FetchError::Timeout(_)) => self.disconnect(connection),
+            _ => (),
+        }
+    }
+
+    // a node that doesn't answer is as good as a dead one, the subscription of heads has a
+    // timeout of its own
+    async fn with_timeout<T, E, F>(&self, request: F) -> Result<T, FetchError>
+    where
+        F: std::future::Future<Output = Result<T, E>>,
+        E: Into<FetchError>,
+    {
+        match async_std::future::timeout(self.timeout, request).await {
+            Ok(res) => res.map_err(Into::into),
+            Err(_) => Err(FetchError::Timeout(self.timeout)),
+        }
+    }
+
+    fn disconnect(&self, connection: &Connection) {
+        let mut active = self.connection.lock().unwrap();
+        if active.as_ref().map(|active| active.id) == Some(connection.id) {
+            warn!("disconnecting from {}", self.endpoints[connection.endpoint]);
+            *active = None;
+        }
+    }
+
+    fn check_height(&self, client: &SubClient<Runtime>, height: u64) -> Result<(), FetchError> {
+        self.best_height
+            .lock()
+            .unwrap()
+            .check(client.genesis().as_bytes(), height)
+    }
+
+    async fn check_compatibility(
+        &self,
+        url: &Url,
+        client: &SubClient<Runtime>,
+    ) -> Result<(), FetchError> {
+        let metadata = self.with_timeout(self.fetch_metadata(url)).await?;
+        let mut incompatibilities = check_metadata(&metadata, &self.expected_metadata);
+        if let Some(ref genesis_hash) = self.genesis_hash {
+            if let Err(e) = check_genesis(client.genesis().as_bytes(), genesis_hash) {
+                incompatibilities.push(e);
+            }
+        }
+        if incompatibilities.is_empty() {
+            Ok(())
+        } else {
+            Err(FetchError::Incompatible(incompatibilities))
+        }
+    }
+
+    // subxt only keeps the names of calls and events, their types are in the raw metadata
+    async fn fetch_metadata(&self, url: &Url) -> Result<RuntimeMetadataPrefixed, FetchError> {
+        let rpc_error = |e: String| FetchError::Substrate(SubError::Other(format!("rpc: {}", e)));
+        let rpc = jsonrpsee::ws_client(url.as_str())
+            .await
+            .map_err(|e| rpc_error(format!("{:?}", e)))?;
+        let bytes: Bytes = rpc
+            .request("state_getMetadata", Params::None)
+            .await
+            .map_err(|e| rpc_error(format!("{:?}", e)))?;
+        Ok(RuntimeMetadataPrefixed::decode(&mut &bytes[..])?)
+    }
+
+    // read from the metadata the client fetched on connect, the config has the last word
+    fn round_schedule(&self, client: &SubClient<Runtime>) -> RoundSchedule {
+        let constant = |name: &str| -> Option<u64> {
+            let constant = client
+                .metadata()
+                .module(POC_MODULE)
+                .and_then(|module| module.constant(name))
+                .ok()?;
+            // block numbers are encoded as u32
+            constant
+                .value::<u64>()
+                .or_else(|_| constant.value::<u32>().map(u64::from))
+                .ok()
+        };
+        RoundSchedule::new(
+            constant(EPOCH_LENGTH).unwrap_or(DEFAULT_EPOCH_LENGTH),
+            constant(MINING_WINDOW).unwrap_or(DEFAULT_MINING_WINDOW),
+        )
+        .with_override(&self.round_schedule_override)
+    }
+
+    async fn mining_info(&self) -> Result<MiningInfoResponse, FetchError> {
+        let connection = self.connection().await?;
+        let res = self.fetch_mining_info(&connection.client).await;
+        self.disconnect_on_node_error(&connection, &res);
+        res
+    }
+
+    async fn submit(
+        &self,
+        submission_data: SubmissionParameters,
+    ) -> Result<SubmitNonceResponse, FetchError> {
+        let connection = self.connection().await?;
+        let res = self.submit_to(&connection.client, submission_data).await;
+        self.disconnect_on_node_error(&connection, &res);
+        res
+    }
+
+    async fn fetch_mining_info(
+        &self,
+        client: &SubClient<Runtime>,
+    ) -> Result<MiningInfoResponse, FetchError> {
+        // use block_hash as gen_sig
+        let block_hash = self
+            .with_timeout(client.block_hash(None))
+            .await?
+            .ok_or(FetchError::MissingBlock)?;
+        let block_hash = block_hash.as_fixed_bytes();
+        let height = self.get_current_height(client).await?;
+        self.check_height(client, height)?;
+        let round_schedule = self.round_schedule(client);
+
+        let base_target = if let Some(di) = self.get_last_difficulty(client).await? {
+            info!("THERE WAS a !!!!base_target = {}", di.base_target);
+            di.base_target
+        } else {
+            info!("!!!!!!!use default base-target!!!!");
+            488671834567_u64
+        };
+
+        // the miner only submits below this target, so deadlines of past rounds don't count
+        let deadline = match self.get_last_mining_info(client).await? {
+            Some(ref dl) if round_schedule.same_round(dl.block, height) => {
+                info!("THERE WAS a !!!!best_dl = {}", dl.best_dl);
+                dl.best_dl
+            }
+            _ => {
+                info!("!!!!!!use default deadline!!!!");
+                std::u64::MAX
+            }
+        };
+
+        let now_ts = self.get_now_ts(client).await?;
+        // nothing has been mined on a fresh chain yet
+        let duration_from_last_mining = match self.get_last_mining_ts(client).await? {
+            Some(last_mining_ts) => now_ts.saturating_sub(last_mining_ts),
+            None => std::u64::MAX,
+        };
+        info!("NOW-ts = {}, duration_from_last_mining = {}", now_ts, duration_from_last_mining);
+
+        info!("GET CURRENT Mining Info: base_target = {}, height = {}, sig = {:?}, target_deadline = {}",
+              base_target, height, *block_hash, deadline);
+        Ok(MiningInfoResponse{
+            base_target,
+            height,
+            generation_signature: *block_hash,
+            target_deadline: deadline,
+            duration_from_last_mining,
+            round_schedule,
+        })
+    }
+
+    async fn submit_to(
+        &self,
+        client: &SubClient<Runtime>,
+        submission_data: SubmissionParameters,
+    ) -> Result<SubmitNonceResponse, FetchError> {
+        println!(" --------------start submit nonce to Substrate-------------------");
+
+        info!("check current best deadline!!!");
+        let height = self.get_current_height(client).await?;
+        let round_schedule = self.round_schedule(client);
+        if round_schedule.is_expired(submission_data.height, height) {
+            return Err(FetchError::Expired {
+                height: submission_data.height,
+                current: height,
+            });
+        }
+
+        if let Some(info) = self.get_last_mining_info(client).await? {
+            info!("on-chain best deadline = {} ,  deadline to submit = {}", info.best_dl, submission_data.deadline);
+            if info.best_dl <= submission_data.deadline
+                && round_schedule.same_round(info.block, submission_data.height) {
+                info!(" There was already a better deadline on chain, the best deadline on-chain is {} ", info.best_dl);
+                return Ok(SubmitNonceResponse{verify_result: false});
+            }
+            info!("find a better deadline = {}", submission_data.deadline );
+        } else {
+            info!("find no last-mining-info");
+        }
+
+        let signer = match self.account_id_to_signer.get(&submission_data.account_id) {
+            Some(signer) => signer.clone(),
+            None => return Err(FetchError::Substrate(SubError::Other(format!(
+                "no signing key for account {}", submission_data.account_id
+            )))),
+        };
+
+        info!("starting submit_nonce to substrate, signer = {}", signer.address());
+        let call = Self::mining(
+            submission_data.account_id,
+            submission_data.height,
+            submission_data.gen_sig,
+            submission_data.nonce,
+            submission_data.deadline
+        );
+        // resolves once the extrinsic is included, only this submission waits for it
+        let success = match signer {
+            Signer::Sr25519(pair) => {
+                let xt = self.with_timeout(client.xt(pair, None)).await?;
+                xt.watch().submit(call).await?
+            }
+            Signer::Ed25519(pair) => {
+                let xt = self.with_timeout(client.xt(pair, None)).await?;
+                xt.watch().submit(call).await?
+            }
+        };
+
+        match success.find_event::<(AccountId, bool)>(POC_MODULE, VERIFY_DEADLINE) {
+            Some(Ok((_id, verify_result))) => {
+                info!("verify result: {}", verify_result);
+                Ok(SubmitNonceResponse{verify_result})
+            }
+            Some(Err(err)) => Err(err.into()),
+            None => Err(FetchError::Substrate(SubError::Other("Failed to find PoC::VerifyDeadline".to_string()))),
+        }
+    }
+
+    /// Get the last mining info from Substrate.
+    async fn get_last_mining_info(
+        &self,
+        client: &SubClient<Runtime>,
+    ) -> Result<Option<MiningInfo<AccountId>>, FetchError> {
+        let dls: Option<Vec<MiningInfo<AccountId>>> = self
+            .with_timeout(client.fetch(storage_key(POC_MODULE, "DlInfo"), None))
+            .await?;
+        Ok(dls.and_then(|dls| dls.last().cloned()))
+    }
+
+    /// Get the last difficulty from Substrate.
+    async fn get_last_difficulty(
+        &self,
+        client: &SubClient<Runtime>,
+    ) -> Result<Option<Difficulty>, FetchError> {
+        let targets: Option<Vec<Difficulty>> = self
+            .with_timeout(client.fetch(storage_key(POC_MODULE, "TargetInfo"), None))
+            .await?;
+        Ok(targets.and_then(|targets| targets.last().cloned()))
+    }
+
+    /// Get last mining timestamp from Substrate.
+    async fn get_last_mining_ts(&self, client: &SubClient<Runtime>) -> Result<Option<u64>, FetchError> {
+        self.with_timeout(client.fetch(storage_key(POC_MODULE, "LastMiningTs"), None))
+            .await
+    }
+
+    /// GET now timestamp from Substrate.
+    async fn get_now_ts(&self, client: &SubClient<Runtime>) -> Result<u64, FetchError> {
+        let ts: Option<u64> = self
+            .with_timeout(client.fetch(storage_key(TS_MODULE, "Now"), None))
+            .await?;
+        ts.ok_or_else(|| FetchError::MissingStorage(format!("{}::Now", TS_MODULE)))
+    }
+
+    fn mining(account_id: u64, height: u64, sig: [u8; 32], nonce: u64, deadline: u64) -> Call<MiningArgs>{
+        Call::new(POC_MODULE, MINING, MiningArgs{
+            account_id,
+            height,
+            sig,
+            nonce,
+            deadline,
+        })
+    }
+
+    // Forwards every head of the active node to `tx` until the receiver is gone. After errors or
+    // `HEAD_TIMEOUT` without a head the node is dropped and the next healthy one subscribed.
+    async fn watch_heads(self, tx: mpsc::UnboundedSender<()>) {
+        let kind = if self.finalized_heads { "finalized" } else { "new" };
+        loop {
+            let connection = match self.connection().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("can't subscribe to {} heads: err={}", kind, e);
+                    async_std::task::sleep(RESUBSCRIBE_DELAY).await;
+                    continue;
+                }
+            };
+            let url = &self.endpoints[connection.endpoint];
+            let sub = if self.finalized_heads {
+                connection.client.subscribe_finalized_blocks().await
+            } else {
+                connection.client.subscribe_blocks().await
+            };
+            let mut sub = match sub {
+                Ok(sub) => sub,
+                Err(e) => {
+                    warn!("can't subscribe to {} heads: url={}, err={:?}", kind, url, e);
+                    self.disconnect(&connection);
+                    async_std::task::sleep(RESUBSCRIBE_DELAY).await;
+                    continue;
+                }
+            };
+
+            info!("subscribed to {} heads: url={}", kind, url);
+            // heads might be minutes apart, don't wait for the first one
+            if tx.unbounded_send(()).is_err() {
+                return;
+            }
+            let mut last_health_check = Instant::now();
+            loop {
+                match async_std::future::timeout(HEAD_TIMEOUT, sub.next()).await {
+                    Ok(header) => {
+                        debug!("{} head: height={}, url={}", kind, header.number(), url);
+                        if !self.is_active(&connection) {
+                            break;
+                        }
+                        if connection.endpoint > 0
+                            && last_health_check.elapsed() > HEALTH_CHECK_INTERVAL
+                        {
+                            last_health_check = Instant::now();
+                            if self.fail_back(connection.endpoint).await {
+                                break;
+                            }
+                        }
+                        if tx.unbounded_send(()).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => {
+                        warn!(
+                            "no {} head for {}s: url={}",
+                            kind,
+                            HEAD_TIMEOUT.as_secs(),
+                            url
+                        );
+                        self.disconnect(&connection);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get current block height from Substrate.
+    async fn get_current_height(&self, client: &SubClient<Runtime>) -> Result<u64, FetchError> {
+        let header = self
+            .with_timeout(client.header::<<Runtime as System>::Hash>(None))
+            .await?
+            .ok_or(FetchError::MissingBlock)?;
+        let block_num = *header.number();
+        Ok(block_num as u64)
+    }
+}
+
+// The subxt futures are driven by the tokio executor through the compat layer, so neither the
+// executor nor other requests wait for a node response or an extrinsic's inclusion.
+impl ChainBackend for SubstrateClient {
+    /// Get current mining info.
+    fn get_mining_info(&self) -> FetchFuture<MiningInfoResponse> {
+        let client = self.clone();
+        Box::new(Box::pin(async move { client.mining_info().await }).compat())
+    }
+
+    /// Submit nonce to Substrate.
+    fn submit_nonce(
+        &self,
+        submission_data: &SubmissionParameters,
+    ) -> FetchFuture<SubmitNonceResponse> {
+        let client = self.clone();
+        let submission_data = submission_data.clone();
+        Box::new(Box::pin(async move { client.submit(submission_data).await }).compat())
+    }
+
+    /// Subscribe to new (or finalized) heads of the active node.
+    fn new_heads(&self) -> HeadStream {
+        let (tx, rx) = mpsc::unbounded();
+        async_std::task::spawn(self.clone().watch_heads(tx));
+        Box::new(rx)
+    }
+}
+
+fn storage_key(module: &str, item: &str) -> StorageKey {
+    let mut storage_key = twox_128(module.as_bytes()).to_vec();
+    storage_key.extend(twox_128(item.as_bytes()).to_vec());
+    StorageKey(storage_key)
+}
+
+// the highest of the nodes if all of them are behind the best block seen
+fn lagging_height(errors: &[FetchError]) -> Option<u64> {
+    let mut highest = None;
+    for e in errors {
+        match e {
+            FetchError::Lagging { height, .. } => highest = max(highest, Some(*height)),
+            _ => return None,
+        }
+    }
+    highest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_best_height() {
+        let mut best = BestHeight::default();
+        assert!(best.check(&[1; 32], 10).is_ok());
+        assert!(best.check(&[1; 32], 9).is_err());
+        assert!(best.check(&[1; 32], 11).is_ok());
+        // a new chain starts over
+        assert!(best.check(&[2; 32], 3).is_ok());
+        assert_eq!(best.height, 3);
+
+        let lagging = |height| FetchError::Lagging { height, best: 11 };
+        assert_eq!(lagging_height(&[lagging(4), lagging(7)]), Some(7));
+        assert_eq!(lagging_height(&[lagging(4), FetchError::MissingBlock]), None);
+        assert_eq!(lagging_height(&[]), None);
+    }
+}
@@ -1,27 +1,44 @@
+use crate::com::round::RoundScheduleOverride;
+use crate::submission_policy::SubmissionMode;
 use serde::de::{Deserialize, Deserializer};
 use std::collections::HashMap;
 use std::fs;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::u32;
 use url::Url;
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub enum Benchmark {
     IO,
     XPU,
     Disabled,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Cfg {
     #[serde(default = "default_secret_phrase")]
     pub account_id_to_secret_phrase: HashMap<u64, String>,
 
+    #[serde(default = "default_keystore_path")]
+    pub keystore_path: PathBuf,
+
+    pub keystore_password_file: Option<PathBuf>,
+
+    #[serde(default = "default_keystore_password_env")]
+    pub keystore_password_env: String,
+
     pub plot_dirs: Vec<PathBuf>,
 
+    #[serde(default = "default_mmap_plot_dirs")]
+    pub mmap_plot_dirs: Vec<PathBuf>,
+
     #[serde(with = "url_serde")]
     pub url: Url,
 
+    #[serde(default = "default_fallback_urls", with = "url_list")]
+    pub fallback_urls: Vec<Url>,
+
     #[serde(default = "default_hdd_reader_thread_count")]
     pub hdd_reader_thread_count: usize,
 
@@ -31,6 +48,9 @@ pub struct Cfg {
     #[serde(default = "default_hdd_wakeup_after")]
     pub hdd_wakeup_after: i64,
 
+    #[serde(default = "default_hdd_use_io_uring")]
+    pub hdd_use_io_uring: bool,
+
     #[serde(default = "default_cpu_threads")]
     pub cpu_threads: usize,
 
@@ -70,9 +90,26 @@ pub struct Cfg {
     #[serde(default = "default_account_id_to_target_deadline")]
     pub account_id_to_target_deadline: HashMap<u64, u64>,
 
+    #[serde(default = "default_submission_policy")]
+    pub submission_policy: SubmissionMode,
+
+    #[serde(default = "default_journal_path")]
+    pub journal_path: PathBuf,
+
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+
     #[serde(default = "default_get_mining_info_interval")]
     pub get_mining_info_interval: u64,
 
+    #[serde(default = "default_finalized_heads")]
+    pub finalized_heads: bool,
+
+    #[serde(default = "default_round_schedule")]
+    pub round_schedule: RoundScheduleOverride,
+
+    pub genesis_hash: Option<String>,
+
     #[serde(default = "default_timeout")]
     pub timeout: u64,
 
@@ -106,6 +143,10 @@ pub struct Cfg {
     #[serde(default = "default_show_drive_stats")]
     pub show_drive_stats: bool,
 
+    pub api_address: Option<SocketAddr>,
+
+    pub metrics_address: Option<SocketAddr>,
+
     pub benchmark_only: Option<Benchmark>,
 }
 
@@ -127,6 +168,14 @@ fn default_secret_phrase() -> HashMap<u64, String> {
     HashMap::new()
 }
 
+fn default_keystore_path() -> PathBuf {
+    PathBuf::from("keystore")
+}
+
+fn default_keystore_password_env() -> String {
+    "POC_KEYSTORE_PASSWORD".to_owned()
+}
+
 fn default_hdd_reader_thread_count() -> usize {
     0
 }
@@ -139,6 +188,10 @@ fn default_hdd_wakeup_after() -> i64 {
     240
 }
 
+fn default_hdd_use_io_uring() -> bool {
+    false
+}
+
 fn default_cpu_threads() -> usize {
     0
 }
@@ -191,10 +244,38 @@ fn default_account_id_to_target_deadline() -> HashMap<u64, u64> {
     HashMap::new()
 }
 
+fn default_submission_policy() -> SubmissionMode {
+    SubmissionMode::AccountBest
+}
+
+fn default_journal_path() -> PathBuf {
+    PathBuf::from("submissions.jsonl")
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_fallback_urls() -> Vec<Url> {
+    Vec::new()
+}
+
+fn default_mmap_plot_dirs() -> Vec<PathBuf> {
+    Vec::new()
+}
+
 fn default_get_mining_info_interval() -> u64 {
     9000
 }
 
+fn default_finalized_heads() -> bool {
+    false
+}
+
+fn default_round_schedule() -> RoundScheduleOverride {
+    RoundScheduleOverride::default()
+}
+
 fn default_timeout() -> u64 {
     5000
 }
@@ -239,16 +320,68 @@ fn default_show_drive_stats() -> bool {
     false
 }
 
+// `url_serde` only handles single urls
+mod url_list {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::ser::Serializer;
+    use url::Url;
+
+    pub fn serialize<S: Serializer>(urls: &[Url], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(urls.iter().map(Url::as_str))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Url>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|url| Url::parse(url).map_err(de::Error::custom))
+            .collect()
+    }
+}
+
 pub fn load_cfg(config: &str) -> Cfg {
-    let cfg_str = fs::read_to_string(config).expect("failed to open config");
-    let cfg: Cfg = serde_yaml::from_str(&cfg_str).expect("failed to parse config");
-    if cfg.hdd_use_direct_io {
-        assert!(
-            cfg.cpu_nonces_per_cache % 64 == 0 && cfg.gpu_nonces_per_cache % 64 == 0,
-            "nonces_per_cache should be devisable by 64 when using direct io"
-        );
+    try_load_cfg(config).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like `load_cfg`, but errors are returned, e.g. to keep the running config on a reload.
+pub fn try_load_cfg(config: &str) -> Result<Cfg, String> {
+    let cfg_str =
+        fs::read_to_string(config).map_err(|e| format!("failed to open config: {}", e))?;
+    let cfg: Cfg =
+        serde_yaml::from_str(&cfg_str).map_err(|e| format!("failed to parse config: {}", e))?;
+    if cfg.hdd_use_direct_io
+        && (cfg.cpu_nonces_per_cache % 64 != 0 || cfg.gpu_nonces_per_cache % 64 != 0)
+    {
+        return Err("nonces_per_cache should be devisable by 64 when using direct io".to_owned());
+    }
+    Ok(validate_cfg(cfg))
+}
+
+/// Settings a running miner applies on a config reload, all others need a restart.
+pub const LIVE_SETTINGS: &[&str] = &[
+    "plot_dirs",
+    "submission_policy",
+    "target_deadline",
+    "account_id_to_target_deadline",
+    "console_log_level",
+    "logfile_log_level",
+    "console_log_pattern",
+    "logfile_log_pattern",
+    "logfile_max_count",
+    "logfile_max_size",
+];
+
+/// Names of the settings that differ between two configs.
+pub fn changed_settings(old: &Cfg, new: &Cfg) -> Vec<String> {
+    let old = serde_json::to_value(old).expect("configs serialize");
+    let new = serde_json::to_value(new).expect("configs serialize");
+    match (old, new) {
+        (serde_json::Value::Object(old), serde_json::Value::Object(new)) => new
+            .into_iter()
+            .filter(|(name, value)| old.get(name) != Some(value))
+            .map(|(name, _)| name)
+            .collect(),
+        _ => unreachable!("configs serialize to maps"),
     }
-    validate_cfg(cfg)
 }
 
 pub fn validate_cfg(mut cfg: Cfg) -> Cfg {
@@ -263,6 +396,11 @@ pub fn validate_cfg(mut cfg: Cfg) -> Cfg {
         cfg.cpu_threads = cores;
     };
 
+    if cfg.hdd_use_io_uring && !cfg!(feature = "io_uring") {
+        warn!("hdd_use_io_uring needs a build with the io_uring feature, using blocking reads");
+        cfg.hdd_use_io_uring = false;
+    }
+
     cfg.plot_dirs = cfg
         .plot_dirs
         .iter()
@@ -280,6 +418,15 @@ pub fn validate_cfg(mut cfg: Cfg) -> Cfg {
         })
         .collect();
 
+    for plot_dir in &cfg.mmap_plot_dirs {
+        if !cfg.plot_dirs.contains(plot_dir) {
+            warn!(
+                "mmap plot dir {} isn't one of plot_dirs",
+                plot_dir.to_str().unwrap()
+            );
+        }
+    }
+
     cfg
 }
 
@@ -319,4 +466,27 @@ mod tests {
         pb.push("test_data");
         assert_eq!(cfg.plot_dirs, vec![pb]);
     }
+
+    #[test]
+    fn test_changed_settings() {
+        let cfg = load_cfg("config.yaml");
+        let mut new = load_cfg("config.yaml");
+        assert!(changed_settings(&cfg, &new).is_empty());
+
+        new.target_deadline = 1000;
+        new.cpu_threads += 1;
+        new.account_id_to_target_deadline.insert(1, 2);
+        let mut changed = changed_settings(&cfg, &new);
+        changed.sort();
+        assert_eq!(
+            changed,
+            vec![
+                "account_id_to_target_deadline",
+                "cpu_threads",
+                "target_deadline"
+            ]
+        );
+        assert!(!LIVE_SETTINGS.contains(&"cpu_threads"));
+        assert!(try_load_cfg("missing.yaml").is_err());
+    }
 }
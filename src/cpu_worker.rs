@@ -1,3 +1,4 @@
+use crate::metrics::{self, Metrics};
 use crate::miner::{Buffer, NonceData};
 use crate::poc_hashing::find_best_deadline_rust;
 use crate::reader::ReadReply;
@@ -6,6 +7,8 @@ use futures::sync::mpsc;
 use futures::{Future, Sink};
 #[cfg(any(feature = "simd", feature = "neon"))]
 use libc::{c_void, uint64_t};
+use std::sync::Arc;
+use std::time::Instant;
 use std::u64;
 
 cfg_if! {
@@ -66,6 +69,7 @@ pub fn create_cpu_worker_task(
     rx_read_replies: Receiver<ReadReply>,
     tx_empty_buffers: Sender<Box<Buffer + Send>>,
     tx_nonce_data: mpsc::Sender<NonceData>,
+    metrics: Arc<Metrics>,
 ) -> impl FnOnce() {
     move || {
         for read_reply in rx_read_replies {
@@ -74,6 +78,7 @@ pub fn create_cpu_worker_task(
                 tx_empty_buffers.clone(),
                 tx_nonce_data.clone(),
                 benchmark,
+                metrics.clone(),
             );
 
             thread_pool.spawn(task);
@@ -86,11 +91,16 @@ pub fn hash(
     tx_empty_buffers: Sender<Box<Buffer + Send>>,
     tx_nonce_data: mpsc::Sender<NonceData>,
     benchmark: bool,
+    metrics: Arc<Metrics>,
 ) -> impl FnOnce() {
     move || {
         let mut buffer = read_reply.buffer;
         // handle empty buffers (read errors) && benchmark
         if read_reply.info.len == 0 || benchmark {
+            // mapped plots are only read once their pages are touched
+            if let Some(chunk) = &read_reply.info.mapped {
+                chunk.touch();
+            }
             // forward 'drive finished signal'
             if read_reply.info.finished {
                 let deadline = u64::MAX;
@@ -124,8 +134,14 @@ pub fn hash(
         #[allow(unused_assignments)]
         let mut offset: u64 = 0;
 
-        let bs = buffer.get_buffer_for_writing();
-        let bs = bs.lock().unwrap();
+        let buffer_data = buffer.get_buffer_for_writing();
+        let buffer_data = buffer_data.lock().unwrap();
+        // data of mapped plots isn't copied into the buffer
+        let bs: &[u8] = match &read_reply.info.mapped {
+            Some(chunk) => chunk.as_slice(),
+            None => &buffer_data,
+        };
+        let start = Instant::now();
 
         #[cfg(feature = "simd")]
         unsafe {
@@ -163,7 +179,7 @@ pub fn hash(
                 );
             } else {
                 let result = find_best_deadline_rust(
-                    &bs,
+                    bs,
                     (read_reply.info.len as u64) / 64,
                     &*read_reply.info.gensig,
                 );
@@ -187,7 +203,7 @@ pub fn hash(
                 );
             } else {
                 let result = find_best_deadline_rust(
-                    &bs,
+                    bs,
                     (read_reply.info.len as u64) / 64,
                     &*read_reply.info.gensig,
                 );
@@ -199,13 +215,18 @@ pub fn hash(
         #[cfg(not(any(feature = "simd", feature = "neon")))]
         {
             let result = find_best_deadline_rust(
-                &bs,
+                bs,
                 (read_reply.info.len as u64) / 64,
                 &*read_reply.info.gensig,
             );
             deadline = result.0;
             offset = result.1;
         }
+        metrics.hashed(
+            metrics::CPU,
+            read_reply.info.len as u64 / 64,
+            start.elapsed(),
+        );
 
         tx_nonce_data
             .clone()
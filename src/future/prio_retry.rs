@@ -1,12 +1,19 @@
 //! Prio retry consumes a stream and yields elements with exponential backoffs.
 //!
-//! An element that is enqueued will be yielded instantly if it is a new element.
-//! Otherwise it will be delayed according to the number of times that it has been enqueued
-//! consecutively.
-//! New items will replace old items and start with a delay of 0.
+//! Elements are queued by key, e.g. the account of a submission, and every key has a slot of
+//! its own. An element that is enqueued will be yielded instantly if it is a new element for
+//! its key. Otherwise it will be delayed according to the number of times that it has been
+//! enqueued consecutively, until it has been retried `max_attempts` times.
+//! New items will replace old items of the same key and start with a delay of 0.
+//! Items reported through the `settler` (e.g. submissions that made it) and items given up on
+//! free the slot of their key. Items given up on are handed to the `on_give_up` callback.
 
 use futures::stream::{Fuse, Stream};
+use futures::sync::mpsc;
 use futures::{Async, Future, Poll};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
 use std::time::{Duration, Instant};
 use tokio::timer::{self, Delay};
 
@@ -36,26 +43,66 @@ impl<Item> DelayedItem<Item> {
     }
 }
 
-pub struct PrioRetry<S>
+pub struct PrioRetry<S, K, F>
 where
     S: Stream,
-    S::Item: Ord + Clone + Eq,
+    S::Item: Ord + Clone + Eq + Debug,
+    K: Hash + Eq,
+    F: Fn(&S::Item) -> K,
 {
     delay_duration: Duration,
-    delayed_item: Option<DelayedItem<S::Item>>,
+    max_attempts: u32,
+    key: F,
+    delayed_items: HashMap<K, DelayedItem<S::Item>>,
     stream: Fuse<S>,
+    rx_settled: mpsc::UnboundedReceiver<S::Item>,
+    tx_settled: mpsc::UnboundedSender<S::Item>,
+    on_give_up: Option<Box<FnMut(S::Item) + Send>>,
 }
 
-impl<S> PrioRetry<S>
+impl<S, K, F> PrioRetry<S, K, F>
 where
     S: Stream,
-    S::Item: Ord + Clone + Eq,
+    S::Item: Ord + Clone + Eq + Debug,
+    K: Hash + Eq,
+    F: Fn(&S::Item) -> K,
 {
-    pub fn new(stream: S, delay_duration: Duration) -> Self {
+    pub fn new(stream: S, delay_duration: Duration, max_attempts: u32, key: F) -> Self {
+        let (tx_settled, rx_settled) = mpsc::unbounded();
         Self {
             delay_duration,
-            delayed_item: None,
+            max_attempts,
+            key,
+            delayed_items: HashMap::new(),
             stream: stream.fuse(),
+            rx_settled,
+            tx_settled,
+            on_give_up: None,
+        }
+    }
+
+    /// Called with every item that is given up on after `max_attempts` retries.
+    pub fn on_give_up<G>(mut self, on_give_up: G) -> Self
+    where
+        G: FnMut(S::Item) + Send + 'static,
+    {
+        self.on_give_up = Some(Box::new(on_give_up));
+        self
+    }
+
+    /// Items sent here are done with and won't be requeued, their key's slot is freed unless
+    /// a newer item took it meanwhile.
+    pub fn settler(&self) -> mpsc::UnboundedSender<S::Item> {
+        self.tx_settled.clone()
+    }
+
+    fn settle(&mut self) {
+        // the sender is kept, so the receiver never ends
+        while let Ok(Async::Ready(Some(item))) = self.rx_settled.poll() {
+            let key = (self.key)(&item);
+            if self.delayed_items.get(&key).map_or(false, |d| d.value == item) {
+                self.delayed_items.remove(&key);
+            }
         }
     }
 }
@@ -74,34 +121,50 @@ enum Kind<T> {
     Timer(timer::Error),
 }
 
-impl<S> Stream for PrioRetry<S>
+impl<S, K, F> Stream for PrioRetry<S, K, F>
 where
     S: Stream,
-    S::Item: Ord + Clone + Eq,
+    S::Item: Ord + Clone + Eq + Debug,
+    K: Hash + Eq,
+    F: Fn(&S::Item) -> K,
 {
     type Item = S::Item;
     type Error = Error<S::Error>;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.settle();
         loop {
             match self.stream.poll() {
                 Ok(Async::NotReady) => {
                     break;
                 }
                 Ok(Async::Ready(Some(new_item))) => {
-                    // check if we currently have a delay item
-                    if let Some(ref mut delayed_item) = self.delayed_item {
+                    let key = (self.key)(&new_item);
+                    // check if we currently have a delay item for this key
+                    if let Some(delayed_item) = self.delayed_items.get_mut(&key) {
                         if delayed_item.value < new_item {
                             // we have new item, this one will be yielded instantly
-                            self.delayed_item = Some(DelayedItem::new(new_item.clone()));
+                            *delayed_item = DelayedItem::new(new_item.clone());
                             return Ok(Async::Ready(Some(new_item)));
                         } else if delayed_item.value == new_item {
-                            // if the current item was requeued, then we will yield it with a backoff
-                            delayed_item.exp_backoff(self.delay_duration);
+                            if delayed_item.attempt < self.max_attempts {
+                                // if the current item was requeued, then we will yield it with a backoff
+                                delayed_item.exp_backoff(self.delay_duration);
+                            } else {
+                                warn!(
+                                    "giving up on {:?} after {} retries",
+                                    new_item, delayed_item.attempt
+                                );
+                                self.delayed_items.remove(&key);
+                                if let Some(ref mut on_give_up) = self.on_give_up {
+                                    on_give_up(new_item);
+                                }
+                            }
                         }
                     } else {
                         // we have new item, this one will be yielded instantly
-                        self.delayed_item = Some(DelayedItem::new(new_item.clone()));
+                        self.delayed_items
+                            .insert(key, DelayedItem::new(new_item.clone()));
                         return Ok(Async::Ready(Some(new_item)));
                     }
                 }
@@ -114,7 +177,8 @@ where
             }
         }
 
-        if let Some(ref mut delayed_item) = self.delayed_item {
+        // every key backs off on its own, yield the first item that is due
+        for delayed_item in self.delayed_items.values_mut() {
             if let Some(ref mut delay) = delayed_item.delay {
                 match delay.poll() {
                     Ok(Async::NotReady) => {}
@@ -128,7 +192,7 @@ where
                     }
                 }
             }
-        };
+        }
 
         Ok(Async::NotReady)
     }
@@ -137,19 +201,23 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::future;
     use tokio;
     use tokio::timer::Interval;
 
-    #[test]
-    fn test_prio_retry() {
-        let mut items = vec![0, 1, 2, 3, 3, 3, 0, 1, 2, 2, 6, 5, 7].into_iter();
+    fn run_prio_retry<T, K, F>(items: Vec<T>, max_attempts: u32, key: F, exp: Vec<T>)
+    where
+        T: Ord + Clone + Eq + Debug + Send + 'static,
+        K: Hash + Eq + Send + 'static,
+        F: Fn(&T) -> K + Send + 'static,
+    {
         let len = items.len();
+        let mut items = items.into_iter();
         let items = Interval::new(Instant::now(), Duration::from_millis(200))
             .take(len as u64)
             .map(move |_| items.next().unwrap())
             .map_err(|e| error!("can't consume interval: {:?}", e));
-        let exp: Vec<i64> = vec![0, 1, 2, 3, 3, 3, 6, 7];
-        let stream = PrioRetry::new(items, Duration::from_millis(100));
+        let stream = PrioRetry::new(items, Duration::from_millis(100), max_attempts, key);
         let res = stream.collect();
         tokio::run(res.then(move |res| {
             match res {
@@ -159,4 +227,61 @@ mod tests {
             Ok(())
         }));
     }
+
+    #[test]
+    fn test_prio_retry() {
+        run_prio_retry(
+            vec![0, 1, 2, 3, 3, 3, 0, 1, 2, 2, 6, 5, 7],
+            10,
+            |_| (),
+            vec![0, 1, 2, 3, 3, 3, 6, 7],
+        );
+    }
+
+    #[test]
+    fn test_prio_retry_interleaved_keys() {
+        // (account, deadline rank): a retry of one account never replaces the other's
+        run_prio_retry(
+            vec![(1, 0), (2, 0), (1, 0), (2, 0), (1, 1), (2, 0), (1, 0), (2, 1)],
+            10,
+            |item: &(u64, u64)| item.0,
+            vec![(1, 0), (2, 0), (1, 0), (2, 0), (1, 1), (2, 0), (2, 1)],
+        );
+    }
+
+    #[test]
+    fn test_prio_retry_max_attempts() {
+        run_prio_retry(
+            vec![(1, 0), (2, 0), (1, 0), (1, 0), (2, 0), (1, 0), (1, 1)],
+            2,
+            |item: &(u64, u64)| item.0,
+            vec![(1, 0), (2, 0), (1, 0), (1, 0), (2, 0), (1, 1)],
+        );
+    }
+
+    #[test]
+    fn test_prio_retry_free_slots() {
+        tokio::run(future::lazy(|| {
+            let (tx, rx) = mpsc::unbounded();
+            let (tx_given_up, rx_given_up) = std::sync::mpsc::channel();
+            let mut stream =
+                PrioRetry::new(rx, Duration::from_millis(100), 1, |item: &(u64, u64)| item.0)
+                    .on_give_up(move |item| tx_given_up.send(item).unwrap());
+            let settler = stream.settler();
+            tx.unbounded_send((1, 0)).unwrap();
+            tx.unbounded_send((2, 0)).unwrap();
+            assert_eq!(stream.poll().unwrap(), Async::Ready(Some((1, 0))));
+            assert_eq!(stream.poll().unwrap(), Async::Ready(Some((2, 0))));
+
+            // 1 is settled, 2 is given up on when it fails again after its retry
+            settler.unbounded_send((1, 0)).unwrap();
+            tx.unbounded_send((2, 0)).unwrap();
+            assert_eq!(stream.poll().unwrap(), Async::NotReady);
+            tx.unbounded_send((2, 0)).unwrap();
+            assert_eq!(stream.poll().unwrap(), Async::NotReady);
+            assert!(stream.delayed_items.is_empty());
+            assert_eq!(rx_given_up.try_iter().collect::<Vec<_>>(), vec![(2, 0)]);
+            Ok(())
+        }));
+    }
 }
@@ -1,3 +1,4 @@
+use crate::metrics::{self, Metrics};
 use crate::miner::{Buffer, NonceData};
 use crate::ocl::GpuContext;
 use crate::ocl::{gpu_hash, gpu_transfer};
@@ -6,6 +7,7 @@ use crossbeam_channel::{Receiver, Sender};
 use futures::sync::mpsc;
 use futures::{Future, Sink};
 use std::sync::Arc;
+use std::time::Instant;
 use std::u64;
 
 pub fn create_gpu_worker_task(
@@ -14,6 +16,7 @@ pub fn create_gpu_worker_task(
     tx_empty_buffers: Sender<Box<Buffer + Send>>,
     tx_nonce_data: mpsc::Sender<NonceData>,
     context_mu: Arc<GpuContext>,
+    metrics: Arc<Metrics>,
 ) -> impl FnOnce() {
     move || {
         for read_reply in rx_read_replies {
@@ -48,6 +51,7 @@ pub fn create_gpu_worker_task(
                 continue;
             }
 
+            let start = Instant::now();
             gpu_transfer(
                 &context_mu,
                 buffer.get_gpu_buffers().unwrap(),
@@ -60,6 +64,11 @@ pub fn create_gpu_worker_task(
             );
             let deadline = result.0;
             let offset = result.1;
+            metrics.hashed(
+                metrics::GPU,
+                read_reply.info.len as u64 / 64,
+                start.elapsed(),
+            );
 
             tx_nonce_data
                 .clone()
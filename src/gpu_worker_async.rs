@@ -1,3 +1,4 @@
+use crate::metrics::{self, Metrics};
 use crate::miner::{Buffer, NonceData};
 use crate::ocl::GpuContext;
 use crate::ocl::{gpu_hash, gpu_transfer, gpu_transfer_and_hash};
@@ -6,6 +7,7 @@ use crossbeam_channel::{Receiver, Sender};
 use futures::sync::mpsc;
 use futures::{Future, Sink};
 use std::sync::Arc;
+use std::time::Instant;
 use std::u64;
 
 pub fn create_gpu_worker_task_async(
@@ -15,6 +17,7 @@ pub fn create_gpu_worker_task_async(
     tx_nonce_data: mpsc::Sender<NonceData>,
     context_mu: Arc<GpuContext>,
     num_drives: usize,
+    metrics: Arc<Metrics>,
 ) -> impl FnOnce() {
     move || {
         let mut new_round = true;
@@ -29,6 +32,7 @@ pub fn create_gpu_worker_task_async(
             finished: false,
             account_id: 0,
             gpu_signal: 0,
+            mapped: None,
         };
         let mut drive_count = 0;
         let (tx_sink, rx_sink) = crossbeam_channel::bounded(1);
@@ -79,6 +83,7 @@ pub fn create_gpu_worker_task_async(
             if read_reply.info.gpu_signal == 2 && active_height == read_reply.info.height {
                 drive_count += 1;
                 if drive_count == num_drives && !new_round {
+                    let start = Instant::now();
                     let result = gpu_hash(
                         &context_mu,
                         last_buffer_info_a.len / 64,
@@ -86,6 +91,11 @@ pub fn create_gpu_worker_task_async(
                     );
                     let deadline = result.0;
                     let offset = result.1;
+                    metrics.hashed(
+                        metrics::GPU,
+                        last_buffer_info_a.len as u64 / 64,
+                        start.elapsed(),
+                    );
 
                     tx_nonce_data
                         .clone()
@@ -119,6 +129,8 @@ pub fn create_gpu_worker_task_async(
                     *read_reply.info.gensig,
                 );
             } else {
+                // includes the transfer of the next buffer, hashing and transfer overlap
+                let start = Instant::now();
                 let result = gpu_transfer_and_hash(
                     &context_mu,
                     buffer.get_gpu_buffers().unwrap(),
@@ -127,6 +139,11 @@ pub fn create_gpu_worker_task_async(
                 );
                 let deadline = result.0;
                 let offset = result.1;
+                metrics.hashed(
+                    metrics::GPU,
+                    last_buffer_info_a.len as u64 / 64,
+                    start.elapsed(),
+                );
 
                 tx_nonce_data
                     .clone()
@@ -0,0 +1,306 @@
+//! Append-only journal of submitted deadlines and their outcomes.
+//!
+//! Every state change of a submission is a json line of its own, the last line of a submission
+//! is its current state. Submissions that were still pending when the miner stopped are replayed
+//! on startup as long as their round isn't over, failed and superseded ones aren't. The journal
+//! is compacted to the last line of every submission whenever it is opened.
+
+use crate::com::client::SubmissionParameters;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Queued for submission, possibly retried.
+    Pending,
+    /// Included in a block (or accepted by a pool), see `verify_result`.
+    Included,
+    /// Its round was over before it could be included.
+    Expired,
+    /// Given up on after all retries failed.
+    Failed,
+    /// Replaced by a better deadline of its account before it was included.
+    Superseded,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Unix timestamp in ms.
+    pub ts: u64,
+    pub account_id: u64,
+    pub nonce: u64,
+    pub height: u64,
+    pub block: u64,
+    pub deadline_unadjusted: u64,
+    pub deadline: u64,
+    /// Hex encoded.
+    pub gen_sig: String,
+    pub status: Status,
+    /// Outcome of the deadline verification, only known once included.
+    pub verify_result: Option<bool>,
+}
+
+impl Record {
+    fn new(params: &SubmissionParameters, status: Status, verify_result: Option<bool>) -> Self {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Record {
+            ts,
+            account_id: params.account_id,
+            nonce: params.nonce,
+            height: params.height,
+            block: params.block,
+            deadline_unadjusted: params.deadline_unadjusted,
+            deadline: params.deadline,
+            gen_sig: hex::encode(params.gen_sig),
+            status,
+            verify_result,
+        }
+    }
+
+    pub fn submission(&self) -> Option<SubmissionParameters> {
+        let bytes = hex::decode(&self.gen_sig).ok()?;
+        if bytes.len() != 32 {
+            return None;
+        }
+        let mut gen_sig = [0; 32];
+        gen_sig.copy_from_slice(&bytes);
+        Some(SubmissionParameters {
+            account_id: self.account_id,
+            nonce: self.nonce,
+            height: self.height,
+            block: self.block,
+            deadline_unadjusted: self.deadline_unadjusted,
+            deadline: self.deadline,
+            gen_sig,
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let status = match (self.status, self.verify_result) {
+            (Status::Included, Some(true)) => "included, verified".to_owned(),
+            (Status::Included, Some(false)) => "included, not verified".to_owned(),
+            (status, _) => format!("{:?}", status).to_lowercase(),
+        };
+        format!(
+            "{} height={} account={} nonce={} deadline={} {}",
+            self.ts, self.height, self.account_id, self.nonce, self.deadline, status
+        )
+    }
+}
+
+// settled submissions that survive compacting the journal, pending ones always do
+#[cfg(not(test))]
+const SETTLED_KEPT: usize = 1000;
+#[cfg(test)]
+const SETTLED_KEPT: usize = 2;
+
+#[derive(Clone)]
+pub struct Journal {
+    path: PathBuf,
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    file: File,
+    // the current state of every submission, /status shouldn't have to read the file
+    latest: Latest,
+}
+
+impl Journal {
+    /// Opens the journal at `path` and compacts it to the current state of every submission,
+    /// only the last settled ones are kept.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Journal> {
+        let path = path.as_ref();
+        let mut latest = Latest::default();
+        for record in compact(read(path)?) {
+            latest.push(record);
+        }
+
+        // written next to the journal and renamed, a crash leaves either the old or the new one
+        let tmp_path = path.with_extension("compact");
+        let mut tmp = File::create(&tmp_path)?;
+        for record in &latest.records {
+            tmp.write_all(line(record).as_bytes())?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Journal {
+            path: path.to_path_buf(),
+            inner: Arc::new(Mutex::new(Inner { file, latest })),
+        })
+    }
+
+    /// Appends a state change, the journal is for recovery only, so failures are just logged.
+    pub fn record(
+        &self,
+        params: &SubmissionParameters,
+        status: Status,
+        verify_result: Option<bool>,
+    ) {
+        let record = Record::new(params, status, verify_result);
+        let line = line(&record);
+        let mut inner = self.inner.lock().unwrap();
+        inner.latest.push(record);
+        // one write per line, so a crash leaves at most the last line incomplete
+        let file = &mut inner.file;
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            error!("can't write journal {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Current state of all submissions in the journal, in the order they were first queued.
+    pub fn submissions(&self) -> Vec<Record> {
+        self.inner.lock().unwrap().latest.records.clone()
+    }
+}
+
+fn line(record: &Record) -> String {
+    let mut line = serde_json::to_string(record).expect("journal records serialize");
+    line.push('\n');
+    line
+}
+
+/// Reads the journal at `path` and returns the last record of every submission, in the order
+/// the submissions were first queued.
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<Record>> {
+    let file = match File::open(path.as_ref()) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut latest = Latest::default();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str::<Record>(&line) {
+            Ok(record) => latest.push(record),
+            Err(e) => warn!("skipping journal line '{}': {}", line, e),
+        }
+    }
+    Ok(latest.records)
+}
+
+// drops all but the last `SETTLED_KEPT` settled submissions
+fn compact(records: Vec<Record>) -> Vec<Record> {
+    let settled = records
+        .iter()
+        .filter(|r| r.status != Status::Pending)
+        .count();
+    let mut dropped = settled.saturating_sub(SETTLED_KEPT);
+    records
+        .into_iter()
+        .filter(|r| {
+            if r.status == Status::Pending || dropped == 0 {
+                true
+            } else {
+                dropped -= 1;
+                false
+            }
+        })
+        .collect()
+}
+
+// last record of every submission, in the order the submissions were first queued
+#[derive(Default)]
+struct Latest {
+    records: Vec<Record>,
+    index: HashMap<(u64, u64, u64, String), usize>,
+}
+
+impl Latest {
+    fn push(&mut self, record: Record) {
+        let key = (
+            record.account_id,
+            record.height,
+            record.nonce,
+            record.gen_sig.clone(),
+        );
+        match self.index.get(&key) {
+            Some(&i) => self.records[i] = record,
+            None => {
+                self.index.insert(key, self.records.len());
+                self.records.push(record);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+    use std::fs;
+
+    fn params(nonce: u64, height: u64) -> SubmissionParameters {
+        SubmissionParameters {
+            account_id: 1337,
+            nonce,
+            height,
+            block: 1,
+            deadline_unadjusted: 7123,
+            deadline: 1193,
+            gen_sig: [7; 32],
+        }
+    }
+
+    #[test]
+    fn test_journal() {
+        let dir = test_dir("journal");
+        let path = dir.join("submissions.jsonl");
+        assert!(read(&path).unwrap().is_empty());
+
+        let journal = Journal::open(&path).unwrap();
+        journal.record(&params(1, 10), Status::Pending, None);
+        journal.record(&params(2, 10), Status::Pending, None);
+        journal.record(&params(1, 10), Status::Included, Some(true));
+        journal.record(&params(3, 11), Status::Pending, None);
+        // torn write of a crash
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"ts\":1,\"acc")
+            .unwrap();
+
+        let journal = Journal::open(&path).unwrap();
+        journal.record(&params(2, 10), Status::Expired, None);
+        journal.record(&params(4, 11), Status::Pending, None);
+        journal.record(&params(4, 11), Status::Failed, None);
+        let records = journal.submissions();
+        assert_eq!(records, read(&path).unwrap());
+        let states: Vec<_> = records
+            .iter()
+            .map(|r| (r.nonce, r.status, r.verify_result))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                (1, Status::Included, Some(true)),
+                (2, Status::Expired, None),
+                (3, Status::Pending, None),
+                (4, Status::Failed, None),
+            ]
+        );
+        assert_eq!(records[1].submission(), Some(params(2, 10)));
+        assert!(records[3].to_text().ends_with(" failed"));
+
+        // the oldest settled submission is dropped, the pending one is kept
+        let journal = Journal::open(&path).unwrap();
+        let nonces: Vec<_> = journal.submissions().iter().map(|r| r.nonce).collect();
+        assert_eq!(nonces, vec![2, 3, 4]);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
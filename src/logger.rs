@@ -23,6 +23,15 @@ fn to_log_level(s: &str, default: log::LevelFilter) -> log::LevelFilter {
 }
 
 pub fn init_logger(cfg: &Cfg) -> log4rs::Handle {
+    log4rs::init_config(logger_config(cfg)).unwrap()
+}
+
+/// Applies the log levels and patterns of a reloaded config.
+pub fn reload_logger(handle: &log4rs::Handle, cfg: &Cfg) {
+    handle.set_config(logger_config(cfg));
+}
+
+fn logger_config(cfg: &Cfg) -> Config {
     let level_console = to_log_level(&cfg.console_log_level, log::LevelFilter::Info);
     let level_logfile = to_log_level(&cfg.logfile_log_level, log::LevelFilter::Warn);
     let mut console_log_pattern = if cfg.show_progress {
@@ -49,7 +58,7 @@ pub fn init_logger(cfg: &Cfg) -> log4rs::Handle {
     let trigger = SizeTrigger::new(&cfg.logfile_max_size * 1024 * 1024);
     let policy = Box::new(CompoundPolicy::new(Box::new(trigger), Box::new(roller)));
 
-    let config = if level_logfile == log::LevelFilter::Off {
+    if level_logfile == log::LevelFilter::Off {
         Config::builder()
             .appender(
                 Appender::builder()
@@ -81,8 +90,7 @@ pub fn init_logger(cfg: &Cfg) -> log4rs::Handle {
                     .build(LevelFilter::Trace),
             )
             .unwrap()
-    };
-    log4rs::init_config(config).unwrap()
+    }
 }
 
 #[cfg(test)]
@@ -12,14 +12,21 @@ mod com;
 mod config;
 mod cpu_worker;
 mod future;
+mod journal;
 mod logger;
+mod metrics;
 mod miner;
 mod plot;
+mod plotter;
 mod poc_hashing;
 mod reader;
+mod reload;
 mod requests;
 mod shabal256;
+mod status;
+mod submission_policy;
 mod utils;
+mod verifier;
 
 #[cfg(feature = "opencl")]
 mod gpu_worker;
@@ -27,12 +34,23 @@ mod gpu_worker;
 mod gpu_worker_async;
 #[cfg(feature = "opencl")]
 mod ocl;
+#[cfg(feature = "io_uring")]
+mod uring;
 
+#[cfg(all(feature = "io_uring", not(target_os = "linux")))]
+compile_error!("the io_uring feature is only available on linux");
+
+use crate::com::keystore::{read_password, Keystore};
 use crate::config::load_cfg;
 use crate::miner::Miner;
-use clap::{App, Arg};
-use futures::Future;
+use crate::plotter::PlotterTask;
+use crate::verifier::VerifyTask;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
 use tokio::runtime::Builder;
 
 cfg_if! {
@@ -96,6 +114,143 @@ cfg_if! {
     }
 }
 
+fn thread_count(matches: &ArgMatches, cfg: &config::Cfg) -> usize {
+    if matches.is_present("threads") {
+        value_t!(matches, "threads", usize).unwrap_or_else(|e| e.exit())
+    } else {
+        cfg.cpu_threads
+    }
+}
+
+fn plot(matches: &ArgMatches, cfg: &config::Cfg) -> i32 {
+    let task = PlotterTask {
+        account_id: value_t!(matches, "id", u64).unwrap_or_else(|e| e.exit()),
+        start_nonce: value_t!(matches, "sn", u64).unwrap_or_else(|e| e.exit()),
+        nonces: value_t!(matches, "n", u64).unwrap_or_else(|e| e.exit()),
+        output_path: PathBuf::from(matches.value_of("path").unwrap()),
+        use_direct_io: cfg.hdd_use_direct_io && !matches.is_present("no-direct-io"),
+        threads: thread_count(matches, cfg),
+        nonces_per_chunk: value_t!(matches, "nonces-per-chunk", u64)
+            .unwrap_or_else(|e| e.exit()),
+        show_progress: cfg.show_progress,
+    };
+
+    match plotter::plot(&task) {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("plotting failed: {}", e);
+            1
+        }
+    }
+}
+
+fn verify(matches: &ArgMatches, cfg: &config::Cfg) -> i32 {
+    let task = VerifyTask {
+        paths: match matches.values_of("paths") {
+            Some(paths) => paths.map(PathBuf::from).collect(),
+            None => cfg.plot_dirs.clone(),
+        },
+        sample: if matches.is_present("all") {
+            None
+        } else {
+            Some(value_t!(matches, "sample", u64).unwrap_or_else(|e| e.exit()))
+        },
+        threads: thread_count(matches, cfg),
+        nonces_per_chunk: value_t!(matches, "nonces-per-chunk", u64)
+            .unwrap_or_else(|e| e.exit()),
+    };
+
+    let reports = verifier::verify(&task);
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&reports).unwrap());
+    } else {
+        for report in &reports {
+            println!("{}", report.to_text());
+        }
+    }
+
+    if reports.iter().all(|r| r.passed) {
+        0
+    } else {
+        1
+    }
+}
+
+fn submissions(matches: &ArgMatches, cfg: &config::Cfg) -> i32 {
+    let last = value_t!(matches, "last", usize).unwrap_or_else(|e| e.exit());
+    let records = match journal::read(&cfg.journal_path) {
+        Ok(records) => records,
+        Err(e) => {
+            error!("can't read journal {}: {}", cfg.journal_path.display(), e);
+            return 1;
+        }
+    };
+
+    let records = &records[records.len().saturating_sub(last)..];
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(records).unwrap());
+    } else {
+        for record in records {
+            println!("{}", record.to_text());
+        }
+    }
+    0
+}
+
+fn key(matches: &ArgMatches, cfg: &config::Cfg) -> i32 {
+    let keystore = Keystore::new(&cfg.keystore_path);
+    let password_file = match matches.value_of("password-file") {
+        Some(password_file) => Some(PathBuf::from(password_file)),
+        None => cfg.keystore_password_file.clone(),
+    };
+
+    let res = match matches.subcommand() {
+        ("add", Some(matches)) => {
+            let account_id = value_t!(matches, "account", u64).unwrap_or_else(|e| e.exit());
+            let secret = match matches.value_of("secret-file") {
+                Some(secret_file) => fs::read_to_string(secret_file),
+                None => {
+                    let mut secret = String::new();
+                    io::stdin().read_line(&mut secret).map(|_| secret)
+                }
+            };
+            let secret = match secret {
+                Ok(secret) => secret,
+                Err(e) => {
+                    error!("can't read secret: {}", e);
+                    return 1;
+                }
+            };
+            read_password(&password_file, &cfg.keystore_password_env)
+                .and_then(|password| keystore.add(account_id, &secret, &password))
+                .map(|address| info!("account {}: key added, address={}", account_id, address))
+        }
+        ("list", _) => keystore.list().map(|key_files| {
+            for key_file in key_files {
+                println!("{} {}", key_file.account_id, key_file.address);
+            }
+        }),
+        ("remove", Some(matches)) => {
+            let account_id = value_t!(matches, "account", u64).unwrap_or_else(|e| e.exit());
+            keystore
+                .remove(account_id)
+                .map(|_| info!("account {}: key removed", account_id))
+        }
+        _ => {
+            println!("{}", matches.usage());
+            return 1;
+        }
+    };
+
+    match res {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("{}", e);
+            1
+        }
+    }
+}
+
 fn main() {
     let arg = App::new("Scavenger - a PoC miner")
         .version(crate_version!())
@@ -109,6 +264,169 @@ fn main() {
                 .help("Location of the config file")
                 .takes_value(true)
                 .default_value("config.yaml"),
+        )
+        .subcommand(
+            SubCommand::with_name("plot")
+                .about("Writes a PoC2 plot file named {account}_{start}_{nonces}")
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .value_name("ACCOUNT_ID")
+                        .help("Numeric account id to plot for")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("sn")
+                        .long("sn")
+                        .value_name("START_NONCE")
+                        .help("First nonce of the plot")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("n")
+                        .long("n")
+                        .value_name("NONCES")
+                        .help("Number of nonces to plot (1 nonce = 256 KiB)")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .long("path")
+                        .value_name("DIR")
+                        .help("Target directory of the plot file")
+                        .takes_value(true)
+                        .default_value("."),
+                )
+                .arg(
+                    Arg::with_name("nonces-per-chunk")
+                        .long("nonces-per-chunk")
+                        .value_name("NONCES")
+                        .help("Nonces generated in memory before writing (2 x 256 KiB each)")
+                        .takes_value(true)
+                        .default_value("1024"),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .long("threads")
+                        .value_name("THREADS")
+                        .help("Number of hashing threads (default: cpu_threads of the config)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("no-direct-io")
+                        .long("no-direct-io")
+                        .help("Write without direct io")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Recomputes nonces of plot files and compares them with the data on disk")
+                .arg(
+                    Arg::with_name("paths")
+                        .value_name("PATH")
+                        .help("Plot files or directories (default: plot_dirs of the config)")
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("sample")
+                        .long("sample")
+                        .value_name("NONCES")
+                        .help("Number of random nonces checked per plot file")
+                        .takes_value(true)
+                        .default_value("16"),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Check all nonces instead of a sample")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("nonces-per-chunk")
+                        .long("nonces-per-chunk")
+                        .value_name("NONCES")
+                        .help("Consecutive nonces regenerated at once (256 KiB each)")
+                        .takes_value(true)
+                        .default_value("256"),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .long("threads")
+                        .value_name("THREADS")
+                        .help("Number of hashing threads (default: cpu_threads of the config)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the results as json")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("submissions")
+                .about("Lists recent submissions and their results (journal_path of the config)")
+                .arg(
+                    Arg::with_name("last")
+                        .long("last")
+                        .value_name("COUNT")
+                        .help("Number of submissions to list")
+                        .takes_value(true)
+                        .default_value("20"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the submissions as json")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("key")
+                .about("Manages the encrypted keystore (keystore_path of the config)")
+                .arg(
+                    Arg::with_name("password-file")
+                        .long("password-file")
+                        .value_name("FILE")
+                        .help("File containing the keystore password (default: keystore_password_file or keystore_password_env of the config)")
+                        .takes_value(true),
+                )
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Encrypts the key of an account, the secret is read from stdin if no file is given")
+                        .arg(
+                            Arg::with_name("account")
+                                .long("account")
+                                .value_name("ACCOUNT_ID")
+                                .help("Numeric account id of the plots")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("secret-file")
+                                .long("secret-file")
+                                .value_name("FILE")
+                                .help("File containing the secret (same format as account_id_to_secret_phrase)")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("list").about("Lists accounts and addresses"))
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Deletes the key of an account")
+                        .arg(
+                            Arg::with_name("account")
+                                .long("account")
+                                .value_name("ACCOUNT_ID")
+                                .help("Numeric account id of the plots")
+                                .takes_value(true)
+                                .required(true),
+                        ),
+                ),
         );
     #[cfg(feature = "opencl")]
     let arg = arg.arg(
@@ -123,12 +441,28 @@ fn main() {
     let config = matches.value_of("config").unwrap();
 
     let cfg_loaded = load_cfg(config);
-    logger::init_logger(&cfg_loaded);
+    let logger = logger::init_logger(&cfg_loaded);
 
     info!("Scavenger v.{}", crate_version!());
     #[cfg(feature = "opencl")]
     info!("GPU extensions: OpenCL");
 
+    if let Some(matches) = matches.subcommand_matches("plot") {
+        process::exit(plot(matches, &cfg_loaded));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("verify") {
+        process::exit(verify(matches, &cfg_loaded));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("submissions") {
+        process::exit(submissions(matches, &cfg_loaded));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("key") {
+        process::exit(key(matches, &cfg_loaded));
+    }
+
     if matches.is_present("opencl") {
         #[cfg(feature = "opencl")]
         ocl::platform_info();
@@ -140,8 +474,29 @@ fn main() {
     #[cfg(feature = "opencl")]
     ocl::gpu_info(&cfg_loaded);
 
+    // SIGINT and SIGTERM, Ctrl-C and closing the console on windows
+    let (tx_signal, rx_signal) = crossbeam_channel::unbounded();
+    if let Err(e) = ctrlc::set_handler(move || {
+        tx_signal.send(()).ok();
+    }) {
+        error!("can't handle signals: {}. Shutting down...", e);
+        process::exit(1);
+    }
+    let shutdown_timeout = Duration::from_secs(cfg_loaded.shutdown_timeout);
+
     let rt = Builder::new().core_threads(1).build().unwrap();
-    let m = Miner::new(cfg_loaded, rt.executor());
-    m.run();
-    rt.shutdown_on_idle().wait().unwrap();
+    let m = Miner::new(cfg_loaded.clone(), rt.executor());
+    let miner = m.run();
+    reload::watch(config.to_owned(), cfg_loaded, miner.clone(), logger);
+
+    rx_signal.recv().ok();
+    info!("{: <80}", "shutting down, send the signal again to quit immediately...");
+    // 0 if every submission was settled, 2 if some are left for the next start
+    let code = if miner.shutdown(shutdown_timeout, &rx_signal) {
+        0
+    } else {
+        2
+    };
+    log::logger().flush();
+    process::exit(code);
 }
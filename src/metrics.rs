@@ -0,0 +1,328 @@
+//! Counters for Prometheus, served at `/metrics` of `metrics_address`.
+//!
+//! Events are counted here, everything else is taken from the `MinerStatus` at scrape time.
+
+use crate::miner::MinerStatus;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const CPU: &str = "cpu";
+#[cfg(feature = "opencl")]
+pub const GPU: &str = "gpu";
+
+/// Outcomes of submissions, see `Metrics::submission`.
+pub const SUBMITTED: &str = "submitted";
+pub const VERIFIED: &str = "verified";
+pub const REJECTED: &str = "rejected";
+pub const EXPIRED: &str = "expired";
+pub const FAILED: &str = "failed";
+
+#[derive(Default)]
+struct Hashing {
+    nonces: u64,
+    micros: u64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    rounds: AtomicU64,
+    last_round_ms: AtomicU64,
+    hashing: Mutex<HashMap<&'static str, Hashing>>,
+    submissions: Mutex<HashMap<&'static str, u64>>,
+    outages: AtomicU64,
+    mining_info_errors: AtomicU64,
+}
+
+impl Metrics {
+    /// All plots were read for a round.
+    pub fn round_finished(&self, round_time_ms: u64) {
+        self.rounds.fetch_add(1, Ordering::Relaxed);
+        self.last_round_ms.store(round_time_ms, Ordering::Relaxed);
+    }
+
+    /// A `worker` (`CPU` or `GPU`) searched `nonces` for the best deadline.
+    pub fn hashed(&self, worker: &'static str, nonces: u64, elapsed: Duration) {
+        let mut hashing = self.hashing.lock().unwrap();
+        let hashing = hashing.entry(worker).or_insert_with(Hashing::default);
+        hashing.nonces += nonces;
+        hashing.micros += elapsed.as_micros() as u64;
+    }
+
+    /// A submission was queued or an attempt to submit it ended with `outcome`.
+    pub fn submission(&self, outcome: &'static str) {
+        *self.submissions.lock().unwrap().entry(outcome).or_insert(0) += 1;
+    }
+
+    pub fn mining_info_error(&self, outage_started: bool) {
+        self.mining_info_errors.fetch_add(1, Ordering::Relaxed);
+        if outage_started {
+            self.outages.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Prometheus text format.
+    pub fn render(&self, status: &MinerStatus) -> String {
+        let mut out = String::new();
+
+        metric(
+            &mut out,
+            "scavenger_height",
+            "gauge",
+            "Height of the current round.",
+            vec![(String::new(), status.height)],
+        );
+        metric(
+            &mut out,
+            "scavenger_rounds_total",
+            "counter",
+            "Rounds all plots were read for.",
+            vec![(String::new(), self.rounds.load(Ordering::Relaxed))],
+        );
+        metric(
+            &mut out,
+            "scavenger_round_time_seconds",
+            "gauge",
+            "Time it took to read all plots in the last finished round.",
+            vec![(String::new(), seconds(self.last_round_ms.load(Ordering::Relaxed)))],
+        );
+
+        let mut drives: Vec<_> = status.drives.iter().collect();
+        drives.sort_by(|a, b| a.0.cmp(b.0));
+        metric(
+            &mut out,
+            "scavenger_drive_read_bytes_total",
+            "counter",
+            "Bytes read from a drive.",
+            drives
+                .iter()
+                .map(|(drive, p)| (label("drive", drive), p.total_bytes_read))
+                .collect(),
+        );
+        metric(
+            &mut out,
+            "scavenger_drive_read_seconds_total",
+            "counter",
+            "Time spent reading from a drive.",
+            drives
+                .iter()
+                .map(|(drive, p)| (label("drive", drive), seconds(p.total_read_ms)))
+                .collect(),
+        );
+        metric(
+            &mut out,
+            "scavenger_drive_read_speed_bytes",
+            "gauge",
+            "Read speed of a drive in the current round in bytes per second.",
+            drives
+                .iter()
+                .map(|(drive, p)| (label("drive", drive), p.speed()))
+                .collect(),
+        );
+
+        let hashing = self.hashing.lock().unwrap();
+        let mut workers: Vec<_> = hashing.iter().collect();
+        workers.sort_by(|a, b| a.0.cmp(b.0));
+        metric(
+            &mut out,
+            "scavenger_hashed_nonces_total",
+            "counter",
+            "Nonces searched for deadlines by a worker type.",
+            workers
+                .iter()
+                .map(|(worker, h)| (label("worker", worker), h.nonces))
+                .collect(),
+        );
+        metric(
+            &mut out,
+            "scavenger_hashing_seconds_total",
+            "counter",
+            "Time spent searching for deadlines by a worker type.",
+            workers
+                .iter()
+                .map(|(worker, h)| (label("worker", worker), h.micros as f64 / 1_000_000.0))
+                .collect(),
+        );
+        drop(hashing);
+
+        let mut best_deadlines: Vec<_> = status.best_deadlines.iter().collect();
+        best_deadlines.sort();
+        metric(
+            &mut out,
+            "scavenger_best_deadline_seconds",
+            "gauge",
+            "Best submitted deadline of an account in the current round.",
+            best_deadlines
+                .iter()
+                .map(|(account_id, deadline)| (label("account", account_id), **deadline))
+                .collect(),
+        );
+
+        let submissions = self.submissions.lock().unwrap();
+        metric(
+            &mut out,
+            "scavenger_submissions_total",
+            "counter",
+            "Submissions queued (submitted) and their outcomes.",
+            [SUBMITTED, VERIFIED, REJECTED, EXPIRED, FAILED]
+                .iter()
+                .map(|outcome| {
+                    let count = submissions.get(outcome).cloned().unwrap_or(0);
+                    (label("outcome", outcome), count)
+                })
+                .collect(),
+        );
+        drop(submissions);
+
+        let mut corrupt_nonces: Vec<_> = status.corrupt_nonces.iter().collect();
+        corrupt_nonces.sort();
+        metric(
+            &mut out,
+            "scavenger_corrupt_nonces_total",
+            "counter",
+            "Deadlines not submitted because the plot data was corrupt.",
+            corrupt_nonces
+                .iter()
+                .map(|(plot, count)| (label("plot", plot), **count))
+                .collect(),
+        );
+
+        metric(
+            &mut out,
+            "scavenger_chain_outage",
+            "gauge",
+            "1 while the mining info can't be fetched.",
+            vec![(String::new(), status.outage as u64)],
+        );
+        metric(
+            &mut out,
+            "scavenger_chain_outages_total",
+            "counter",
+            "Times fetching the mining info started to fail.",
+            vec![(String::new(), self.outages.load(Ordering::Relaxed))],
+        );
+        metric(
+            &mut out,
+            "scavenger_mining_info_errors_total",
+            "counter",
+            "Failed attempts to fetch the mining info.",
+            vec![(String::new(), self.mining_info_errors.load(Ordering::Relaxed))],
+        );
+
+        metric(
+            &mut out,
+            "scavenger_plot_capacity_bytes",
+            "gauge",
+            "Size of all plot files.",
+            vec![(String::new(), status.capacity)],
+        );
+        metric(
+            &mut out,
+            "scavenger_plots",
+            "gauge",
+            "Number of plot files.",
+            vec![(String::new(), status.plots as u64)],
+        );
+        out
+    }
+}
+
+fn metric<V: Display>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: Vec<(String, V)>,
+) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+    for (labels, value) in samples {
+        writeln!(out, "{}{} {}", name, labels, value).unwrap();
+    }
+}
+
+fn label<V: Display>(name: &str, value: V) -> String {
+    let value = value
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("{{{}=\"{}\"}}", name, value)
+}
+
+fn seconds(ms: u64) -> f64 {
+    ms as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::DriveProgress;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.round_finished(1500);
+        metrics.hashed(CPU, 1000, Duration::from_millis(250));
+        metrics.hashed(CPU, 1000, Duration::from_millis(250));
+        metrics.submission(SUBMITTED);
+        metrics.submission(VERIFIED);
+        metrics.mining_info_error(true);
+        metrics.mining_info_error(false);
+
+        let mut drives = HashMap::new();
+        drives.insert(
+            "C:\\".to_owned(),
+            DriveProgress {
+                size: 4096,
+                bytes_read: 2048,
+                read_ms: 500,
+                finished: false,
+                total_bytes_read: 10240,
+                total_read_ms: 2500,
+            },
+        );
+        let mut best_deadlines = HashMap::new();
+        best_deadlines.insert(1337, 42);
+        let status = MinerStatus {
+            height: 7,
+            scoop: 1,
+            base_target: 1,
+            scanning: true,
+            paused: false,
+            outage: false,
+            round_time: 0,
+            capacity: 1 << 30,
+            plots: 2,
+            drives,
+            best_deadlines,
+            corrupt_nonces: HashMap::new(),
+            submissions: Vec::new(),
+        };
+
+        let text = metrics.render(&status);
+        for line in &[
+            "scavenger_height 7",
+            "scavenger_rounds_total 1",
+            "scavenger_round_time_seconds 1.5",
+            "scavenger_drive_read_bytes_total{drive=\"C:\\\\\"} 10240",
+            "scavenger_drive_read_seconds_total{drive=\"C:\\\\\"} 2.5",
+            "scavenger_drive_read_speed_bytes{drive=\"C:\\\\\"} 4096",
+            "scavenger_hashed_nonces_total{worker=\"cpu\"} 2000",
+            "scavenger_hashing_seconds_total{worker=\"cpu\"} 0.5",
+            "scavenger_best_deadline_seconds{account=\"1337\"} 42",
+            "scavenger_submissions_total{outcome=\"submitted\"} 1",
+            "scavenger_submissions_total{outcome=\"expired\"} 0",
+            "scavenger_chain_outage 0",
+            "scavenger_chain_outages_total 1",
+            "scavenger_mining_info_errors_total 2",
+            "scavenger_plot_capacity_bytes 1073741824",
+            "# TYPE scavenger_plots gauge",
+        ] {
+            assert!(text.lines().any(|l| l == *line), "missing '{}'", line);
+        }
+    }
+}
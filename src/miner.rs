@@ -1,34 +1,43 @@
-use crate::com::api::MiningInfoResponse as MiningInfo;
+use crate::com::api::{FetchError, MiningInfoResponse as MiningInfo};
+use crate::com::client::BackendKind;
+use crate::com::keystore::{read_password, Keystore};
+use crate::com::round::RoundSchedule;
+use crate::com::signer::{load_signers, Signer};
 use crate::config::Cfg;
 use crate::cpu_worker::create_cpu_worker_task;
-use crate::future::interval::Interval;
 #[cfg(feature = "opencl")]
 use crate::gpu_worker::create_gpu_worker_task;
 #[cfg(feature = "opencl")]
 use crate::gpu_worker_async::create_gpu_worker_task_async;
+use crate::journal::{Journal, Record};
+use crate::metrics::Metrics;
 #[cfg(feature = "opencl")]
 use crate::ocl::GpuBuffer;
 #[cfg(feature = "opencl")]
 use crate::ocl::GpuContext;
-use crate::plot::{Plot, SCOOP_SIZE};
+use crate::plot::{Meta, Plot, PlotSource, NONCE_SIZE, SCOOP_SIZE};
+use crate::plotter::noncegen;
 use crate::poc_hashing;
-use crate::reader::Reader;
+use crate::reader::{check_overlap, count_open_files, DrivePlots, DriveProgress, Reader};
 use crate::requests::RequestHandler;
-use crate::utils::{get_device_id, new_thread_pool};
+use crate::status;
+use crate::submission_policy::{Decision, Reason, SubmissionMode, SubmissionPolicy};
+use crate::utils::{get_device_id, new_thread_pool, raise_open_files_limit};
 use crossbeam_channel;
+use crossbeam_channel::Receiver;
 use filetime::FileTime;
-use futures::sync::mpsc;
+use futures::sync::{mpsc, oneshot};
 #[cfg(feature = "opencl")]
 use ocl_core::Mem;
-use std::cmp::{max, min};
-use std::collections::HashMap;
+use std::cmp::max;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::read_dir;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
-use std::u64;
 use stopwatch::Stopwatch;
 use tokio::prelude::*;
 use tokio::runtime::TaskExecutor;
@@ -37,56 +46,97 @@ pub struct Miner {
     reader: Reader,
     request_handler: RequestHandler,
     rx_nonce_data: mpsc::Receiver<NonceData>,
-    target_deadline: u64,
-    account_id_to_target_deadline: HashMap<u64, u64>,
     state: Arc<Mutex<State>>,
-    reader_task_count: usize,
-    get_mining_info_interval: u64,
     executor: TaskExecutor,
     wakeup_after: i64,
+    plot_dirs: Vec<PathBuf>,
+    mmap_plot_dirs: Vec<PathBuf>,
+    use_direct_io: bool,
+    // plots of the cpu benchmark, their deadlines can't be verified
+    dummy_plots: bool,
+    // plots of reloaded plot dirs need keys too (Substrate only)
+    signers: Option<Arc<HashMap<u64, Signer>>>,
+    api_address: Option<SocketAddr>,
+    metrics_address: Option<SocketAddr>,
+    metrics: Arc<Metrics>,
 }
 
 pub struct State {
     generation_signature_bytes: [u8; 32],
     height: u64,
     block: u64,
-    account_id_to_best_deadline: HashMap<u64, u64>,
-    server_target_deadline: u64,
+    policy: SubmissionPolicy,
     base_target: u64,
+    round_schedule: RoundSchedule,
     sw: Stopwatch,
+    // time since the current round was started
+    round_sw: Stopwatch,
+    // ms the round had been running on chain when the miner started it
+    round_offset: u64,
     scanning: bool,
     processed_reader_tasks: usize,
     scoop: u32,
     first: bool,
     outage: bool,
+    // scanning was paused through the status api
+    paused: bool,
+    // no new rounds or submissions once set
+    shutting_down: bool,
+    // plots of reloaded plot dirs, they are swapped in when the next round starts
+    next_plots: Option<(HashMap<String, DrivePlots>, u64)>,
+    plots: Vec<Meta>,
+    reader_task_count: usize,
+    total_size: u64,
+    // deadlines that didn't match the plot data, by plot name
+    corrupt_nonces: HashMap<String, u64>,
 }
 
 impl State {
-    fn new() -> Self {
+    fn new(policy: SubmissionPolicy) -> Self {
         Self {
             height: 0,
             block: 0,
             scoop: 0,
-            account_id_to_best_deadline: HashMap::new(),
-            server_target_deadline: u64::MAX,
+            policy,
             base_target: 1,
+            round_schedule: RoundSchedule::per_block(),
             processed_reader_tasks: 0,
             sw: Stopwatch::new(),
+            round_sw: Stopwatch::new(),
+            round_offset: 0,
             generation_signature_bytes: [0; 32],
             scanning: false,
             first: true,
             outage: false,
+            paused: false,
+            shutting_down: false,
+            next_plots: None,
+            plots: Vec::new(),
+            reader_task_count: 0,
+            total_size: 0,
+            corrupt_nonces: HashMap::new(),
         }
     }
 
+    fn set_plots(
+        &mut self,
+        drive_id_to_plots: &HashMap<String, DrivePlots>,
+        total_size: u64,
+    ) {
+        self.plots = drive_id_to_plots
+            .values()
+            .flat_map(|plots| plots.iter().map(|p| p.lock().unwrap().meta().clone()))
+            .collect();
+        self.reader_task_count = drive_id_to_plots.len();
+        self.total_size = total_size;
+    }
+
     fn update_mining_info(&mut self, mining_info: &MiningInfo) {
-        for best_deadlines in self.account_id_to_best_deadline.values_mut() {
-            *best_deadlines = u64::MAX;
-        }
+        self.policy.new_round(mining_info.target_deadline);
         self.height = mining_info.height;
         self.block += 1;
         self.base_target = mining_info.base_target;
-        self.server_target_deadline = mining_info.target_deadline;
+        self.round_schedule = mining_info.round_schedule;
 
         self.generation_signature_bytes = mining_info.generation_signature.clone();
         let scoop =
@@ -97,10 +147,49 @@ impl State {
         );
         self.scoop = scoop;
 
+        // the chain knows when the round started, pools and fresh chains don't tell
+        self.round_offset = match mining_info.duration_from_last_mining {
+            std::u64::MAX => 0,
+            duration => duration,
+        };
         self.sw.restart();
+        self.round_sw.restart();
         self.processed_reader_tasks = 0;
         self.scanning = true;
     }
+
+    // a new best block only starts a round if it belongs to the next one, replaced blocks
+    // restart the current round
+    fn starts_round(&self, mining_info: &MiningInfo) -> bool {
+        mining_info.generation_signature != self.generation_signature_bytes
+            && (self.height == 0
+                || self.height == mining_info.height
+                || !mining_info
+                    .round_schedule
+                    .same_round(self.height, mining_info.height))
+    }
+
+    // ms since the round started on chain, a miner that saw it late has less time
+    fn round_time(&self) -> u64 {
+        self.round_offset
+            .saturating_add(self.round_sw.elapsed_ms() as u64)
+    }
+
+    fn window_closed(&self) -> bool {
+        self.round_schedule.window_closed(self.round_time())
+    }
+
+    fn count_corrupt_nonce(&mut self, plot: &str) -> u64 {
+        let corrupt_nonces = self.corrupt_nonces.entry(plot.to_owned()).or_insert(0);
+        *corrupt_nonces += 1;
+        *corrupt_nonces
+    }
+
+    // a replaced block restarts the round at the same height, nonces of the old one were
+    // computed against another generation signature
+    fn is_current(&self, nonce_data: &NonceData) -> bool {
+        self.height == nonce_data.height && self.block == nonce_data.block
+    }
 }
 
 pub struct NonceData {
@@ -162,51 +251,87 @@ impl Buffer for CpuBuffer {
     }
 }
 
+// open files left for sockets, logs and the journal when plot files are opened
+const RESERVED_OPEN_FILES: u64 = 64;
+
+// `open_files` are held by plots that stay in use until the scanned ones replace them
 fn scan_plots(
     plot_dirs: &[PathBuf],
+    mmap_plot_dirs: &[PathBuf],
     use_direct_io: bool,
     dummy: bool,
-) -> (HashMap<String, Arc<Vec<Mutex<Plot>>>>, u64) {
-    let mut drive_id_to_plots: HashMap<String, Vec<Mutex<Plot>>> = HashMap::new();
+    mut open_files: u64,
+) -> (HashMap<String, DrivePlots>, u64) {
+    let mut drive_id_to_plots: HashMap<String, Vec<Plot>> = HashMap::new();
     let mut global_capacity: u64 = 0;
 
+    // every plot keeps its file open, plots read unaligned with direct io a second one
+    let open_files_limit = raise_open_files_limit().unwrap_or_else(|e| {
+        warn!("can't raise the limit of open files: {}", e);
+        u64::max_value()
+    });
+    let mut limit_reached = false;
+
     for plot_dir in plot_dirs {
         let mut num_plots = 0;
         let mut local_capacity: u64 = 0;
+        let use_mmap = mmap_plot_dirs.contains(plot_dir);
         for file in read_dir(plot_dir).unwrap() {
             let file = &file.unwrap().path();
 
-            if let Ok(p) = Plot::new(file, use_direct_io, dummy) {
-                let drive_id = get_device_id(&file.to_str().unwrap().to_string());
-                let plots = drive_id_to_plots.entry(drive_id).or_insert(Vec::new());
+            if open_files + 2 + RESERVED_OPEN_FILES > open_files_limit {
+                error!(
+                    "the limit of open files ({}) is too low for the plots, skipping {} and the \
+                     ones after it. Raise it, e.g. with 'ulimit -n'",
+                    open_files_limit,
+                    file.to_str().unwrap()
+                );
+                limit_reached = true;
+                break;
+            }
+            match Plot::new(file, use_direct_io, use_mmap, dummy) {
+                Ok(p) => {
+                    open_files += p.open_files();
+                    let drive_id = get_device_id(&file.to_str().unwrap().to_string());
+                    let plots = drive_id_to_plots.entry(drive_id).or_insert(Vec::new());
 
-                local_capacity += p.meta.nonces as u64;
-                plots.push(Mutex::new(p));
-                num_plots += 1;
+                    local_capacity += p.meta.nonces as u64;
+                    plots.push(p);
+                    num_plots += 1;
+                }
+                Err(e) => debug!("skipping {}: {}", file.to_str().unwrap(), e),
             }
         }
 
         info!(
-            "path={}, files={}, size={:.4} TiB",
+            "path={}, files={}, size={:.4} TiB{}",
             plot_dir.to_str().unwrap(),
             num_plots,
-            local_capacity as f64 / 4.0 / 1024.0 / 1024.0
+            local_capacity as f64 / 4.0 / 1024.0 / 1024.0,
+            if use_mmap { ", mmap" } else { "" }
         );
 
         global_capacity += local_capacity;
         if num_plots == 0 {
             warn!("no plots in {}", plot_dir.to_str().unwrap());
         }
+        if limit_reached {
+            break;
+        }
     }
 
     // sort plots by filetime and get them into an arc
-    let drive_id_to_plots: HashMap<String, Arc<Vec<Mutex<Plot>>>> = drive_id_to_plots
+    let drive_id_to_plots: HashMap<String, DrivePlots> = drive_id_to_plots
         .drain()
         .map(|(drive_id, mut plots)| {
             plots.sort_by_key(|p| {
-                let m = p.lock().unwrap().fh.metadata().unwrap();
+                let m = p.fh.metadata().unwrap();
                 -FileTime::from_last_modification_time(&m).unix_seconds()
             });
+            let plots = plots
+                .into_iter()
+                .map(|p| Mutex::new(Box::new(p) as Box<PlotSource + Send>))
+                .collect();
             (drive_id, Arc::new(plots))
         })
         .collect();
@@ -220,10 +345,375 @@ fn scan_plots(
     (drive_id_to_plots, global_capacity * 64)
 }
 
+// keys of the encrypted keystore take precedence over plaintext keys of the config
+fn load_keys(cfg: &Cfg) -> Result<HashMap<u64, Signer>, String> {
+    let mut signers = load_signers(&cfg.account_id_to_secret_phrase)
+        .map_err(|e| format!("invalid key for {}", e))?;
+
+    let keystore = Keystore::new(&cfg.keystore_path);
+    let key_count = keystore.list().map_err(|e| e.to_string())?.len();
+    if key_count > 0 {
+        let password = read_password(&cfg.keystore_password_file, &cfg.keystore_password_env)
+            .map_err(|e| e.to_string())?;
+        let unlocked = keystore.unlock(&password).map_err(|e| e.to_string())?;
+        for (account_id, signer) in unlocked {
+            if signers.insert(account_id, signer).is_some() {
+                warn!(
+                    "account {}: keystore key overrides account_id_to_secret_phrase",
+                    account_id
+                );
+            }
+        }
+        info!(
+            "keystore unlocked: path={}, keys={}",
+            cfg.keystore_path.to_str().unwrap(),
+            key_count
+        );
+    }
+    Ok(signers)
+}
+
+// (re)starts reading the plots for the round of `state`
+fn start_reading(state: &mut State, reader: &mut Reader) {
+    state.sw.restart();
+    state.processed_reader_tasks = 0;
+    state.scanning = true;
+    reader.start_reading(
+        state.height,
+        state.block,
+        state.base_target,
+        state.scoop,
+        &Arc::new(state.generation_signature_bytes),
+    );
+}
+
+fn plot_name(plots: &[Meta], account_id: u64, nonce: u64) -> &str {
+    plots
+        .iter()
+        .find(|p| {
+            p.account_id == account_id
+                && p.start_nonce <= nonce
+                && nonce < p.start_nonce + p.nonces
+        })
+        .map_or("unknown", |p| p.name.as_str())
+}
+
+// deadline of a nonce computed from scratch, a worker's one has to match it
+fn recompute_deadline(account_id: u64, nonce: u64, scoop: u32, gensig: &[u8; 32]) -> u64 {
+    let mut nonce_data = vec![0u8; NONCE_SIZE as usize];
+    noncegen(account_id, nonce, 1, &mut nonce_data);
+    let start = scoop as usize * SCOOP_SIZE as usize;
+    poc_hashing::find_best_deadline_rust(&nonce_data[start..start + SCOOP_SIZE as usize], 1, gensig)
+        .0
+}
+
+// every plot account needs a key, otherwise its deadlines could never be submitted
+fn check_signers(
+    drive_id_to_plots: &HashMap<String, DrivePlots>,
+    signers: &HashMap<u64, Signer>,
+) -> bool {
+    let account_ids: BTreeSet<u64> = drive_id_to_plots
+        .values()
+        .flat_map(|plots| plots.iter())
+        .map(|p| p.lock().unwrap().meta().account_id)
+        .collect();
+
+    let mut complete = true;
+    for account_id in account_ids {
+        match signers.get(&account_id) {
+            Some(signer) => info!("account {}: signer={}", account_id, signer.address()),
+            None => {
+                error!(
+                    "account {}: no key in keystore or account_id_to_secret_phrase for its plots",
+                    account_id
+                );
+                complete = false;
+            }
+        }
+    }
+    complete
+}
+
+/// Snapshot of a running miner as served by the status api.
+#[derive(Serialize)]
+pub struct MinerStatus {
+    pub height: u64,
+    pub scoop: u32,
+    pub base_target: u64,
+    pub scanning: bool,
+    pub paused: bool,
+    pub outage: bool,
+    /// Time since the start of the round in ms.
+    pub round_time: i64,
+    /// Plot capacity in bytes.
+    pub capacity: u64,
+    pub plots: usize,
+    pub drives: HashMap<String, DriveProgress>,
+    /// Best submitted deadline of every account in the current round.
+    pub best_deadlines: HashMap<u64, u64>,
+    pub corrupt_nonces: HashMap<String, u64>,
+    /// Most recent submissions first.
+    pub submissions: Vec<Record>,
+}
+
+/// Shared handle of a running miner to watch and control it.
+#[derive(Clone)]
+pub struct MinerHandle {
+    state: Arc<Mutex<State>>,
+    reader: Arc<Mutex<Reader>>,
+    request_handler: RequestHandler,
+    plot_dirs: Arc<Mutex<Vec<PathBuf>>>,
+    mmap_plot_dirs: Vec<PathBuf>,
+    use_direct_io: bool,
+    dummy_plots: bool,
+    signers: Option<Arc<HashMap<u64, Signer>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl MinerHandle {
+    pub fn status(&self, submissions: usize) -> MinerStatus {
+        let mut status = {
+            let state = self.state.lock().unwrap();
+            MinerStatus {
+                height: state.height,
+                scoop: state.scoop,
+                base_target: state.base_target,
+                scanning: state.scanning,
+                paused: state.paused,
+                outage: state.outage,
+                round_time: state.round_sw.elapsed_ms(),
+                capacity: state.total_size * 4096,
+                plots: state.plots.len(),
+                drives: self.reader.lock().unwrap().progress(),
+                best_deadlines: state.policy.best_deadlines().clone(),
+                corrupt_nonces: state.corrupt_nonces.clone(),
+                submissions: Vec::new(),
+            }
+        };
+
+        if submissions > 0 {
+            let records = self.request_handler.journal().submissions();
+            status.submissions = records.into_iter().rev().take(submissions).collect();
+        }
+        status
+    }
+
+    /// Metrics in Prometheus text format.
+    pub fn metrics(&self) -> String {
+        self.metrics.render(&self.status(0))
+    }
+
+    /// Stops reading until `resume`, new rounds are tracked but not scanned.
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.paused {
+            return;
+        }
+        info!("scanning paused");
+        state.paused = true;
+        if state.scanning {
+            self.reader.lock().unwrap().stop_reading();
+            state.scanning = false;
+            state.sw.restart();
+        }
+    }
+
+    /// Scans the current round again unless its mining window is over.
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.paused {
+            return;
+        }
+        info!("scanning resumed");
+        state.paused = false;
+        if state.height != 0 && !state.window_closed() && !state.shutting_down {
+            start_reading(&mut state, &mut self.reader.lock().unwrap());
+        }
+    }
+
+    // the plots in use and the ones waiting for the next round stay open while others are
+    // scanned
+    fn open_plot_files(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        let next_plots = state
+            .next_plots
+            .as_ref()
+            .map_or(0, |(drive_id_to_plots, _)| {
+                count_open_files(drive_id_to_plots)
+            });
+        self.reader.lock().unwrap().open_files() + next_plots
+    }
+
+    /// Loads the plots of the plot dirs again and restarts a round in progress with them.
+    /// Returns the number of plots.
+    pub fn rescan(&self) -> usize {
+        let plot_dirs = self.plot_dirs.lock().unwrap().clone();
+        let (drive_id_to_plots, total_size) = scan_plots(
+            &plot_dirs,
+            &self.mmap_plot_dirs,
+            self.use_direct_io,
+            self.dummy_plots,
+            self.open_plot_files(),
+        );
+        if !self.dummy_plots {
+            check_overlap(&drive_id_to_plots);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let mut reader = self.reader.lock().unwrap();
+        state.next_plots = None;
+        state.set_plots(&drive_id_to_plots, total_size);
+        reader.replace_plots(drive_id_to_plots, total_size);
+        if state.scanning {
+            start_reading(&mut state, &mut reader);
+        }
+        state.plots.len()
+    }
+
+    /// Switches to the plots of `plot_dirs`, e.g. after a config reload. A round being scanned
+    /// isn't restarted, the new plots are read from the next round on. Returns false if keys
+    /// are missing for plot accounts, the current plots are kept then.
+    pub fn set_plot_dirs(&self, plot_dirs: Vec<PathBuf>) -> bool {
+        let (drive_id_to_plots, total_size) = scan_plots(
+            &plot_dirs,
+            &self.mmap_plot_dirs,
+            self.use_direct_io,
+            self.dummy_plots,
+            self.open_plot_files(),
+        );
+        if !self.dummy_plots {
+            check_overlap(&drive_id_to_plots);
+        }
+        if let Some(signers) = &self.signers {
+            if !check_signers(&drive_id_to_plots, signers) {
+                error!("missing keys for plot accounts, keeping the current plots");
+                return false;
+            }
+        }
+        *self.plot_dirs.lock().unwrap() = plot_dirs;
+
+        let mut state = self.state.lock().unwrap();
+        if state.scanning {
+            info!("new plots are read from the next round on");
+            state.next_plots = Some((drive_id_to_plots, total_size));
+        } else {
+            state.set_plots(&drive_id_to_plots, total_size);
+            self.reader
+                .lock()
+                .unwrap()
+                .replace_plots(drive_id_to_plots, total_size);
+        }
+        true
+    }
+
+    pub fn set_submission_policy(
+        &self,
+        mode: SubmissionMode,
+        target_deadline: u64,
+        account_id_to_target_deadline: HashMap<u64, u64>,
+    ) {
+        self.state.lock().unwrap().policy.reconfigure(
+            mode,
+            target_deadline,
+            account_id_to_target_deadline,
+        );
+    }
+
+    pub fn wakeup(&self) {
+        self.reader.lock().unwrap().wakeup();
+    }
+
+    /// Stops reading and ignores new rounds, then waits for queued submissions to be settled,
+    /// at most `timeout` or until `abort` receives. Returns whether all submissions were
+    /// settled, pending ones are replayed on the next start while their round lasts.
+    pub fn shutdown(&self, timeout: Duration, abort: &Receiver<()>) -> bool {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.shutting_down = true;
+            if state.scanning {
+                self.reader.lock().unwrap().stop_reading();
+                state.scanning = false;
+            }
+        }
+
+        let pending = self.request_handler.drain(timeout, abort);
+        if pending == 0 {
+            info!("all submissions settled");
+            true
+        } else {
+            warn!(
+                "{} submissions still pending, they will be replayed on the next start",
+                pending
+            );
+            false
+        }
+    }
+}
+
 impl Miner {
     pub fn new(cfg: Cfg, executor: TaskExecutor) -> Miner {
-        let (drive_id_to_plots, total_size) =
-            scan_plots(&cfg.plot_dirs, cfg.hdd_use_direct_io, cfg.benchmark_cpu());
+        let (drive_id_to_plots, total_size) = scan_plots(
+            &cfg.plot_dirs,
+            &cfg.mmap_plot_dirs,
+            cfg.hdd_use_direct_io,
+            cfg.benchmark_cpu(),
+            0,
+        );
+
+        let backend = match BackendKind::from_url(&cfg.url) {
+            Some(backend) => backend,
+            None => {
+                error!(
+                    "unsupported url scheme '{}', use ws(s):// for a Substrate node \
+                     or http(s):// for a pool/proxy/wallet. Shutting down...",
+                    cfg.url.scheme()
+                );
+                process::exit(1);
+            }
+        };
+        for url in &cfg.fallback_urls {
+            if backend != BackendKind::Substrate
+                || BackendKind::from_url(url) != Some(BackendKind::Substrate)
+            {
+                error!(
+                    "fallback url '{}' isn't supported, fallbacks need a ws(s):// url \
+                     and a Substrate node as primary url. Shutting down...",
+                    url
+                );
+                process::exit(1);
+            }
+        }
+
+        // only Substrate submissions are signed by the miner, wallets get the secret phrase
+        // itself and pools need none at all
+        let (signers, secret_phrases) = match backend {
+            BackendKind::Substrate => {
+                let signers = match load_keys(&cfg) {
+                    Ok(signers) => signers,
+                    Err(e) => {
+                        error!("can't load keys: {}. Shutting down...", e);
+                        process::exit(1);
+                    }
+                };
+                if !cfg.benchmark_cpu()
+                    && !cfg.benchmark_io()
+                    && !check_signers(&drive_id_to_plots, &signers)
+                {
+                    error!("missing keys for plot accounts. Shutting down...");
+                    process::exit(1);
+                }
+                (signers, HashMap::new())
+            }
+            BackendKind::Http => (HashMap::new(), cfg.account_id_to_secret_phrase.clone()),
+        };
+        let checked_signers = if backend == BackendKind::Substrate
+            && !cfg.benchmark_cpu()
+            && !cfg.benchmark_io()
+        {
+            Some(Arc::new(signers.clone()))
+        } else {
+            None
+        };
 
         let cpu_threads = cfg.cpu_threads;
         let cpu_worker_task_count = cfg.cpu_worker_task_count;
@@ -369,6 +859,7 @@ impl Miner {
         }
 
         let (tx_nonce_data, rx_nonce_data) = mpsc::channel(buffer_count);
+        let metrics = Arc::new(Metrics::default());
 
         thread::spawn({
             create_cpu_worker_task(
@@ -377,6 +868,7 @@ impl Miner {
                 rx_read_replies_cpu.clone(),
                 tx_empty_buffers.clone(),
                 tx_nonce_data.clone(),
+                metrics.clone(),
             )
         });
 
@@ -391,6 +883,7 @@ impl Miner {
                         tx_nonce_data.clone(),
                         gpu_contexts[i].clone(),
                         drive_id_to_plots.len(),
+                        metrics.clone(),
                     )
                 });
             } else {
@@ -402,6 +895,7 @@ impl Miner {
                         tx_empty_buffers.clone(),
                         tx_nonce_data.clone(),
                         gpu_contexts[i].clone(),
+                        metrics.clone(),
                     )
                 });
             }
@@ -412,8 +906,26 @@ impl Miner {
         #[cfg(not(feature = "opencl"))]
         let tx_read_replies_gpu = None;
 
+        let journal = match Journal::open(&cfg.journal_path) {
+            Ok(journal) => journal,
+            Err(e) => {
+                error!(
+                    "can't open journal {}: {}. Shutting down...",
+                    cfg.journal_path.display(),
+                    e
+                );
+                process::exit(1);
+            }
+        };
+
+        let mut state = State::new(SubmissionPolicy::new(
+            cfg.submission_policy,
+            cfg.target_deadline,
+            cfg.account_id_to_target_deadline,
+        ));
+        state.set_plots(&drive_id_to_plots, total_size);
+
         Miner {
-            reader_task_count: drive_id_to_plots.len(),
             reader: Reader::new(
                 drive_id_to_plots,
                 total_size,
@@ -426,66 +938,140 @@ impl Miner {
                 cfg.show_drive_stats,
                 cfg.cpu_thread_pinning,
                 cfg.benchmark_cpu(),
+                cfg.benchmark_io(),
+                cfg.hdd_use_io_uring,
             ),
             rx_nonce_data,
-            target_deadline: cfg.target_deadline,
-            account_id_to_target_deadline: cfg.account_id_to_target_deadline,
             request_handler: RequestHandler::new(
                 cfg.url,
-                cfg.account_id_to_secret_phrase,
+                cfg.fallback_urls,
+                signers,
+                secret_phrases,
+                cfg.finalized_heads,
+                cfg.round_schedule,
+                cfg.genesis_hash,
+                // floor at 1s to protect servers
+                max(1000, cfg.get_mining_info_interval),
                 cfg.timeout,
                 (total_size * 4 / 1024 / 1024) as usize,
                 cfg.send_proxy_details,
                 cfg.additional_headers,
+                journal,
+                metrics.clone(),
                 executor.clone(),
             ),
-            state: Arc::new(Mutex::new(State::new())),
-            // floor at 1s to protect servers
-            get_mining_info_interval: max(1000, cfg.get_mining_info_interval),
+            state: Arc::new(Mutex::new(state)),
             executor,
             wakeup_after: cfg.hdd_wakeup_after * 1000, // ms -> s
+            use_direct_io: cfg.hdd_use_direct_io,
+            dummy_plots: cfg.benchmark_cpu(),
+            signers: checked_signers,
+            plot_dirs: cfg.plot_dirs,
+            mmap_plot_dirs: cfg.mmap_plot_dirs,
+            api_address: cfg.api_address,
+            metrics_address: cfg.metrics_address,
+            metrics,
         }
     }
 
-    pub fn run(self) {
+    pub fn run(self) -> MinerHandle {
         let request_handler = self.request_handler.clone();
-        let total_size = self.reader.total_size;
 
         // TODO: this doesn't need to be arc mutex if we manage to separate
         // reader from miner so that we can simply move it
         let reader = Arc::new(Mutex::new(self.reader));
 
+        let handle = MinerHandle {
+            state: self.state.clone(),
+            reader: reader.clone(),
+            request_handler: self.request_handler.clone(),
+            plot_dirs: Arc::new(Mutex::new(self.plot_dirs)),
+            mmap_plot_dirs: self.mmap_plot_dirs,
+            use_direct_io: self.use_direct_io,
+            dummy_plots: self.dummy_plots,
+            signers: self.signers,
+            metrics: self.metrics.clone(),
+        };
+        if let Some(address) = self.api_address {
+            status::serve(address, handle.clone(), &self.executor);
+        }
+        if let Some(address) = self.metrics_address {
+            status::serve_metrics(address, handle.clone(), &self.executor);
+        }
+
         let state = self.state.clone();
+        let metrics = self.metrics.clone();
         // there might be a way to solve this without two nested moves
-        let get_mining_info_interval = self.get_mining_info_interval;
         let wakeup_after = self.wakeup_after;
-        let sleep_duration = Duration::from_millis(get_mining_info_interval - 1000);
-        let interval_duration = Duration::from_millis(1000);
         self.executor.clone().spawn(
-            Interval::new_interval(interval_duration)
+            request_handler
+                .new_heads()
                 .for_each(move |_| {
                     let state = state.clone();
                     let reader = reader.clone();
+                    let journal_handler = request_handler.clone();
+                    let metrics = metrics.clone();
                     request_handler.get_mining_info().then(move |mining_info| {
                         match mining_info {
                             Ok(mining_info) => {
                                 let mut state = state.lock().unwrap();
+                                if state.shutting_down {
+                                    return future::ok(());
+                                }
                                 state.first = false;
+                                let first_round = state.height == 0;
                                 if state.outage {
                                     error!("{: <80}", "outage resolved.");
                                     state.outage = false;
                                 }
-                                if mining_info.generation_signature != state.generation_signature_bytes {
+                                if state.starts_round(&mining_info) {
                                     state.update_mining_info(&mining_info);
+                                    if let Some((drive_id_to_plots, total_size)) =
+                                        state.next_plots.take()
+                                    {
+                                        state.set_plots(&drive_id_to_plots, total_size);
+                                        reader
+                                            .lock()
+                                            .unwrap()
+                                            .replace_plots(drive_id_to_plots, total_size);
+                                        info!("switched to new plots: plots={}", state.plots.len());
+                                    }
 
-                                    reader.lock().unwrap().start_reading(
-                                        mining_info.height,
-                                        state.block,
-                                        mining_info.base_target,
-                                        state.scoop,
-                                        &Arc::new(state.generation_signature_bytes),
-                                    );
+                                    if first_round {
+                                        // replayed deadlines have to be beaten like submitted ones
+                                        let height = state.height;
+                                        for params in
+                                            journal_handler.replay(&mining_info, state.block)
+                                        {
+                                            if mining_info
+                                                .round_schedule
+                                                .same_round(params.height, height)
+                                            {
+                                                state
+                                                    .policy
+                                                    .submitted(params.account_id, params.deadline);
+                                            }
+                                        }
+                                    }
+
+                                    if state.paused {
+                                        state.scanning = false;
+                                    } else {
+                                        start_reading(&mut state, &mut reader.lock().unwrap());
+                                    }
                                     drop(state);
+                                } else if state.scanning && state.window_closed() {
+                                    info!(
+                                        "{: <80}",
+                                        format!(
+                                            "mining window closed: height={}, roundtime={}ms",
+                                            state.height,
+                                            state.round_time()
+                                        )
+                                    );
+                                    reader.lock().unwrap().stop_reading();
+                                    state.scanning = false;
+                                    state.sw.restart();
                                 } else if !state.scanning
                                     && wakeup_after != 0
                                     && state.sw.elapsed_ms() > wakeup_after
@@ -494,16 +1080,21 @@ impl Miner {
                                     reader.lock().unwrap().wakeup();
                                     state.sw.restart();
                                 }
-                                if mining_info.duration_from_last_mining <= 1000 {
-                                    std::thread::sleep(sleep_duration)
-                                }
                             }
-                            _ => {
+                            Err(e @ FetchError::Incompatible(_)) => {
+                                error!("{}. Shutting down...", e);
+                                process::exit(1);
+                            }
+                            Err(e) => {
                                 let mut state = state.lock().unwrap();
+                                metrics.mining_info_error(!state.outage);
                                 if state.first {
                                     error!(
                                         "{: <80}",
-                                        "error getting mining info, please check server config"
+                                        format!(
+                                            "error getting mining info, please check server config: {}",
+                                            e
+                                        )
                                     );
                                     state.first = false;
                                     state.outage = true;
@@ -511,8 +1102,13 @@ impl Miner {
                                     if !state.outage {
                                         error!(
                                             "{: <80}",
-                                            "error getting mining info => connection outage..."
+                                            format!(
+                                                "error getting mining info => connection outage: {}",
+                                                e
+                                            )
                                         );
+                                    } else {
+                                        debug!("error getting mining info: {}", e);
                                     }
                                     state.outage = true;
                                 }
@@ -521,74 +1117,375 @@ impl Miner {
                         future::ok(())
                     })
                 })
-                .map_err(|e| panic!("interval errored: err={:?}", e)),
+                .map_err(|e| panic!("head stream errored: err={:?}", e)),
         );
 
-        let target_deadline = self.target_deadline;
-        let account_id_to_target_deadline = self.account_id_to_target_deadline;
         let request_handler = self.request_handler.clone();
         let state = self.state.clone();
-        let reader_task_count = self.reader_task_count;
+        let metrics = self.metrics;
+        let reader = handle.reader.clone();
+        let verify_deadlines = !self.dummy_plots;
         self.executor.clone().spawn(
             self.rx_nonce_data
                 .for_each(move |nonce_data| {
-                    let mut state = state.lock().unwrap();
                     let deadline = nonce_data.deadline / nonce_data.base_target;
-                    if state.height == nonce_data.height {
-                        let best_deadline = *state
-                            .account_id_to_best_deadline
-                            .get(&nonce_data.account_id)
-                            .unwrap_or(&u64::MAX);
-                        info!("@@@@@@@@@@ best_deadline = {}, deadline = {} @@@@@@@@@", best_deadline, deadline);
-                        info!("~~~~~~~~~~~~~~~~server_target_deadline = {}, accountid_id_dl= {} ~~~~~~~~~~~", state.server_target_deadline, *(account_id_to_target_deadline
-                            .get(&nonce_data.account_id)
-                            .unwrap_or(&target_deadline)));
-                        // if deadline == 0 || (best_deadline > deadline
-                        //     && deadline
-                        //         < min(
-                        //             state.server_target_deadline,
-                        //             *(account_id_to_target_deadline
-                        //                 .get(&nonce_data.account_id)
-                        //                 .unwrap_or(&target_deadline)),
-                        //         ))
-                        // {
-                            state
-                                .account_id_to_best_deadline
-                                .insert(nonce_data.account_id, deadline);
-                            request_handler.submit_nonce(
-                                nonce_data.account_id,
-                                nonce_data.nonce,
-                                nonce_data.height,
-                                nonce_data.block,
-                                nonce_data.deadline,
-                                deadline,
-                                state.generation_signature_bytes,
-                            );
-                        //}
+                    let (decision, scoop, generation_signature_bytes) = {
+                        let state = state.lock().unwrap();
+                        let decision = if !state.is_current(&nonce_data) {
+                            None
+                        } else if state.shutting_down {
+                            Some(Decision::Skip(Reason::ShuttingDown))
+                        } else if state.window_closed() {
+                            Some(Decision::Skip(Reason::WindowClosed))
+                        } else {
+                            Some(state.policy.decide(&nonce_data, deadline))
+                        };
+                        (decision, state.scoop, state.generation_signature_bytes)
+                    };
+
+                    // hashing a whole nonce takes a while, it must neither block the executor
+                    // nor the state
+                    let expected = match decision {
+                        Some(Decision::Submit(_)) if verify_deadlines => {
+                            let (tx_expected, rx_expected) = oneshot::channel();
+                            let account_id = nonce_data.account_id;
+                            let nonce = nonce_data.nonce;
+                            rayon::spawn(move || {
+                                tx_expected
+                                    .send(recompute_deadline(
+                                        account_id,
+                                        nonce,
+                                        scoop,
+                                        &generation_signature_bytes,
+                                    ))
+                                    .ok();
+                            });
+                            let expected = rx_expected
+                                .map(Some)
+                                .map_err(|e| panic!("deadline verification failed: err={:?}", e));
+                            future::Either::A(expected)
+                        }
+                        _ => future::Either::B(future::ok(None)),
+                    };
+
+                    let state = state.clone();
+                    let request_handler = request_handler.clone();
+                    let metrics = metrics.clone();
+                    let reader = reader.clone();
+                    expected.map(move |expected| {
+                        let mut state = state.lock().unwrap();
+                        // the round might have moved on while the deadline was verified
+                        let decision = match decision {
+                            Some(decision) if state.is_current(&nonce_data) => decision,
+                            _ => return,
+                        };
+                        let decision = match expected {
+                            Some(expected) if expected != nonce_data.deadline => {
+                                let plot = plot_name(
+                                    &state.plots,
+                                    nonce_data.account_id,
+                                    nonce_data.nonce,
+                                )
+                                .to_owned();
+                                let corrupt_nonces = state.count_corrupt_nonce(&plot);
+                                error!(
+                                    "corrupt plot data: plot={}, nonce={}, deadline={}, \
+                                     expected={}, corrupt nonces={}",
+                                    plot,
+                                    nonce_data.nonce,
+                                    deadline,
+                                    expected / nonce_data.base_target,
+                                    corrupt_nonces
+                                );
+                                Decision::Skip(Reason::CorruptPlot)
+                            }
+                            _ => decision,
+                        };
+                        match decision {
+                            Decision::Submit(reason) => {
+                                state.policy.submitted(nonce_data.account_id, deadline);
+                                info!(
+                                    "submit: account={}, nonce={}, deadline={}, reason={}",
+                                    nonce_data.account_id, nonce_data.nonce, deadline, reason
+                                );
+                                request_handler.submit_nonce(
+                                    nonce_data.account_id,
+                                    nonce_data.nonce,
+                                    nonce_data.height,
+                                    nonce_data.block,
+                                    nonce_data.deadline,
+                                    deadline,
+                                    state.generation_signature_bytes,
+                                );
+                            }
+                            Decision::Skip(reason) => debug!(
+                                "skip: account={}, nonce={}, deadline={}, reason={}",
+                                nonce_data.account_id, nonce_data.nonce, deadline, reason
+                            ),
+                        }
 
                         if nonce_data.reader_task_processed {
                             state.processed_reader_tasks += 1;
-                            if state.processed_reader_tasks == reader_task_count {
+                            if state.processed_reader_tasks == state.reader_task_count {
                                 info!(
                                     "{: <80}",
                                     format!(
                                         "round finished: roundtime={}ms, speed={:.2}MiB/s",
                                         state.sw.elapsed_ms(),
-                                        total_size as f64 * 1000.0
+                                        state.total_size as f64 * 1000.0
                                             / 1024.0
                                             / 1024.0
                                             / state.sw.elapsed_ms() as f64
                                     )
                                 );
+                                metrics.round_finished(state.sw.elapsed_ms() as u64);
+                                reader
+                                    .lock()
+                                    .unwrap()
+                                    .round_finished(state.sw.elapsed_ms() as u64);
                                 state.sw.restart();
                                 println!("%%%%%%%%%%% finished sw.restart %%%%%%%%%%%");
                                 state.scanning = false;
                             }
                         }
-                    }
-                    Ok(())
+                    })
                 })
                 .map_err(|e| panic!("interval errored: err={:?}", e)),
         );
+        handle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::com::compat::POC_MODULE;
+    use crate::com::mock_node::MockNode;
+    use crate::config::validate_cfg;
+    use crate::plotter::{plot, PlotterTask};
+    use crate::poc_hashing::{
+        calculate_deadline, calculate_scoop, find_best_deadline_rust, noncegen_rust, NONCE_SIZE,
+    };
+    use crate::utils::test_dir;
+    use codec::Encode;
+    use std::fs;
+    use std::net::TcpListener;
+    use std::time::Instant;
+    use sub_runtime::poc::Difficulty;
+    use tokio::runtime::Builder;
+
+    const BASE_TARGET: u64 = 70_312;
+
+    #[test]
+    fn test_verify_deadline() {
+        let mut state = State::new(SubmissionPolicy::new(
+            SubmissionMode::AccountBest,
+            u64::max_value(),
+            HashMap::new(),
+        ));
+        state.height = 1;
+        state.block = 2;
+
+        let deadline = calculate_deadline(1337, 42, 1234, &[3; 32]);
+        assert_eq!(recompute_deadline(1337, 42, 1234, &[3; 32]), deadline);
+        assert_ne!(recompute_deadline(1337, 43, 1234, &[3; 32]), deadline);
+
+        let mut nonce_data = NonceData {
+            height: 1,
+            block: 2,
+            base_target: 1,
+            deadline,
+            nonce: 42,
+            reader_task_processed: false,
+            account_id: 1337,
+        };
+        assert!(state.is_current(&nonce_data));
+        // same height, but computed for the block that got replaced
+        nonce_data.block = 1;
+        assert!(!state.is_current(&nonce_data));
+
+        let plots = vec![Meta {
+            account_id: 1337,
+            start_nonce: 40,
+            nonces: 3,
+            name: "1337_40_3".to_owned(),
+        }];
+        assert_eq!(plot_name(&plots, 1337, 42), "1337_40_3");
+        assert_eq!(plot_name(&plots, 1337, 43), "unknown");
+        assert_eq!(state.count_corrupt_nonce("1337_40_3"), 1);
+        assert_eq!(state.count_corrupt_nonce("1337_40_3"), 2);
+    }
+
+    #[test]
+    fn test_window_closed() {
+        let mut state = State::new(SubmissionPolicy::new(
+            SubmissionMode::AccountBest,
+            u64::max_value(),
+            HashMap::new(),
+        ));
+        let mut mining_info = MiningInfo {
+            generation_signature: [3; 32],
+            base_target: 1,
+            height: 4,
+            target_deadline: u64::max_value(),
+            duration_from_last_mining: 8500,
+            round_schedule: RoundSchedule::new(3, 9000),
+        };
+        state.update_mining_info(&mining_info);
+        assert!(!state.window_closed());
+        // the round started on chain 9.5s before the miner saw it
+        mining_info.duration_from_last_mining = 9500;
+        state.update_mining_info(&mining_info);
+        assert!(state.window_closed());
+        // nothing mined yet, the miner's own clock is all there is
+        mining_info.duration_from_last_mining = u64::max_value();
+        state.update_mining_info(&mining_info);
+        assert!(!state.window_closed());
+    }
+
+    #[test]
+    fn test_mine_against_mock_node() {
+        let node = MockNode::start();
+
+        let dir = test_dir("miner");
+        let plot_dir = dir.join("plots");
+        fs::create_dir_all(&plot_dir).unwrap();
+        let api_address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let metrics_address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        plot(&PlotterTask {
+            account_id: 1337,
+            start_nonce: 0,
+            nonces: 16,
+            output_path: plot_dir.clone(),
+            use_direct_io: false,
+            threads: 2,
+            nonces_per_chunk: 16,
+            show_progress: false,
+        })
+        .unwrap();
+
+        let cfg = format!(
+            "account_id_to_secret_phrase:\n  1337: '//Alice'\n\
+             keystore_path: '{}'\n\
+             plot_dirs: ['{}']\n\
+             url: '{}'\n\
+             hdd_use_direct_io: false\n\
+             cpu_threads: 1\n\
+             cpu_worker_task_count: 1\n\
+             cpu_nonces_per_cache: 16\n\
+             show_progress: false\n\
+             journal_path: '{}'\n\
+             api_address: '{}'\n\
+             metrics_address: '{}'\n",
+            dir.join("keystore").display(),
+            plot_dir.display(),
+            node.url(),
+            dir.join("submissions.jsonl").display(),
+            api_address,
+            metrics_address
+        );
+        let cfg = validate_cfg(serde_yaml::from_str(&cfg).unwrap());
+        // the miner takes the base target of the latest difficulty adjustment
+        let difficulty = Difficulty {
+            base_target: BASE_TARGET,
+            ..Default::default()
+        };
+        node.set_storage(POC_MODULE, "TargetInfo", vec![difficulty].encode());
+
+        let rt = Builder::new().core_threads(1).build().unwrap();
+        let miner = Miner::new(cfg, rt.executor()).run();
+
+        let started = Instant::now();
+        let mining = loop {
+            if let Some(mining) = node.minings().into_iter().find(|m| m.height == 1) {
+                break mining;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(60),
+                "nothing submitted"
+            );
+            thread::sleep(Duration::from_millis(100));
+        };
+
+        let gensig = node.block_hash(1);
+        let scoop = calculate_scoop(1, &gensig) as usize;
+        let mut scoops = Vec::new();
+        let mut nonce_data = vec![0u8; NONCE_SIZE];
+        for nonce in 0..16 {
+            noncegen_rust(1337, nonce, &mut nonce_data);
+            scoops.extend_from_slice(&nonce_data[scoop * 64..scoop * 64 + 64]);
+        }
+        let (deadline, nonce) = find_best_deadline_rust(&scoops, 16, &gensig);
+
+        assert_eq!(mining.account_id, 1337);
+        assert_eq!(mining.sig, gensig);
+        assert_eq!(mining.nonce, nonce);
+        assert_eq!(mining.deadline, deadline / BASE_TARGET);
+
+        let client = reqwest::Client::new();
+        let status = |client: &reqwest::Client| -> serde_json::Value {
+            client
+                .get(&format!("http://{}/status", api_address))
+                .send()
+                .unwrap()
+                .json()
+                .unwrap()
+        };
+        let s = status(&client);
+        assert_eq!(s["plots"], 1);
+        assert_eq!(s["capacity"], 16 * 262_144);
+        assert_eq!(s["paused"], false);
+        assert_eq!(s["drives"].as_object().unwrap().len(), 1);
+        assert!(s["submissions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|r| r["height"] == 1 && r["nonce"] == nonce));
+
+        let post = |path: &str| {
+            client
+                .post(&format!("http://{}/{}", api_address, path))
+                .send()
+                .unwrap()
+                .status()
+        };
+        assert_eq!(post("pause"), reqwest::StatusCode::NO_CONTENT);
+        assert_eq!(status(&client)["paused"], true);
+        assert_eq!(post("resume"), reqwest::StatusCode::NO_CONTENT);
+        assert_eq!(status(&client)["paused"], false);
+        assert_eq!(post("unknown"), reqwest::StatusCode::NOT_FOUND);
+
+        let metrics = client
+            .get(&format!("http://{}/metrics", metrics_address))
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert!(metrics
+            .lines()
+            .any(|l| l == "scavenger_plot_capacity_bytes 4194304"));
+        assert!(metrics
+            .lines()
+            .any(|l| l.starts_with("scavenger_submissions_total{outcome=\"submitted\"} ")
+                && !l.ends_with(" 0")));
+        assert!(metrics
+            .lines()
+            .any(|l| l.starts_with("scavenger_hashed_nonces_total{worker=\"cpu\"} ")));
+
+        let (_tx_abort, rx_abort) = crossbeam_channel::unbounded();
+        assert!(miner.shutdown(Duration::from_secs(30), &rx_abort));
+        assert_eq!(status(&client)["scanning"], false);
+        node.new_block();
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(status(&client)["height"], 1);
+
+        rt.shutdown_now().wait().unwrap();
+        let _ = fs::remove_dir_all(&dir);
     }
 }
@@ -1,17 +1,21 @@
 use crate::utils::get_sector_size;
+use memmap::{Mmap, MmapOptions};
 use rand::prelude::*;
 use std::cmp::{max, min};
 use std::error::Error;
 use std::fs;
 use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "io_uring")]
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
 
-const SCOOPS_IN_NONCE: u64 = 4096;
+pub const SCOOPS_IN_NONCE: u64 = 4096;
 const SHABAL256_HASH_SIZE: u64 = 32;
 pub const SCOOP_SIZE: u64 = SHABAL256_HASH_SIZE * 2;
-const NONCE_SIZE: u64 = SCOOP_SIZE * SCOOPS_IN_NONCE;
+pub const NONCE_SIZE: u64 = SCOOP_SIZE * SCOOPS_IN_NONCE;
 
 #[derive(Clone)]
 pub struct Meta {
@@ -41,19 +45,90 @@ impl Meta {
     }
 }
 
+/// Storage the reader reads scoops from, a plot file or e.g. synthetic plot data in memory.
+pub trait PlotSource {
+    fn meta(&self) -> &Meta;
+    /// Starts reading `scoop` from its first nonce.
+    fn prepare(&mut self, scoop: u32) -> io::Result<()>;
+    /// Reads the next part of `scoop` into `bs`, at most its capacity. Returns the number of
+    /// bytes read, the nonce they start with and whether the scoop is read completely.
+    fn read(&mut self, bs: &mut Vec<u8>, scoop: u32) -> io::Result<(usize, u64, bool)>;
+    /// Touches the storage at random to keep a drive from spinning down.
+    fn wakeup(&mut self) -> io::Result<()>;
+    /// Files the source keeps open once it is read.
+    fn open_files(&self) -> u64 {
+        0
+    }
+    /// Moves on like `read`, but hands out the data without copying it if the source is
+    /// mapped. Returns None otherwise.
+    fn read_mapped(&mut self, _scoop: u32, _cap: u64) -> Option<(MappedChunk, u64, bool)> {
+        None
+    }
+    /// Moves on like `read`, but leaves the reading to the caller, e.g. to keep several reads
+    /// in flight. Sources that can't be read that way return None and are read with `read`.
+    #[cfg(feature = "io_uring")]
+    fn next_chunk(&mut self, _scoop: u32, _cap: u64) -> Option<Chunk> {
+        None
+    }
+}
+
+/// Part of a scoop to read from a file.
+#[cfg(feature = "io_uring")]
+pub struct Chunk {
+    pub fd: RawFd,
+    pub offset: u64,
+    pub len: usize,
+    pub start_nonce: u64,
+    /// The scoop is complete with this chunk.
+    pub finished: bool,
+}
+
 pub struct Plot {
     pub meta: Meta,
     pub path: String,
+    /// Stays open across rounds, reads are positional.
     pub fh: File,
+    // buffered handle for the unaligned head and tail of a scoop with direct io, only opened
+    // for plots whose scoops aren't sector aligned
+    fh_unaligned: Option<File>,
+    // file the handles were opened on, they are reopened once the path points to another one
+    file_id: FileId,
     read_offset: u64,
     use_direct_io: bool,
+    // read through a mapping of the scoop instead of reads into the buffers
+    use_mmap: bool,
+    map: Option<Arc<Mmap>>,
     sector_size: u64,
     dummy: bool,
 }
 
+/// Part of a scoop in a mapped plot file, handed to the CPU workers without copying it.
+#[derive(Clone)]
+pub struct MappedChunk {
+    map: Arc<Mmap>,
+    offset: usize,
+    len: usize,
+}
+
+impl MappedChunk {
+    pub fn as_slice(&self) -> &[u8] {
+        &self.map[self.offset..self.offset + self.len]
+    }
+
+    /// Reads a byte of every page, e.g. to benchmark mapped reads without hashing.
+    pub fn touch(&self) {
+        for b in self.as_slice().iter().step_by(page_size::get()) {
+            unsafe { ptr::read_volatile(b) };
+        }
+    }
+}
+
+// identifies a file and its size, replaced or resized plot files get a new one
+type FileId = (u64, u64, u64);
+
 cfg_if! {
     if #[cfg(unix)] {
-        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::fs::{FileExt, MetadataExt, OpenOptionsExt};
 
         const O_DIRECT: i32 = 0o0_040_000;
 
@@ -70,8 +145,28 @@ cfg_if! {
                 .open(path)
         }
 
+        pub fn open_for_writing_using_direct_io<P: AsRef<Path>>(path: P) -> io::Result<File> {
+            OpenOptions::new()
+                .write(true)
+                .custom_flags(O_DIRECT)
+                .open(path)
+        }
+
+        pub fn open_for_writing<P: AsRef<Path>>(path: P) -> io::Result<File> {
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+        }
+
+        fn read_at(fh: &File, bs: &mut [u8], offset: u64) -> io::Result<()> {
+            fh.read_exact_at(bs, offset)
+        }
+
+        fn file_id(m: &fs::Metadata) -> FileId {
+            (m.dev(), m.ino(), m.len())
+        }
     } else {
-        use std::os::windows::fs::OpenOptionsExt;
+        use std::os::windows::fs::{FileExt, MetadataExt, OpenOptionsExt};
 
         const FILE_FLAG_NO_BUFFERING: u32 = 0x2000_0000;
         const FILE_FLAG_SEQUENTIAL_SCAN: u32 = 0x0800_0000;
@@ -90,11 +185,63 @@ cfg_if! {
                 .custom_flags(FILE_FLAG_SEQUENTIAL_SCAN | FILE_FLAG_RANDOM_ACCESS)
                 .open(path)
         }
+
+        pub fn open_for_writing_using_direct_io<P: AsRef<Path>>(path: P) -> io::Result<File> {
+            OpenOptions::new()
+                .write(true)
+                .custom_flags(FILE_FLAG_NO_BUFFERING)
+                .open(path)
+        }
+
+        pub fn open_for_writing<P: AsRef<Path>>(path: P) -> io::Result<File> {
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+        }
+
+        fn read_at(fh: &File, mut bs: &mut [u8], mut offset: u64) -> io::Result<()> {
+            while !bs.is_empty() {
+                match fh.seek_read(bs, offset) {
+                    Ok(0) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "failed to fill whole buffer",
+                        ))
+                    }
+                    Ok(n) => {
+                        let rest = bs;
+                        bs = &mut rest[n..];
+                        offset += n as u64;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+
+        // no inode numbers in std, a replaced file is told apart by its timestamps
+        fn file_id(m: &fs::Metadata) -> FileId {
+            (m.creation_time(), m.last_write_time(), m.file_size())
+        }
+    }
+}
+
+fn open_plot(path: &str, use_direct_io: bool) -> io::Result<File> {
+    if use_direct_io {
+        open_using_direct_io(path)
+    } else {
+        open(path)
     }
 }
 
 impl Plot {
-    pub fn new(path: &PathBuf, mut use_direct_io: bool, dummy: bool) -> Result<Plot, Box<Error>> {
+    pub fn new(
+        path: &PathBuf,
+        mut use_direct_io: bool,
+        use_mmap: bool,
+        dummy: bool,
+    ) -> Result<Plot, Box<Error>> {
         if !path.is_file() {
             return Err(From::from(format!(
                 "{} is not a file",
@@ -121,11 +268,11 @@ impl Plot {
             )));
         }
 
-        let fh = if use_direct_io {
-            open_using_direct_io(path)?
-        } else {
-            open(path)?
-        };
+        // mappings don't bypass the page cache
+        let use_mmap = use_mmap && !dummy;
+        if use_mmap {
+            use_direct_io = false;
+        }
 
         let plot_file_name = plot_file.to_string();
         let sector_size = get_sector_size(&path.to_str().unwrap().to_owned());
@@ -138,6 +285,8 @@ impl Plot {
         }
 
         let file_path = path.clone().into_os_string().into_string().unwrap();
+        let fh = open_plot(&file_path, use_direct_io)?;
+        let file_id = file_id(&fh.metadata()?);
         Ok(Plot {
             meta: Meta {
                 account_id,
@@ -146,61 +295,114 @@ impl Plot {
                 name: plot_file_name,
             },
             fh,
+            fh_unaligned: None,
+            file_id,
             path: file_path,
             read_offset: 0,
             use_direct_io,
+            use_mmap,
+            map: None,
             sector_size,
             dummy,
         })
     }
 
-    pub fn prepare(&mut self, scoop: u32) -> io::Result<u64> {
-        self.read_offset = 0;
-        let nonces = self.meta.nonces;
-        let mut seek_addr = u64::from(scoop) * nonces as u64 * SCOOP_SIZE;
+    // moves on to the next read of `scoop` with buffers of `cap` bytes: file offset and length
+    // of the read, its first nonce, whether the scoop is complete and if the read is unaligned
+    fn advance(&mut self, scoop: u32, cap: u64) -> (u64, u64, u64, bool, bool) {
+        let size = SCOOP_SIZE * self.meta.nonces;
+        let start_nonce = self.meta.start_nonce + self.read_offset / 64;
+        let addr = u64::from(scoop) * size + self.read_offset;
+
+        let (bytes_to_read, unaligned) = next_read(
+            addr,
+            size - self.read_offset,
+            cap,
+            self.use_direct_io,
+            self.sector_size,
+        );
+        self.read_offset += bytes_to_read;
+        let finished = self.read_offset == size;
+
+        (addr, bytes_to_read, start_nonce, finished, unaligned)
+    }
 
-        // reopening file handles
-        if !self.use_direct_io {
-            self.fh = open(&self.path)?;
-        } else {
-            self.fh = open_using_direct_io(&self.path)?;
-        };
+    // reopens the handles if the file at the path was replaced or resized since they were opened
+    fn check_file(&mut self) -> io::Result<()> {
+        let metadata = fs::metadata(&self.path)?;
+        if file_id(&metadata) == self.file_id {
+            return Ok(());
+        }
 
-        if self.use_direct_io {
-            self.read_offset = self.round_seek_addr(&mut seek_addr);
+        let exp_size = self.meta.nonces * NONCE_SIZE;
+        if metadata.len() != exp_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected plot size {} but got {}", exp_size, metadata.len()),
+            ));
         }
+        let fh = open_plot(&self.path, self.use_direct_io)?;
+        self.file_id = file_id(&fh.metadata()?);
+        self.fh = fh;
+        self.fh_unaligned = None;
+        info!("reopened plot {}: file changed", self.meta.name);
+        Ok(())
+    }
 
-        self.fh.seek(SeekFrom::Start(seek_addr))
+    /// Whether reads of the plot need a buffered handle next to the direct io one.
+    fn reads_unaligned(&self) -> bool {
+        self.use_direct_io && self.meta.nonces * SCOOP_SIZE % self.sector_size != 0
     }
+}
 
-    pub fn read(&mut self, bs: &mut Vec<u8>, scoop: u32) -> Result<(usize, u64, bool), io::Error> {
-        let read_offset = self.read_offset;
-        let buffer_cap = bs.capacity();
-        let start_nonce = self.meta.start_nonce + self.read_offset / 64;
+impl PlotSource for Plot {
+    fn meta(&self) -> &Meta {
+        &self.meta
+    }
 
-        let (bytes_to_read, finished) =
-            if read_offset as usize + buffer_cap >= (SCOOP_SIZE * self.meta.nonces) as usize {
-                let mut bytes_to_read =
-                    (SCOOP_SIZE * self.meta.nonces) as usize - self.read_offset as usize;
-                if self.use_direct_io {
-                    let r = bytes_to_read % self.sector_size as usize;
-                    if r != 0 {
-                        bytes_to_read -= r;
-                    }
-                }
+    fn open_files(&self) -> u64 {
+        if self.reads_unaligned() {
+            2
+        } else {
+            1
+        }
+    }
 
-                (bytes_to_read, true)
-            } else {
-                (buffer_cap as usize, false)
+    fn prepare(&mut self, scoop: u32) -> io::Result<()> {
+        self.read_offset = 0;
+        self.check_file()?;
+        if self.fh_unaligned.is_none() && self.reads_unaligned() {
+            self.fh_unaligned = Some(open(&self.path)?);
+        }
+
+        if self.use_mmap {
+            let nonces = self.meta.nonces;
+            // the previous mapping stays alive until workers are done with its chunks
+            let map = unsafe {
+                MmapOptions::new()
+                    .offset(u64::from(scoop) * nonces * SCOOP_SIZE)
+                    .len((nonces * SCOOP_SIZE) as usize)
+                    .map(&self.fh)?
             };
+            self.map = Some(Arc::new(map));
+        }
+        Ok(())
+    }
 
-        let offset = self.read_offset;
-        let nonces = self.meta.nonces;
-        let seek_addr =
-            SeekFrom::Start(offset as u64 + u64::from(scoop) * nonces as u64 * SCOOP_SIZE);
-        if !self.dummy {
-            self.fh.seek(seek_addr)?;
-            self.fh.read_exact(&mut bs[0..bytes_to_read])?;
+    fn read(&mut self, bs: &mut Vec<u8>, scoop: u32) -> io::Result<(usize, u64, bool)> {
+        let (addr, bytes_to_read, start_nonce, finished, unaligned) =
+            self.advance(scoop, bs.capacity() as u64);
+        let bytes_to_read = bytes_to_read as usize;
+
+        if let Some(ref map) = self.map {
+            let offset = (addr - u64::from(scoop) * SCOOP_SIZE * self.meta.nonces) as usize;
+            bs[..bytes_to_read].copy_from_slice(&map[offset..offset + bytes_to_read]);
+        } else if !self.dummy {
+            let fh = match self.fh_unaligned {
+                Some(ref fh) if unaligned => fh,
+                _ => &self.fh,
+            };
+            read_at(fh, &mut bs[0..bytes_to_read], addr)?;
             // interrupt avoider (not implemented)
             // let read_chunk_size_in_nonces = 65536;
             // for i in (0..bytes_to_read).step_by(read_chunk_size_in_nonces) {
@@ -209,31 +411,302 @@ impl Plot {
             //     )?;
             // }
         }
-        self.read_offset += bytes_to_read as u64;
 
         Ok((bytes_to_read, start_nonce, finished))
     }
 
-    pub fn seek_random(&mut self) -> io::Result<u64> {
+    fn read_mapped(&mut self, scoop: u32, cap: u64) -> Option<(MappedChunk, u64, bool)> {
+        let map = self.map.clone()?;
+        let (addr, len, start_nonce, finished, _) = self.advance(scoop, cap);
+        let chunk = MappedChunk {
+            map,
+            offset: (addr - u64::from(scoop) * SCOOP_SIZE * self.meta.nonces) as usize,
+            len: len as usize,
+        };
+        Some((chunk, start_nonce, finished))
+    }
+
+    #[cfg(feature = "io_uring")]
+    fn next_chunk(&mut self, scoop: u32, cap: u64) -> Option<Chunk> {
+        if self.dummy || self.use_mmap {
+            return None;
+        }
+        let (offset, len, start_nonce, finished, unaligned) = self.advance(scoop, cap);
+        let fh = match self.fh_unaligned {
+            Some(ref fh) if unaligned => fh,
+            _ => &self.fh,
+        };
+        Some(Chunk {
+            fd: fh.as_raw_fd(),
+            offset,
+            len: len as usize,
+            start_nonce,
+            finished,
+        })
+    }
+
+    fn wakeup(&mut self) -> io::Result<()> {
         let mut rng = thread_rng();
         let rand_scoop = rng.gen_range(0, SCOOPS_IN_NONCE);
 
-        let mut seek_addr = rand_scoop as u64 * self.meta.nonces as u64 * SCOOP_SIZE;
-        if self.use_direct_io {
-            self.round_seek_addr(&mut seek_addr);
+        // a single buffered entry, direct io would need an aligned buffer and plots with aligned
+        // scoops have no buffered handle
+        let addr = rand_scoop as u64 * self.meta.nonces as u64 * SCOOP_SIZE;
+        let mut entry = [0u8; SCOOP_SIZE as usize];
+        match self.fh_unaligned {
+            Some(ref fh) => read_at(fh, &mut entry, addr),
+            None if self.use_direct_io => read_at(&open(&self.path)?, &mut entry, addr),
+            None => read_at(&self.fh, &mut entry, addr),
         }
+    }
+}
 
-        self.fh.seek(SeekFrom::Start(seek_addr))
+/// Size of the next read of a scoop at file offset `addr` with `remaining` bytes left in it,
+/// and whether it has to go through a buffered handle.
+///
+/// Direct io needs sector aligned offsets and lengths, but scoops rarely start or end on a sector
+/// boundary. The head of a scoop up to its first boundary and the tail after its last one are
+/// read buffered, everything in between directly.
+fn next_read(
+    addr: u64,
+    remaining: u64,
+    cap: u64,
+    use_direct_io: bool,
+    sector_size: u64,
+) -> (u64, bool) {
+    let bytes_to_read = min(cap, remaining);
+    if !use_direct_io {
+        return (bytes_to_read, false);
     }
+    let head = (sector_size - addr % sector_size) % sector_size;
+    if head != 0 {
+        return (min(head, bytes_to_read), true);
+    }
+    let aligned = bytes_to_read - bytes_to_read % sector_size;
+    if aligned == 0 {
+        (bytes_to_read, true)
+    } else {
+        (aligned, false)
+    }
+}
 
-    fn round_seek_addr(&mut self, seek_addr: &mut u64) -> u64 {
-        let r = *seek_addr % self.sector_size;
-        if r != 0 {
-            let offset = self.sector_size - r;
-            *seek_addr += offset;
-            offset
-        } else {
-            0
+/// Synthetic plot data in memory, every entry of a scoop holds its nonce and the scoop.
+#[cfg(test)]
+pub struct MemoryPlot {
+    meta: Meta,
+    read_offset: u64,
+    /// Upper limit of a single read in bytes.
+    pub max_read: u64,
+    /// Reads fail while set.
+    pub fail: bool,
+    /// `prepare` fails while set, like it does for a resized plot file.
+    pub unprepared: bool,
+}
+
+#[cfg(test)]
+impl MemoryPlot {
+    pub fn new(account_id: u64, start_nonce: u64, nonces: u64) -> MemoryPlot {
+        MemoryPlot {
+            meta: Meta {
+                account_id,
+                start_nonce,
+                nonces,
+                name: format!("{}_{}_{}", account_id, start_nonce, nonces),
+            },
+            read_offset: 0,
+            max_read: u64::max_value(),
+            fail: false,
+            unprepared: false,
+        }
+    }
+
+    /// Entry of `nonce` in `scoop`.
+    pub fn entry(nonce: u64, scoop: u32) -> Vec<u8> {
+        let mut entry = nonce.to_le_bytes().to_vec();
+        entry.extend_from_slice(&scoop.to_le_bytes());
+        entry.resize(SCOOP_SIZE as usize, 0);
+        entry
+    }
+}
+
+/// Writes a sparse plot file to `path`, only the scoops in `scoops` hold data: the entries of
+/// `MemoryPlot` for the nonces from `start_nonce` on.
+#[cfg(test)]
+pub fn write_sparse_plot(path: &Path, start_nonce: u64, nonces: u64, scoops: &[u32]) {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let mut file = File::create(path).unwrap();
+    file.set_len(nonces * NONCE_SIZE).unwrap();
+    for &scoop in scoops {
+        file.seek(SeekFrom::Start(u64::from(scoop) * nonces * SCOOP_SIZE))
+            .unwrap();
+        for nonce in start_nonce..start_nonce + nonces {
+            file.write_all(&MemoryPlot::entry(nonce, scoop)).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+impl PlotSource for MemoryPlot {
+    fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    fn prepare(&mut self, _scoop: u32) -> io::Result<()> {
+        if self.unprepared {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "synthetic resize",
+            ));
+        }
+        self.read_offset = 0;
+        Ok(())
+    }
+
+    fn read(&mut self, bs: &mut Vec<u8>, scoop: u32) -> io::Result<(usize, u64, bool)> {
+        if self.fail {
+            return Err(io::Error::new(io::ErrorKind::Other, "synthetic read error"));
         }
+        let size = SCOOP_SIZE * self.meta.nonces;
+        let cap = min(bs.capacity() as u64, self.max_read);
+        let bytes_to_read = min(cap - cap % SCOOP_SIZE, size - self.read_offset);
+        let start_nonce = self.meta.start_nonce + self.read_offset / SCOOP_SIZE;
+        for (i, entry) in bs[..bytes_to_read as usize]
+            .chunks_mut(SCOOP_SIZE as usize)
+            .enumerate()
+        {
+            entry.copy_from_slice(&MemoryPlot::entry(start_nonce + i as u64, scoop));
+        }
+        self.read_offset += bytes_to_read;
+
+        Ok((
+            bytes_to_read as usize,
+            start_nonce,
+            self.read_offset == size,
+        ))
+    }
+
+    fn wakeup(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    // reads a scoop like the reader does, returns the nonces read and the reads
+    fn read_scoop(plot: &mut Plot, scoop: u32, cap: usize) -> (Vec<u64>, Vec<(u64, u64, bool)>) {
+        let mut nonces = Vec::new();
+        let mut reads = Vec::new();
+        let mut bs = vec![0u8; cap];
+        loop {
+            let addr = u64::from(scoop) * plot.meta.nonces * SCOOP_SIZE + plot.read_offset;
+            let (_, unaligned) = next_read(
+                addr,
+                plot.meta.nonces * SCOOP_SIZE - plot.read_offset,
+                cap as u64,
+                plot.use_direct_io,
+                plot.sector_size,
+            );
+            let (len, start_nonce, finished) = plot.read(&mut bs, scoop).unwrap();
+            assert!(len > 0);
+            reads.push((addr, len as u64, unaligned));
+            for (i, entry) in bs[..len].chunks(SCOOP_SIZE as usize).enumerate() {
+                let nonce = start_nonce + i as u64;
+                assert_eq!(entry, &MemoryPlot::entry(nonce, scoop)[..]);
+                nonces.push(nonce);
+            }
+            if finished {
+                return (nonces, reads);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_unaligned_direct_io() {
+        let sector_size = 512;
+        let cap = 1024;
+        let scoops = [0u32, 1, 2, 7, 4095];
+        let dir = test_dir("read_unaligned");
+        for &nonces in &[8u64, 13, 21, 77] {
+            let path = dir.join(format!("1337_100_{}", nonces));
+            write_sparse_plot(&path, 100, nonces, &scoops);
+
+            // the file system of the test might not support direct io, so the plot is opened
+            // buffered, the reads are checked for alignment instead
+            let mut plot = Plot {
+                meta: Meta {
+                    account_id: 1337,
+                    start_nonce: 100,
+                    nonces,
+                    name: format!("1337_100_{}", nonces),
+                },
+                path: path.to_str().unwrap().to_owned(),
+                fh: open(&path).unwrap(),
+                fh_unaligned: None,
+                file_id: file_id(&fs::metadata(&path).unwrap()),
+                read_offset: 0,
+                use_direct_io: true,
+                use_mmap: false,
+                map: None,
+                sector_size,
+                dummy: false,
+            };
+            for &scoop in &scoops {
+                plot.prepare(scoop).unwrap();
+                assert_eq!(plot.fh_unaligned.is_some(), nonces % 8 != 0);
+                let (read, reads) = read_scoop(&mut plot, scoop, cap);
+                let expected: Vec<u64> = (100..100 + nonces).collect();
+                assert_eq!(read, expected, "nonces={}, scoop={}", nonces, scoop);
+                for (addr, len, unaligned) in reads {
+                    if !unaligned {
+                        assert_eq!(addr % sector_size, 0);
+                        assert_eq!(len % sector_size, 0);
+                    }
+                }
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reopen_changed_file() {
+        let dir = test_dir("reopen");
+        let path = dir.join("1337_0_4");
+        // scoop 0 of a sparse plot, nonces start at `first` to tell files apart
+        let write = |path: &Path, first: u64| write_sparse_plot(path, first, 4, &[0]);
+        let mut bs = vec![0u8; 4 * SCOOP_SIZE as usize];
+        let mut first_nonce = |plot: &mut Plot| -> io::Result<u64> {
+            plot.prepare(0)?;
+            plot.read(&mut bs, 0)?;
+            let mut nonce = [0u8; 8];
+            nonce.copy_from_slice(&bs[..8]);
+            Ok(u64::from_le_bytes(nonce))
+        };
+
+        write(&path, 0);
+        let mut plot = Plot::new(&path, false, false, false).unwrap();
+        assert_eq!(first_nonce(&mut plot).unwrap(), 0);
+
+        // the handle still points to the old file, it has to be reopened
+        let replacement = dir.join("replacement");
+        write(&replacement, 50);
+        fs::rename(&replacement, &path).unwrap();
+        assert_eq!(first_nonce(&mut plot).unwrap(), 50);
+
+        File::create(&path).unwrap().set_len(NONCE_SIZE).unwrap();
+        let e = first_nonce(&mut plot).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_file(&path).unwrap();
+        let e = first_nonce(&mut plot).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+        write(&path, 70);
+        assert_eq!(first_nonce(&mut plot).unwrap(), 70);
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
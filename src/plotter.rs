@@ -0,0 +1,341 @@
+use crate::miner::{Buffer, CpuBuffer};
+use crate::plot::{
+    open_for_writing, open_for_writing_using_direct_io, NONCE_SIZE, SCOOPS_IN_NONCE, SCOOP_SIZE,
+};
+use crate::poc_hashing::noncegen_rust;
+use crate::utils::{get_sector_size, new_thread_pool};
+#[cfg(any(feature = "simd", feature = "neon"))]
+use libc::{c_void, uint64_t};
+use pbr::{ProgressBar, Units};
+use rayon::prelude::*;
+use std::cmp::min;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use stopwatch::Stopwatch;
+
+cfg_if! {
+    if #[cfg(feature = "simd")] {
+        extern "C" {
+            pub fn noncegen_avx512f(
+                cache: *mut c_void,
+                numeric_id: uint64_t,
+                start_nonce: uint64_t,
+                nonces: uint64_t,
+            ) -> ();
+
+            pub fn noncegen_avx2(
+                cache: *mut c_void,
+                numeric_id: uint64_t,
+                start_nonce: uint64_t,
+                nonces: uint64_t,
+            ) -> ();
+
+            pub fn noncegen_avx(
+                cache: *mut c_void,
+                numeric_id: uint64_t,
+                start_nonce: uint64_t,
+                nonces: uint64_t,
+            ) -> ();
+
+            pub fn noncegen_sse2(
+                cache: *mut c_void,
+                numeric_id: uint64_t,
+                start_nonce: uint64_t,
+                nonces: uint64_t,
+            ) -> ();
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "neon")] {
+        extern "C" {
+            pub fn noncegen_neon(
+                cache: *mut c_void,
+                numeric_id: uint64_t,
+                start_nonce: uint64_t,
+                nonces: uint64_t,
+            ) -> ();
+        }
+    }
+}
+
+// nonces generated per call, a multiple of the lanes of every SIMD kernel
+pub const NONCES_PER_CALL: usize = 16;
+
+/// Everything needed to write a single PoC2 plot file.
+pub struct PlotterTask {
+    pub account_id: u64,
+    pub start_nonce: u64,
+    pub nonces: u64,
+    pub output_path: PathBuf,
+    pub use_direct_io: bool,
+    pub threads: usize,
+    pub nonces_per_chunk: u64,
+    pub show_progress: bool,
+}
+
+/// Generates the plot described by `task` and returns the path of the written file.
+///
+/// The file is named `{account_id}_{start_nonce}_{nonces}` so that the miner picks it up
+/// without renaming. Nonces are generated chunk wise and written scoop by scoop, so every
+/// write lands at the same offsets `Plot::read` uses later on.
+pub fn plot(task: &PlotterTask) -> Result<PathBuf, Box<Error>> {
+    if !task.output_path.is_dir() {
+        return Err(From::from(format!(
+            "{} is not a directory",
+            task.output_path.to_str().unwrap()
+        )));
+    }
+
+    let mut nonces = task.nonces;
+    let mut nonces_per_chunk = task.nonces_per_chunk;
+    let mut use_direct_io = task.use_direct_io;
+
+    // the sector size is determined from the file itself, so we create a placeholder first
+    let probe = task.output_path.join(format!(
+        "{}_{}_{}.tmp",
+        task.account_id, task.start_nonce, task.nonces
+    ));
+    OpenOptions::new().write(true).create_new(true).open(&probe)?;
+    let sector_size = get_sector_size(&probe.to_str().unwrap().to_owned());
+    std::fs::remove_file(&probe)?;
+
+    if use_direct_io {
+        // each scoop of a chunk must start and end on a sector boundary
+        let nonces_per_sector = sector_size / SCOOP_SIZE;
+        if nonces < nonces_per_sector {
+            warn!(
+                "not enough nonces for using direct io: nonces={}, sector size={}",
+                nonces, sector_size
+            );
+            use_direct_io = false;
+        } else {
+            if nonces % nonces_per_sector != 0 {
+                nonces -= nonces % nonces_per_sector;
+                warn!(
+                    "nonces rounded down to a multiple of {} for direct io: nonces={}",
+                    nonces_per_sector, nonces
+                );
+            }
+            nonces_per_chunk = nonces_per_chunk / nonces_per_sector * nonces_per_sector;
+            if nonces_per_chunk == 0 {
+                nonces_per_chunk = nonces_per_sector;
+            }
+        }
+    }
+    if nonces == 0 || nonces_per_chunk == 0 {
+        return Err(From::from("nothing to plot: nonces and nonces per chunk must be > 0"));
+    }
+
+    let file_name = format!("{}_{}_{}", task.account_id, task.start_nonce, nonces);
+    let path = task.output_path.join(&file_name);
+    let fh = OpenOptions::new().write(true).create_new(true).open(&path)?;
+    fh.set_len(nonces * NONCE_SIZE)?;
+    drop(fh);
+
+    let mut fh = if use_direct_io {
+        open_for_writing_using_direct_io(&path)?
+    } else {
+        open_for_writing(&path)?
+    };
+
+    info!(
+        "plotting: file={}, nonces={}, size={:.4} GiB, direct io={}",
+        file_name,
+        nonces,
+        (nonces * NONCE_SIZE) as f64 / 1024.0 / 1024.0 / 1024.0,
+        use_direct_io
+    );
+
+    let chunk_size = min(nonces_per_chunk, nonces) as usize;
+    let pool = new_thread_pool(task.threads, false);
+    let mut nonce_data = vec![0u8; chunk_size * NONCE_SIZE as usize];
+    let mut buffer = CpuBuffer::new(chunk_size * NONCE_SIZE as usize);
+    let scoop_buffer = buffer.get_buffer_for_writing();
+    let mut scoop_buffer = scoop_buffer.lock().unwrap();
+    let scoop_data = &mut scoop_buffer[..];
+
+    let mut pb = if task.show_progress {
+        let mut pb = ProgressBar::new(nonces * NONCE_SIZE);
+        pb.format("│██░│");
+        pb.set_width(Some(80));
+        pb.set_units(Units::Bytes);
+        pb.message("Plotting: ");
+        Some(pb)
+    } else {
+        None
+    };
+
+    let sw = Stopwatch::start_new();
+    let mut offset = 0u64;
+    while offset < nonces {
+        let chunk_nonces = min(chunk_size as u64, nonces - offset) as usize;
+        let start_nonce = task.start_nonce + offset;
+        let chunk_bytes = chunk_nonces * SCOOP_SIZE as usize;
+
+        pool.install(|| {
+            nonce_data[..chunk_nonces * NONCE_SIZE as usize]
+                .par_chunks_mut(NONCES_PER_CALL * NONCE_SIZE as usize)
+                .enumerate()
+                .for_each(|(i, cache)| {
+                    noncegen(
+                        task.account_id,
+                        start_nonce + (i * NONCES_PER_CALL) as u64,
+                        cache.len() as u64 / NONCE_SIZE,
+                        cache,
+                    )
+                });
+
+            // nonce-major to scoop-major
+            let nonce_data = &nonce_data[..];
+            scoop_data[..SCOOPS_IN_NONCE as usize * chunk_bytes]
+                .par_chunks_mut(chunk_bytes)
+                .enumerate()
+                .for_each(|(scoop, scoop_chunk)| {
+                    for (i, dst) in scoop_chunk.chunks_mut(SCOOP_SIZE as usize).enumerate() {
+                        let src = i * NONCE_SIZE as usize + scoop * SCOOP_SIZE as usize;
+                        dst.clone_from_slice(&nonce_data[src..src + SCOOP_SIZE as usize]);
+                    }
+                });
+        });
+
+        for scoop in 0..SCOOPS_IN_NONCE {
+            let seek_addr = scoop * nonces * SCOOP_SIZE + offset * SCOOP_SIZE;
+            fh.seek(SeekFrom::Start(seek_addr))?;
+            let start = scoop as usize * chunk_bytes;
+            fh.write_all(&scoop_data[start..start + chunk_bytes])?;
+        }
+
+        offset += chunk_nonces as u64;
+        if let Some(pb) = &mut pb {
+            pb.add(chunk_nonces as u64 * NONCE_SIZE);
+        }
+    }
+    fh.sync_all()?;
+
+    if let Some(pb) = &mut pb {
+        pb.finish();
+    }
+    info!(
+        "{: <80}",
+        format!(
+            "plot finished: file={}, time={}ms, speed={:.0} nonces/min",
+            file_name,
+            sw.elapsed_ms(),
+            nonces as f64 * 60_000.0 / (sw.elapsed_ms() + 1) as f64
+        )
+    );
+
+    Ok(path)
+}
+
+/// Generates the nonces `start_nonce..start_nonce + nonces` into `cache` one after another, with
+/// the SIMD kernels if the cpu supports them.
+pub fn noncegen(numeric_id: u64, start_nonce: u64, nonces: u64, cache: &mut [u8]) {
+    let cache = &mut cache[..nonces as usize * NONCE_SIZE as usize];
+
+    #[cfg(feature = "simd")]
+    unsafe {
+        let ptr = cache.as_mut_ptr() as *mut c_void;
+        if is_x86_feature_detected!("avx512f") {
+            noncegen_avx512f(ptr, numeric_id, start_nonce, nonces);
+            return;
+        } else if is_x86_feature_detected!("avx2") {
+            noncegen_avx2(ptr, numeric_id, start_nonce, nonces);
+            return;
+        } else if is_x86_feature_detected!("avx") {
+            noncegen_avx(ptr, numeric_id, start_nonce, nonces);
+            return;
+        } else if is_x86_feature_detected!("sse2") {
+            noncegen_sse2(ptr, numeric_id, start_nonce, nonces);
+            return;
+        }
+    }
+
+    #[cfg(feature = "neon")]
+    unsafe {
+        #[cfg(target_arch = "arm")]
+        let neon = is_arm_feature_detected!("neon");
+        #[cfg(target_arch = "aarch64")]
+        let neon = true;
+        if neon {
+            noncegen_neon(
+                cache.as_mut_ptr() as *mut c_void,
+                numeric_id,
+                start_nonce,
+                nonces,
+            );
+            return;
+        }
+    }
+
+    for (i, nonce) in cache.chunks_mut(NONCE_SIZE as usize).enumerate() {
+        noncegen_rust(numeric_id, start_nonce + i as u64, nonce);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plot::Plot;
+    use crate::utils::test_dir;
+    use std::fs;
+    use std::io::{Read, SeekFrom};
+
+    #[test]
+    fn test_plot() {
+        let output_path = test_dir("plot");
+
+        let task = PlotterTask {
+            account_id: 1337,
+            start_nonce: 42,
+            nonces: 3,
+            output_path: output_path.clone(),
+            use_direct_io: false,
+            threads: 2,
+            nonces_per_chunk: 2,
+            show_progress: false,
+        };
+        let path = plot(&task).unwrap();
+        assert_eq!(path, output_path.join("1337_42_3"));
+
+        let p = Plot::new(&path, false, false, false).unwrap();
+        assert_eq!(p.meta.account_id, 1337);
+        assert_eq!(p.meta.start_nonce, 42);
+        assert_eq!(p.meta.nonces, 3);
+
+        let mut expected = vec![0u8; NONCE_SIZE as usize];
+        noncegen_rust(1337, 43, &mut expected);
+        let mut fh = fs::File::open(&path).unwrap();
+        for &scoop in &[0u64, 1, 2047, 4095] {
+            let mut scoop_data = [0u8; SCOOP_SIZE as usize];
+            fh.seek(SeekFrom::Start(scoop * 3 * SCOOP_SIZE + SCOOP_SIZE))
+                .unwrap();
+            fh.read_exact(&mut scoop_data).unwrap();
+            let start = (scoop * SCOOP_SIZE) as usize;
+            assert_eq!(
+                &scoop_data[..],
+                &expected[start..start + SCOOP_SIZE as usize]
+            );
+        }
+
+        fs::remove_dir_all(&output_path).unwrap();
+    }
+
+    #[test]
+    fn test_noncegen() {
+        // more nonces than the lanes of any kernel, the rest is generated one by one
+        let nonces = 19;
+        let mut cache = vec![0u8; nonces * NONCE_SIZE as usize];
+        noncegen(1337, 42, nonces as u64, &mut cache);
+
+        let mut expected = vec![0u8; NONCE_SIZE as usize];
+        for (i, nonce) in cache.chunks(NONCE_SIZE as usize).enumerate() {
+            noncegen_rust(1337, 42 + i as u64, &mut expected);
+            assert!(nonce == &expected[..], "nonce {}", 42 + i);
+        }
+    }
+}
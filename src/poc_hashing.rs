@@ -1,9 +1,14 @@
-use crate::shabal256::{shabal256_deadline_fast, shabal256_hash_fast};
+use crate::shabal256::{shabal256_deadline_fast, shabal256_hash, shabal256_hash_fast};
 use hex;
+use std::cmp::min;
 use std::mem::transmute;
 use std::u64;
 
+const HASH_SIZE: usize = 32;
+const HASH_CAP: usize = 4096;
+const NUM_SCOOPS: usize = 4096;
 const SCOOP_SIZE: usize = 64;
+pub const NONCE_SIZE: usize = NUM_SCOOPS * SCOOP_SIZE;
 
 pub fn calculate_scoop(height: u64, gensig: &[u8; 32]) -> u32 {
     let mut data: [u8; 64] = [0; 64];
@@ -35,3 +40,42 @@ pub fn find_best_deadline_rust(
     }
     (best_deadline, best_offset as u64)
 }
+
+/// Generates a single nonce and writes it into `nonce_data` in PoC2 layout, i.e. the
+/// second hash of every scoop is swapped with the one of its mirror scoop.
+pub fn noncegen_rust(numeric_id: u64, nonce: u64, nonce_data: &mut [u8]) {
+    let mut gendata = vec![0u8; NONCE_SIZE + 16];
+    gendata[NONCE_SIZE..NONCE_SIZE + 8].clone_from_slice(&numeric_id.to_be_bytes());
+    gendata[NONCE_SIZE + 8..].clone_from_slice(&nonce.to_be_bytes());
+
+    let mut i = NONCE_SIZE;
+    while i > 0 {
+        let len = min(NONCE_SIZE + 16 - i, HASH_CAP);
+        let hash = shabal256_hash(&gendata[i..i + len]);
+        gendata[i - HASH_SIZE..i].clone_from_slice(&hash);
+        i -= HASH_SIZE;
+    }
+
+    let final_hash = shabal256_hash(&gendata);
+    for (i, byte) in gendata[..NONCE_SIZE].iter_mut().enumerate() {
+        *byte ^= final_hash[i % HASH_SIZE];
+    }
+
+    for scoop in 0..NUM_SCOOPS {
+        let mirror = NUM_SCOOPS - 1 - scoop;
+        let dst = &mut nonce_data[scoop * SCOOP_SIZE..(scoop + 1) * SCOOP_SIZE];
+        dst[..HASH_SIZE]
+            .clone_from_slice(&gendata[scoop * SCOOP_SIZE..scoop * SCOOP_SIZE + HASH_SIZE]);
+        dst[HASH_SIZE..].clone_from_slice(
+            &gendata[mirror * SCOOP_SIZE + HASH_SIZE..(mirror + 1) * SCOOP_SIZE],
+        );
+    }
+}
+
+/// Unadjusted deadline of a nonce computed from scratch, independent of any plot data.
+pub fn calculate_deadline(numeric_id: u64, nonce: u64, scoop: u32, gensig: &[u8; 32]) -> u64 {
+    let mut nonce_data = vec![0u8; NONCE_SIZE];
+    noncegen_rust(numeric_id, nonce, &mut nonce_data);
+    let start = scoop as usize * SCOOP_SIZE;
+    find_best_deadline_rust(&nonce_data[start..start + SCOOP_SIZE], 1, gensig).0
+}
@@ -1,17 +1,23 @@
 use crate::miner::Buffer;
 #[cfg(feature = "opencl")]
 use crate::miner::CpuBuffer;
-use crate::plot::{Meta, Plot};
+use crate::plot::{MappedChunk, Meta, PlotSource};
+#[cfg(feature = "io_uring")]
+use crate::uring::{self, create_read_task as create_uring_read_task};
 use crate::utils::new_thread_pool;
 use crossbeam_channel;
 use crossbeam_channel::{Receiver, Sender};
 use pbr::{ProgressBar, Units};
 use rayon::prelude::*;
+use std::cmp::max;
 use std::collections::HashMap;
 use std::io::Stdout;
 use std::sync::{Arc, Mutex};
 use stopwatch::Stopwatch;
 
+/// Plots of a drive, read one after another.
+pub type DrivePlots = Arc<Vec<Mutex<Box<PlotSource + Send>>>>;
+
 pub struct BufferInfo {
     pub len: usize,
     pub height: u64,
@@ -22,7 +28,35 @@ pub struct BufferInfo {
     pub finished: bool,
     pub account_id: u64,
     pub gpu_signal: u64,
+    /// Data of mapped plots, the buffer is left untouched then.
+    pub mapped: Option<MappedChunk>,
+}
+/// Reading progress of a drive in the current round.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DriveProgress {
+    /// Bytes read per round, one scoop of every nonce on the drive.
+    pub size: u64,
+    pub bytes_read: u64,
+    /// Time spent reading in ms.
+    pub read_ms: u64,
+    pub finished: bool,
+    /// Bytes read in all rounds.
+    pub total_bytes_read: u64,
+    /// Time spent reading in all rounds in ms.
+    pub total_read_ms: u64,
 }
+
+impl DriveProgress {
+    /// Read speed in the current round in bytes/s.
+    pub fn speed(&self) -> u64 {
+        if self.read_ms == 0 {
+            0
+        } else {
+            self.bytes_read * 1000 / self.read_ms
+        }
+    }
+}
+
 pub struct ReadReply {
     pub buffer: Box<Buffer + Send>,
     pub info: BufferInfo,
@@ -30,7 +64,7 @@ pub struct ReadReply {
 
 #[allow(dead_code)]
 pub struct Reader {
-    drive_id_to_plots: HashMap<String, Arc<Vec<Mutex<Plot>>>>,
+    drive_id_to_plots: HashMap<String, DrivePlots>,
     pub total_size: u64,
     pool: rayon::ThreadPool,
     rx_empty_buffers: Receiver<Box<Buffer + Send>>,
@@ -38,13 +72,19 @@ pub struct Reader {
     tx_read_replies_cpu: Sender<ReadReply>,
     tx_read_replies_gpu: Option<Vec<Sender<ReadReply>>>,
     interupts: Vec<Sender<()>>,
+    progress: Arc<Mutex<HashMap<String, DriveProgress>>>,
     show_progress: bool,
     show_drive_stats: bool,
+    // reads of the current round use io_uring
+    use_io_uring: bool,
+    // total bytes and ms of the finished rounds of each read backend, the I/O benchmark
+    // alternates between them
+    io_benchmark: Option<HashMap<&'static str, (u64, u64)>>,
 }
 
 impl Reader {
     pub fn new(
-        drive_id_to_plots: HashMap<String, Arc<Vec<Mutex<Plot>>>>,
+        drive_id_to_plots: HashMap<String, DrivePlots>,
         total_size: u64,
         num_threads: usize,
         rx_empty_buffers: Receiver<Box<Buffer + Send>>,
@@ -55,12 +95,33 @@ impl Reader {
         show_drive_stats: bool,
         thread_pinning: bool,
         benchmark: bool,
+        benchmark_io: bool,
+        use_io_uring: bool,
     ) -> Reader {
         if !benchmark {
             check_overlap(&drive_id_to_plots);
         }
 
+        #[cfg(feature = "io_uring")]
+        let use_io_uring = use_io_uring
+            && match uring::probe() {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("io_uring isn't available: {} -> using blocking reads", e);
+                    false
+                }
+            };
+        info!(
+            "reader: {}",
+            if use_io_uring {
+                "io_uring"
+            } else {
+                "blocking reads"
+            }
+        );
+
         Reader {
+            progress: Arc::new(Mutex::new(new_progress(&drive_id_to_plots))),
             drive_id_to_plots,
             total_size,
             pool: new_thread_pool(num_threads, thread_pinning),
@@ -71,6 +132,12 @@ impl Reader {
             interupts: Vec::new(),
             show_progress,
             show_drive_stats,
+            use_io_uring,
+            io_benchmark: if benchmark_io && use_io_uring {
+                Some(HashMap::new())
+            } else {
+                None
+            },
         }
     }
 
@@ -82,8 +149,21 @@ impl Reader {
         scoop: u32,
         gensig: &Arc<[u8; 32]>,
     ) {
-        for interupt in &self.interupts {
-            interupt.send(()).ok();
+        self.stop_reading();
+        if self.io_benchmark.is_some() {
+            self.use_io_uring = !self.use_io_uring;
+            info!(
+                "{: <80}",
+                format!("I/O benchmark: round with {}", self.backend())
+            );
+        }
+        for progress in self.progress.lock().unwrap().values_mut() {
+            *progress = DriveProgress {
+                size: progress.size,
+                total_bytes_read: progress.total_bytes_read,
+                total_read_ms: progress.total_read_ms,
+                ..Default::default()
+            };
         }
         let mut pb = ProgressBar::new(self.total_size);
         pb.format("│██░│");
@@ -108,6 +188,7 @@ impl Reader {
                         finished: false,
                         account_id: 0,
                         gpu_signal: 1,
+                        mapped: None,
                     },
                 })
                 .expect("Error sending 'round start' signal to GPU");
@@ -116,31 +197,34 @@ impl Reader {
             .drive_id_to_plots
             .iter()
             .map(|(drive, plots)| {
-                let (interupt, task) = if self.show_progress {
-                    self.create_read_task(
-                        Some(pb.clone()),
-                        drive.clone(),
-                        plots.clone(),
-                        height,
-                        block,
-                        base_target,
-                        scoop,
-                        gensig.clone(),
-                        self.show_drive_stats,
-                    )
+                let pb = if self.show_progress {
+                    Some(pb.clone())
                 } else {
-                    self.create_read_task(
-                        None,
-                        drive.clone(),
-                        plots.clone(),
-                        height,
-                        block,
-                        base_target,
-                        scoop,
-                        gensig.clone(),
-                        self.show_drive_stats,
-                    )
+                    None
                 };
+                let delivery = self.delivery(
+                    pb,
+                    drive.clone(),
+                    height,
+                    block,
+                    base_target,
+                    gensig.clone(),
+                );
+                #[cfg(feature = "io_uring")]
+                {
+                    if self.use_io_uring {
+                        let (interupt, task) = create_uring_read_task(
+                            delivery,
+                            plots.clone(),
+                            scoop,
+                            self.rx_empty_buffers.clone(),
+                            self.tx_empty_buffers.clone(),
+                        );
+                        self.pool.spawn(task);
+                        return interupt;
+                    }
+                }
+                let (interupt, task) = self.create_read_task(delivery, plots.clone(), scoop);
 
                 self.pool.spawn(task);
                 interupt
@@ -148,204 +232,366 @@ impl Reader {
             .collect();
     }
 
+    /// Interrupts the read tasks of the current round.
+    pub fn stop_reading(&mut self) {
+        for interupt in self.interupts.drain(..) {
+            interupt.send(()).ok();
+        }
+    }
+
+    /// Replaces the plots, e.g. after a rescan of the plot dirs. Reading is stopped.
+    pub fn replace_plots(
+        &mut self,
+        drive_id_to_plots: HashMap<String, DrivePlots>,
+        total_size: u64,
+    ) {
+        self.stop_reading();
+        let mut progress = self.progress.lock().unwrap();
+        let mut replaced = new_progress(&drive_id_to_plots);
+        // totals are counters, keep them for drives that are still there
+        for (drive, p) in replaced.iter_mut() {
+            if let Some(old) = progress.get(drive) {
+                p.total_bytes_read = old.total_bytes_read;
+                p.total_read_ms = old.total_read_ms;
+            }
+        }
+        *progress = replaced;
+        drop(progress);
+        self.drive_id_to_plots = drive_id_to_plots;
+        self.total_size = total_size;
+    }
+
+    /// Logs the speed of both read backends in the I/O benchmark once a round is finished.
+    pub fn round_finished(&mut self, round_ms: u64) {
+        let backend = self.backend();
+        let total_size = self.total_size;
+        if let Some(io_benchmark) = self.io_benchmark.as_mut() {
+            let (bytes, ms) = io_benchmark.entry(backend).or_insert((0, 0));
+            *bytes += total_size;
+            *ms += round_ms;
+            let speeds: Vec<String> = ["io_uring", "blocking reads"]
+                .iter()
+                .filter_map(|backend| {
+                    io_benchmark.get(backend).map(|&(bytes, ms)| {
+                        let speed = bytes as f64 * 1000.0 / 1024.0 / 1024.0 / max(ms, 1) as f64;
+                        format!("{}={:.2}MiB/s", backend, speed)
+                    })
+                })
+                .collect();
+            info!("{: <80}", format!("I/O benchmark: {}", speeds.join(", ")));
+        }
+    }
+
+    fn backend(&self) -> &'static str {
+        if self.use_io_uring {
+            "io_uring"
+        } else {
+            "blocking reads"
+        }
+    }
+
+    /// Files kept open by the plots.
+    pub fn open_files(&self) -> u64 {
+        count_open_files(&self.drive_id_to_plots)
+    }
+
+    /// Progress of every drive in the current round.
+    pub fn progress(&self) -> HashMap<String, DriveProgress> {
+        self.progress.lock().unwrap().clone()
+    }
+
     pub fn wakeup(&mut self) {
         for plots in self.drive_id_to_plots.values() {
             let plots = plots.clone();
             self.pool.spawn(move || {
                 let mut p = plots[0].lock().unwrap();
 
-                if let Err(e) = p.seek_random() {
+                if let Err(e) = p.wakeup() {
                     error!(
                         "wakeup: error during wakeup {}: {} -> skip one round",
-                        p.meta.name, e
+                        p.meta().name,
+                        e
                     );
                 }
             });
         }
     }
 
-    fn create_read_task(
+    // hands the buffers of a drive read in this round on to the workers
+    fn delivery(
         &self,
         pb: Option<Arc<Mutex<pbr::ProgressBar<Stdout>>>>,
         drive: String,
-        plots: Arc<Vec<Mutex<Plot>>>,
         height: u64,
         block: u64,
         base_target: u64,
-        scoop: u32,
         gensig: Arc<[u8; 32]>,
-        show_drive_stats: bool,
+    ) -> Delivery {
+        Delivery {
+            drive,
+            height,
+            block,
+            base_target,
+            gensig,
+            tx_read_replies_cpu: self.tx_read_replies_cpu.clone(),
+            #[cfg(feature = "opencl")]
+            tx_read_replies_gpu: self.tx_read_replies_gpu.clone(),
+            progress: self.progress.clone(),
+            pb,
+            show_drive_stats: self.show_drive_stats,
+            elapsed: 0,
+            nonces_processed: 0,
+        }
+    }
+
+    fn create_read_task(
+        &self,
+        mut delivery: Delivery,
+        plots: DrivePlots,
+        scoop: u32,
     ) -> (Sender<()>, impl FnOnce()) {
         let (tx_interupt, rx_interupt) = crossbeam_channel::unbounded();
         let rx_empty_buffers = self.rx_empty_buffers.clone();
         let tx_empty_buffers = self.tx_empty_buffers.clone();
-        let tx_read_replies_cpu = self.tx_read_replies_cpu.clone();
-        #[cfg(feature = "opencl")]
-        let tx_read_replies_gpu = self.tx_read_replies_gpu.clone();
 
         (tx_interupt, move || {
             let mut sw = Stopwatch::new();
-            let mut elapsed = 0i64;
-            let mut nonces_processed = 0u64;
             let plot_count = plots.len();
+            let mut finished = false;
             'outer: for (i_p, p) in plots.iter().enumerate() {
                 let mut p = p.lock().unwrap();
                 if let Err(e) = p.prepare(scoop) {
                     error!(
                         "reader: error preparing {} for reading: {} -> skip one round",
-                        p.meta.name, e
+                        p.meta().name,
+                        e
                     );
                     continue 'outer;
                 }
 
                 'inner: for mut buffer in rx_empty_buffers.clone() {
-                    if show_drive_stats {
-                        sw.restart();
-                    }
-                    let mut_bs = buffer.get_buffer_for_writing();
-                    let mut bs = mut_bs.lock().unwrap();
-                    let (bytes_read, start_nonce, next_plot) = match p.read(&mut bs, scoop) {
-                        Ok(x) => x,
-                        Err(e) => {
-                            error!(
-                                "reader: error reading chunk from {}: {} -> skip one round",
-                                p.meta.name, e
-                            );
-                            buffer.unmap();
-                            (0, 0, true)
-                        }
-                    };
+                    sw.restart();
+                    let (bytes_read, start_nonce, next_plot, mapped) =
+                        read_next(&mut p, &mut buffer, scoop);
 
                     if rx_interupt.try_recv().is_ok() {
                         buffer.unmap();
                         tx_empty_buffers.send(buffer).unwrap();
-                        break 'outer;
-                    }
-
-                    let finished = i_p == (plot_count - 1) && next_plot;
-                    // buffer routing
-                    #[cfg(feature = "opencl")]
-                    match buffer.get_id() {
-                        0 => {
-                            tx_read_replies_cpu
-                                .send(ReadReply {
-                                    buffer,
-                                    info: BufferInfo {
-                                        len: bytes_read,
-                                        height,
-                                        block,
-                                        base_target,
-                                        gensig: gensig.clone(),
-                                        start_nonce,
-                                        finished,
-                                        account_id: p.meta.account_id,
-                                        gpu_signal: 0,
-                                    },
-                                })
-                                .expect("failed to send read data to CPU thread");
-                        }
-                        i => {
-                            tx_read_replies_gpu.as_ref().unwrap()[i - 1]
-                                .send(ReadReply {
-                                    buffer,
-                                    info: BufferInfo {
-                                        len: bytes_read,
-                                        height,
-                                        block,
-                                        base_target,
-                                        gensig: gensig.clone(),
-                                        start_nonce,
-                                        finished,
-                                        account_id: p.meta.account_id,
-                                        gpu_signal: 0,
-                                    },
-                                })
-                                .expect("failed to send read data to GPU thread A");
-                        }
-                    }
-                    #[cfg(not(feature = "opencl"))]
-                    tx_read_replies_cpu
-                        .send(ReadReply {
-                            buffer,
-                            info: BufferInfo {
-                                len: bytes_read,
-                                height,
-                                block,
-                                base_target,
-                                gensig: gensig.clone(),
-                                start_nonce,
-                                finished,
-                                account_id: p.meta.account_id,
-                                gpu_signal: 0,
-                            },
-                        })
-                        .unwrap();
-
-                    nonces_processed += bytes_read as u64 / 64;
-
-                    match &pb {
-                        Some(pb) => {
-                            let mut pb = pb.lock().unwrap();
-                            pb.add(bytes_read as u64);
-                        }
-                        None => (),
-                    }
-
-                    if show_drive_stats {
-                        elapsed += sw.elapsed_ms();
-                    }
-
-                    // send termination signal (dummy buffer) to gpu
-                    if finished {
-                        #[cfg(feature = "opencl")]
-                        for i in 0..tx_read_replies_gpu.as_ref().unwrap().len() {
-                            tx_read_replies_gpu.as_ref().unwrap()[i]
-                                .send(ReadReply {
-                                    buffer: Box::new(CpuBuffer::new(0)) as Box<Buffer + Send>,
-                                    info: BufferInfo {
-                                        len: 1,
-                                        height,
-                                        block,
-                                        base_target,
-                                        gensig: gensig.clone(),
-                                        start_nonce: 0,
-                                        finished: false,
-                                        account_id: 0,
-                                        gpu_signal: 2,
-                                    },
-                                })
-                                .expect("Error sending 'drive finished' signal to GPU thread A");
-                        }
+                        return;
                     }
 
-                    if finished && show_drive_stats {
-                        info!(
-                            "{: <80}",
-                            format!(
-                                "drive {} finished, speed={} MiB/s",
-                                drive,
-                                nonces_processed * 1000 / (elapsed + 1) as u64 * 64 / 1024 / 1024,
-                            )
-                        );
-                    }
+                    finished = i_p == (plot_count - 1) && next_plot;
+                    delivery.deliver(
+                        buffer,
+                        bytes_read,
+                        start_nonce,
+                        finished,
+                        p.meta().account_id,
+                        sw.elapsed_ms(),
+                        mapped,
+                    );
 
                     if next_plot {
                         break 'inner;
                     }
                 }
             }
+
+            // the last plot couldn't be prepared, the workers still have to learn that the
+            // drive is done
+            if !finished && rx_interupt.try_recv().is_err() {
+                if let Ok(buffer) = rx_empty_buffers.recv() {
+                    let account_id = plots[plot_count - 1].lock().unwrap().meta().account_id;
+                    delivery.deliver(buffer, 0, 0, true, account_id, 0, None);
+                }
+            }
         })
     }
 }
 
+/// Routes the buffers of a drive read in a round to the CPU or GPU workers and keeps track of
+/// the progress of the drive.
+pub struct Delivery {
+    drive: String,
+    height: u64,
+    block: u64,
+    base_target: u64,
+    gensig: Arc<[u8; 32]>,
+    tx_read_replies_cpu: Sender<ReadReply>,
+    #[cfg(feature = "opencl")]
+    tx_read_replies_gpu: Option<Vec<Sender<ReadReply>>>,
+    progress: Arc<Mutex<HashMap<String, DriveProgress>>>,
+    pb: Option<Arc<Mutex<pbr::ProgressBar<Stdout>>>>,
+    show_drive_stats: bool,
+    elapsed: i64,
+    nonces_processed: u64,
+}
+
+impl Delivery {
+    #[cfg(feature = "io_uring")]
+    pub fn drive(&self) -> &str {
+        &self.drive
+    }
+
+    /// Sends `bytes_read` bytes of `buffer`, or of `mapped`, read in `read_ms` to the
+    /// workers. `finished` marks the last buffer of the drive in this round.
+    pub fn deliver(
+        &mut self,
+        buffer: Box<Buffer + Send>,
+        bytes_read: usize,
+        start_nonce: u64,
+        finished: bool,
+        account_id: u64,
+        read_ms: i64,
+        mapped: Option<MappedChunk>,
+    ) {
+        let info = BufferInfo {
+            len: bytes_read,
+            height: self.height,
+            block: self.block,
+            base_target: self.base_target,
+            gensig: self.gensig.clone(),
+            start_nonce,
+            finished,
+            account_id,
+            gpu_signal: 0,
+            mapped,
+        };
+        // buffer routing
+        #[cfg(feature = "opencl")]
+        match buffer.get_id() {
+            0 => {
+                self.tx_read_replies_cpu
+                    .send(ReadReply { buffer, info })
+                    .expect("failed to send read data to CPU thread");
+            }
+            i => {
+                self.tx_read_replies_gpu.as_ref().unwrap()[i - 1]
+                    .send(ReadReply { buffer, info })
+                    .expect("failed to send read data to GPU thread A");
+            }
+        }
+        #[cfg(not(feature = "opencl"))]
+        self.tx_read_replies_cpu
+            .send(ReadReply { buffer, info })
+            .unwrap();
+
+        self.nonces_processed += bytes_read as u64 / 64;
+
+        match &self.pb {
+            Some(pb) => {
+                let mut pb = pb.lock().unwrap();
+                pb.add(bytes_read as u64);
+            }
+            None => (),
+        }
+
+        self.elapsed += read_ms;
+        if let Some(progress) = self.progress.lock().unwrap().get_mut(&self.drive) {
+            progress.bytes_read += bytes_read as u64;
+            progress.read_ms += read_ms as u64;
+            progress.total_bytes_read += bytes_read as u64;
+            progress.total_read_ms += read_ms as u64;
+            progress.finished = finished;
+        }
+
+        // send termination signal (dummy buffer) to gpu
+        if finished {
+            #[cfg(feature = "opencl")]
+            for i in 0..self.tx_read_replies_gpu.as_ref().unwrap().len() {
+                self.tx_read_replies_gpu.as_ref().unwrap()[i]
+                    .send(ReadReply {
+                        buffer: Box::new(CpuBuffer::new(0)) as Box<Buffer + Send>,
+                        info: BufferInfo {
+                            len: 1,
+                            height: self.height,
+                            block: self.block,
+                            base_target: self.base_target,
+                            gensig: self.gensig.clone(),
+                            start_nonce: 0,
+                            finished: false,
+                            account_id: 0,
+                            gpu_signal: 2,
+                            mapped: None,
+                        },
+                    })
+                    .expect("Error sending 'drive finished' signal to GPU thread A");
+            }
+        }
+
+        if finished && self.show_drive_stats {
+            info!(
+                "{: <80}",
+                format!(
+                    "drive {} finished, speed={} MiB/s",
+                    self.drive,
+                    self.nonces_processed * 1000 / (self.elapsed + 1) as u64 * 64 / 1024 / 1024,
+                )
+            );
+        }
+    }
+}
+
+/// Reads the next part of `scoop` from `p` into `buffer`. Mapped plots hand their data to CPU
+/// workers without a copy. Read errors skip the rest of the plot with an empty buffer.
+pub fn read_next(
+    p: &mut Box<PlotSource + Send>,
+    buffer: &mut Box<Buffer + Send>,
+    scoop: u32,
+) -> (usize, u64, bool, Option<MappedChunk>) {
+    if buffer.get_id() == 0 {
+        let cap = buffer.get_buffer().lock().unwrap().capacity() as u64;
+        if let Some((chunk, start_nonce, next_plot)) = p.read_mapped(scoop, cap) {
+            return (chunk.as_slice().len(), start_nonce, next_plot, Some(chunk));
+        }
+    }
+
+    let mut_bs = buffer.get_buffer_for_writing();
+    let mut bs = mut_bs.lock().unwrap();
+    match p.read(&mut bs, scoop) {
+        Ok((bytes_read, start_nonce, next_plot)) => (bytes_read, start_nonce, next_plot, None),
+        Err(e) => {
+            error!(
+                "reader: error reading chunk from {}: {} -> skip one round",
+                p.meta().name,
+                e
+            );
+            buffer.unmap();
+            (0, 0, true, None)
+        }
+    }
+}
+
+fn new_progress(drive_id_to_plots: &HashMap<String, DrivePlots>) -> HashMap<String, DriveProgress> {
+    drive_id_to_plots
+        .iter()
+        .map(|(drive, plots)| {
+            let nonces: u64 = plots.iter().map(|p| p.lock().unwrap().meta().nonces).sum();
+            let progress = DriveProgress {
+                size: nonces * 64,
+                ..Default::default()
+            };
+            (drive.clone(), progress)
+        })
+        .collect()
+}
+
+pub fn count_open_files(drive_id_to_plots: &HashMap<String, DrivePlots>) -> u64 {
+    drive_id_to_plots
+        .values()
+        .flat_map(|plots| plots.iter())
+        .map(|p| p.lock().unwrap().open_files())
+        .sum()
+}
+
 // Don't waste your time striving for perfection; instead, strive for excellence - doing your best.
 // let my_best = perfection;
-pub fn check_overlap(drive_id_to_plots: &HashMap<String, Arc<Vec<Mutex<Plot>>>>) -> bool {
+pub fn check_overlap(drive_id_to_plots: &HashMap<String, DrivePlots>) -> bool {
     let plots: Vec<Meta> = drive_id_to_plots
         .values()
         .map(|a| a.iter())
         .flatten()
-        .map(|plot| plot.lock().unwrap().meta.clone())
+        .map(|plot| plot.lock().unwrap().meta().clone())
         .collect();
     plots
         .par_iter()
@@ -362,3 +608,311 @@ pub fn check_overlap(drive_id_to_plots: &HashMap<String, Arc<Vec<Mutex<Plot>>>>)
         .count()
         > 0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::miner::CpuBuffer;
+    use crate::plot::{write_sparse_plot, MemoryPlot, Plot, SCOOP_SIZE};
+    use crate::utils::test_dir;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    const SCOOP: u32 = 7;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn drives(drives: Vec<(&str, Vec<MemoryPlot>)>) -> HashMap<String, DrivePlots> {
+        drives
+            .into_iter()
+            .map(|(drive, plots)| {
+                let plots = plots
+                    .into_iter()
+                    .map(|p| Mutex::new(Box::new(p) as Box<PlotSource + Send>))
+                    .collect();
+                (drive.to_owned(), Arc::new(plots))
+            })
+            .collect()
+    }
+
+    // a reader with a single thread, the test plays the workers and hands buffers back
+    fn reader(
+        drive_id_to_plots: HashMap<String, DrivePlots>,
+        buffers: Vec<Box<Buffer + Send>>,
+        tx_read_replies_gpu: Vec<Sender<ReadReply>>,
+        use_io_uring: bool,
+    ) -> (Reader, Receiver<ReadReply>, Sender<Box<Buffer + Send>>) {
+        let (tx_empty_buffers, rx_empty_buffers) = crossbeam_channel::unbounded();
+        let (tx_read_replies_cpu, rx_read_replies_cpu) = crossbeam_channel::unbounded();
+        for buffer in buffers {
+            tx_empty_buffers.send(buffer).unwrap();
+        }
+        let reader = Reader::new(
+            drive_id_to_plots,
+            0,
+            1,
+            rx_empty_buffers,
+            tx_empty_buffers.clone(),
+            tx_read_replies_cpu,
+            Some(tx_read_replies_gpu),
+            false,
+            false,
+            false,
+            false,
+            false,
+            use_io_uring,
+        );
+        (reader, rx_read_replies_cpu, tx_empty_buffers)
+    }
+
+    fn cpu_buffers(count: usize, nonces: u64) -> Vec<Box<Buffer + Send>> {
+        (0..count)
+            .map(|_| Box::new(CpuBuffer::new((nonces * SCOOP_SIZE) as usize)) as Box<Buffer + Send>)
+            .collect()
+    }
+
+    // a sparse plot file, only the scoop read holds data
+    fn plot_file(dir: &Path, start_nonce: u64, nonces: u64) -> PathBuf {
+        let path = dir.join(format!("1337_{}_{}", start_nonce, nonces));
+        write_sparse_plot(&path, start_nonce, nonces, &[SCOOP]);
+        path
+    }
+
+    // nonces in the buffer (or the mapping) of a reply, checking the data of every entry
+    fn nonces(reply: &mut ReadReply) -> Vec<u64> {
+        let data = reply.buffer.get_buffer();
+        let data = data.lock().unwrap();
+        let data = match &reply.info.mapped {
+            Some(chunk) => chunk.as_slice(),
+            None => &data[..],
+        };
+        data[..reply.info.len]
+            .chunks(SCOOP_SIZE as usize)
+            .enumerate()
+            .map(|(i, entry)| {
+                let nonce = reply.info.start_nonce + i as u64;
+                assert_eq!(entry, &MemoryPlot::entry(nonce, SCOOP)[..]);
+                nonce
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_start_reading() {
+        let mut split = MemoryPlot::new(1337, 0, 10);
+        split.max_read = 3 * SCOOP_SIZE;
+        let mut broken = MemoryPlot::new(42, 500, 4);
+        broken.fail = true;
+        let mut resized = MemoryPlot::new(7, 300, 2);
+        resized.unprepared = true;
+        let (mut reader, rx_read_replies, tx_empty_buffers) = reader(
+            drives(vec![
+                ("a", vec![split, MemoryPlot::new(1337, 10, 5)]),
+                ("b", vec![broken, MemoryPlot::new(42, 100, 7)]),
+                ("c", vec![MemoryPlot::new(7, 0, 3), resized]),
+            ]),
+            cpu_buffers(2, 4),
+            Vec::new(),
+            false,
+        );
+        let gensig = Arc::new([5u8; 32]);
+        reader.start_reading(9, 8, 7777, SCOOP, &gensig);
+
+        let mut account_id_to_nonces: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut empty = 0;
+        let mut finished = 0;
+        while finished < 3 {
+            let mut reply = rx_read_replies.recv_timeout(TIMEOUT).unwrap();
+            assert_eq!(
+                (reply.info.height, reply.info.block, reply.info.base_target),
+                (9, 8, 7777)
+            );
+            assert_eq!(reply.info.gensig, gensig);
+            assert_eq!(reply.info.gpu_signal, 0);
+            account_id_to_nonces
+                .entry(reply.info.account_id)
+                .or_insert_with(Vec::new)
+                .extend(nonces(&mut reply));
+            if reply.info.len == 0 {
+                empty += 1;
+            }
+            if reply.info.finished {
+                finished += 1;
+            }
+            tx_empty_buffers.send(reply.buffer).unwrap();
+        }
+
+        for nonces in account_id_to_nonces.values_mut() {
+            nonces.sort();
+        }
+        assert_eq!(account_id_to_nonces[&1337], (0..15).collect::<Vec<u64>>());
+        assert_eq!(account_id_to_nonces[&42], (100..107).collect::<Vec<u64>>());
+        assert_eq!(account_id_to_nonces[&7], (0..3).collect::<Vec<u64>>());
+        // the broken plot is skipped with an empty buffer, the drive of the resized one is
+        // finished with one
+        assert_eq!(empty, 2);
+        assert!(rx_read_replies.try_recv().is_err());
+
+        let progress = reader.progress();
+        assert_eq!(progress["a"].size, 15 * SCOOP_SIZE);
+        assert_eq!(progress["b"].size, 11 * SCOOP_SIZE);
+    }
+
+    #[test]
+    fn test_stop_reading() {
+        let (mut reader, rx_read_replies, tx_empty_buffers) = reader(
+            drives(vec![("a", vec![MemoryPlot::new(1337, 0, 100)])]),
+            cpu_buffers(1, 4),
+            Vec::new(),
+            false,
+        );
+        reader.start_reading(1, 1, 1, SCOOP, &Arc::new([0; 32]));
+
+        // the only buffer is with the worker, so reading waits until it comes back
+        let mut reply = rx_read_replies.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(nonces(&mut reply), vec![0, 1, 2, 3]);
+        reader.stop_reading();
+        tx_empty_buffers.send(reply.buffer).unwrap();
+        // the pool has a single thread, this waits for the read task to end
+        reader.pool.install(|| ());
+
+        // the chunk read after the interrupt is dropped and its buffer is free again
+        assert!(rx_read_replies.try_recv().is_err());
+        assert_eq!(reader.rx_empty_buffers.len(), 1);
+        let progress = reader.progress();
+        assert_eq!(progress["a"].bytes_read, 4 * SCOOP_SIZE);
+        assert!(!progress["a"].finished);
+    }
+
+    #[cfg(feature = "opencl")]
+    struct TestGpuBuffer(CpuBuffer);
+
+    #[cfg(feature = "opencl")]
+    impl Buffer for TestGpuBuffer {
+        fn get_buffer(&mut self) -> Arc<Mutex<Vec<u8>>> {
+            self.0.get_buffer()
+        }
+        fn get_buffer_for_writing(&mut self) -> Arc<Mutex<Vec<u8>>> {
+            self.0.get_buffer_for_writing()
+        }
+        fn get_gpu_buffers(&self) -> Option<&crate::ocl::GpuBuffer> {
+            None
+        }
+        fn get_gpu_data(&self) -> Option<ocl_core::Mem> {
+            None
+        }
+        fn unmap(&self) {}
+        fn get_id(&self) -> usize {
+            1
+        }
+    }
+
+    #[cfg(feature = "opencl")]
+    #[test]
+    fn test_gpu_routing() {
+        let (tx_read_replies_gpu, rx_read_replies_gpu) = crossbeam_channel::unbounded();
+        let (mut reader, rx_read_replies, tx_empty_buffers) = reader(
+            drives(vec![("a", vec![MemoryPlot::new(1337, 0, 6)])]),
+            vec![Box::new(TestGpuBuffer(CpuBuffer::new(
+                4 * SCOOP_SIZE as usize,
+            )))],
+            vec![tx_read_replies_gpu],
+            false,
+        );
+        reader.start_reading(1, 1, 1, SCOOP, &Arc::new([0; 32]));
+
+        let mut signals = Vec::new();
+        let mut read = Vec::new();
+        while signals.len() < 2 {
+            let mut reply = rx_read_replies_gpu.recv_timeout(TIMEOUT).unwrap();
+            if reply.info.gpu_signal != 0 {
+                signals.push(reply.info.gpu_signal);
+                continue;
+            }
+            read.extend(nonces(&mut reply));
+            tx_empty_buffers.send(reply.buffer).unwrap();
+        }
+
+        // round start and drive finished signals around the data, nothing for the cpu
+        assert_eq!(signals, vec![1, 2]);
+        assert_eq!(read, (0..6).collect::<Vec<u64>>());
+        assert!(rx_read_replies.try_recv().is_err());
+    }
+
+    #[cfg(feature = "io_uring")]
+    #[test]
+    fn test_io_uring() {
+        let dir = test_dir("io_uring");
+        let mut plots = Vec::new();
+        for &(start_nonce, nonces) in &[(0u64, 13u64), (13, 6)] {
+            let path = plot_file(&dir, start_nonce, nonces);
+            let plot = Plot::new(&path, false, false, false).unwrap();
+            plots.push(Mutex::new(Box::new(plot) as Box<PlotSource + Send>));
+        }
+        // plots without a file are read in between
+        let plot = MemoryPlot::new(1337, 100, 5);
+        plots.insert(1, Mutex::new(Box::new(plot) as Box<PlotSource + Send>));
+        // a plot that can't be prepared doesn't keep the drive from finishing
+        let mut resized = MemoryPlot::new(1337, 200, 3);
+        resized.unprepared = true;
+        plots.push(Mutex::new(Box::new(resized) as Box<PlotSource + Send>));
+        let mut drive_id_to_plots = HashMap::new();
+        drive_id_to_plots.insert("a".to_owned(), Arc::new(plots));
+
+        let (mut reader, rx_read_replies, tx_empty_buffers) =
+            reader(drive_id_to_plots, cpu_buffers(3, 4), Vec::new(), true);
+        reader.start_reading(1, 1, 1, SCOOP, &Arc::new([0; 32]));
+
+        let mut read = Vec::new();
+        loop {
+            let mut reply = rx_read_replies.recv_timeout(TIMEOUT).unwrap();
+            assert_ne!(reply.info.len, 0);
+            read.extend(nonces(&mut reply));
+            let finished = reply.info.finished;
+            tx_empty_buffers.send(reply.buffer).unwrap();
+            if finished {
+                break;
+            }
+        }
+        reader.pool.install(|| ());
+
+        // the last buffer finishes the drive
+        assert!(rx_read_replies.try_recv().is_err());
+        read.sort();
+        let expected: Vec<u64> = (0..19).chain(100..105).collect();
+        assert_eq!(read, expected);
+        assert_eq!(reader.rx_empty_buffers.len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mmap() {
+        let dir = test_dir("mmap");
+        let path = plot_file(&dir, 0, 10);
+        let plot = Plot::new(&path, false, true, false).unwrap();
+        let plots = vec![Mutex::new(Box::new(plot) as Box<PlotSource + Send>)];
+        let mut drive_id_to_plots = HashMap::new();
+        drive_id_to_plots.insert("a".to_owned(), Arc::new(plots));
+
+        let (mut reader, rx_read_replies, tx_empty_buffers) =
+            reader(drive_id_to_plots, cpu_buffers(1, 4), Vec::new(), false);
+        reader.start_reading(1, 1, 1, SCOOP, &Arc::new([0; 32]));
+
+        let mut read = Vec::new();
+        loop {
+            let mut reply = rx_read_replies.recv_timeout(TIMEOUT).unwrap();
+            // cpu buffers get the mapping, nothing is copied
+            assert!(reply.info.mapped.is_some());
+            read.extend(nonces(&mut reply));
+            let finished = reply.info.finished;
+            tx_empty_buffers.send(reply.buffer).unwrap();
+            if finished {
+                break;
+            }
+        }
+        reader.pool.install(|| ());
+
+        assert_eq!(read, (0..10).collect::<Vec<u64>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+//! Reloads the config of a running miner on SIGHUP or when the file changes.
+//!
+//! Plot dirs, submission targets and logging are applied in place, see `LIVE_SETTINGS`. Other
+//! changes are reported and take effect after a restart.
+
+use crate::config::{changed_settings, try_load_cfg, Cfg, LIVE_SETTINGS};
+use crate::logger::reload_logger;
+use crate::miner::MinerHandle;
+use filetime::FileTime;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+// how often the config file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub fn watch(path: String, cfg: Cfg, miner: MinerHandle, logger: log4rs::Handle) {
+    let (tx_reload, rx_reload) = crossbeam_channel::unbounded();
+
+    #[cfg(unix)]
+    {
+        match signal_hook::iterator::Signals::new(&[signal_hook::SIGHUP]) {
+            Ok(signals) => {
+                let tx_reload = tx_reload.clone();
+                thread::spawn(move || {
+                    for _ in signals.forever() {
+                        info!("SIGHUP, reloading config");
+                        tx_reload.send(()).ok();
+                    }
+                });
+            }
+            Err(e) => error!("can't handle SIGHUP: {}", e),
+        }
+    }
+
+    let watched = path.clone();
+    thread::spawn(move || {
+        let mut last_modified = modified(&watched);
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let modified = modified(&watched);
+            if modified != last_modified {
+                last_modified = modified;
+                info!("{} changed, reloading config", watched);
+                tx_reload.send(()).ok();
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        // settings that need a restart keep their values from the start
+        let mut running = cfg;
+        for _ in rx_reload {
+            match try_load_cfg(&path) {
+                Ok(cfg) => apply(&mut running, cfg, &miner, &logger),
+                Err(e) => error!("can't reload config, keeping the current one: {}", e),
+            }
+        }
+    });
+}
+
+fn modified(path: &str) -> Option<FileTime> {
+    fs::metadata(path)
+        .ok()
+        .map(|metadata| FileTime::from_last_modification_time(&metadata))
+}
+
+fn apply(running: &mut Cfg, cfg: Cfg, miner: &MinerHandle, logger: &log4rs::Handle) {
+    let (live, restart): (Vec<String>, Vec<String>) = changed_settings(running, &cfg)
+        .into_iter()
+        .partition(|name| LIVE_SETTINGS.contains(&name.as_str()));
+    if !restart.is_empty() {
+        warn!(
+            "config: {} can't change while mining, restart to apply",
+            restart.join(", ")
+        );
+    }
+    if live.is_empty() {
+        debug!("config: nothing to apply");
+        return;
+    }
+    let changed = |names: &[&str]| live.iter().any(|name| names.contains(&name.as_str()));
+
+    let mut applied = Vec::new();
+    if changed(&["plot_dirs"]) && miner.set_plot_dirs(cfg.plot_dirs.clone()) {
+        running.plot_dirs = cfg.plot_dirs.clone();
+        applied.push("plot_dirs");
+    }
+    if changed(&[
+        "submission_policy",
+        "target_deadline",
+        "account_id_to_target_deadline",
+    ]) {
+        miner.set_submission_policy(
+            cfg.submission_policy,
+            cfg.target_deadline,
+            cfg.account_id_to_target_deadline.clone(),
+        );
+        running.submission_policy = cfg.submission_policy;
+        running.target_deadline = cfg.target_deadline;
+        running.account_id_to_target_deadline = cfg.account_id_to_target_deadline.clone();
+        applied.push("submission targets");
+    }
+    if changed(&[
+        "console_log_level",
+        "logfile_log_level",
+        "console_log_pattern",
+        "logfile_log_pattern",
+        "logfile_max_count",
+        "logfile_max_size",
+    ]) {
+        running.console_log_level = cfg.console_log_level;
+        running.logfile_log_level = cfg.logfile_log_level;
+        running.console_log_pattern = cfg.console_log_pattern;
+        running.logfile_log_pattern = cfg.logfile_log_pattern;
+        running.logfile_max_count = cfg.logfile_max_count;
+        running.logfile_max_size = cfg.logfile_max_size;
+        reload_logger(logger, running);
+        applied.push("logging");
+    }
+    if !applied.is_empty() {
+        info!("config reloaded: {}", applied.join(", "));
+    }
+}
@@ -1,50 +1,99 @@
 use crate::com::api::{FetchError, MiningInfoResponse};
-use crate::com::client::{Client, ProxyDetails, SubmissionParameters};
+use crate::com::client::{Client, HeadStream, ProxyDetails, SubmissionParameters};
+use crate::com::round::RoundScheduleOverride;
+use crate::com::signer::Signer;
 use crate::future::prio_retry::PrioRetry;
+use crate::journal::{Journal, Status};
+use crate::metrics::{self, Metrics};
+use crossbeam_channel::Receiver;
 use futures::future::Future;
 use futures::stream::Stream;
 use futures::sync::mpsc;
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::u64;
 use tokio;
 use tokio::runtime::TaskExecutor;
 use url::Url;
 
+// failed submissions are retried after 3s, 6s, 12s, 24s and 48s
+#[cfg(not(test))]
+const SUBMISSION_RETRY_DELAY: Duration = Duration::from_secs(3);
+#[cfg(test)]
+const SUBMISSION_RETRY_DELAY: Duration = Duration::from_millis(100);
+const SUBMISSION_MAX_RETRIES: u32 = 5;
+
 #[derive(Clone)]
 pub struct RequestHandler {
     client: Client,
     tx_submit_data: mpsc::UnboundedSender<SubmissionParameters>,
+    journal: Journal,
+    metrics: Arc<Metrics>,
+    // latest queued submission of every account until it is settled, mirrors the slots of the
+    // PrioRetry stream
+    outstanding: Arc<Mutex<HashMap<u64, SubmissionParameters>>>,
 }
 
 impl RequestHandler {
     pub fn new(
         base_uri: Url,
+        fallback_uris: Vec<Url>,
+        signers: HashMap<u64, Signer>,
         secret_phrases: HashMap<u64, String>,
+        finalized_heads: bool,
+        round_schedule_override: RoundScheduleOverride,
+        genesis_hash: Option<String>,
+        get_mining_info_interval: u64,
         timeout: u64,
         total_size_gb: usize,
         send_proxy_details: bool,
         additional_headers: HashMap<String, String>,
+        journal: Journal,
+        metrics: Arc<Metrics>,
         executor: TaskExecutor,
     ) -> RequestHandler {
 
+        let proxy_details = if send_proxy_details {
+            ProxyDetails::Enabled
+        } else {
+            ProxyDetails::Disabled
+        };
+
         let client = Client::new(
             base_uri,
+            fallback_uris,
+            signers,
             secret_phrases,
+            finalized_heads,
+            round_schedule_override,
+            genesis_hash,
+            get_mining_info_interval,
+            timeout,
             total_size_gb,
+            proxy_details,
+            additional_headers,
         );
 
         let (tx_submit_data, rx_submit_nonce_data) = mpsc::unbounded();
+        let outstanding = Arc::new(Mutex::new(HashMap::new()));
         RequestHandler::handle_submissions(
             client.clone(),
             rx_submit_nonce_data,
             tx_submit_data.clone(),
+            journal.clone(),
+            metrics.clone(),
+            outstanding.clone(),
             executor,
         );
 
         RequestHandler {
             client,
             tx_submit_data,
+            journal,
+            metrics,
+            outstanding,
         }
     }
 
@@ -52,43 +101,70 @@ impl RequestHandler {
         client: Client,
         rx: mpsc::UnboundedReceiver<SubmissionParameters>,
         tx_submit_data: mpsc::UnboundedSender<SubmissionParameters>,
+        journal: Journal,
+        metrics: Arc<Metrics>,
+        outstanding: Arc<Mutex<HashMap<u64, SubmissionParameters>>>,
         executor: TaskExecutor,
     ) {
-        let stream = PrioRetry::new(rx, Duration::from_secs(3))
-            .and_then(move |submission_params| {
-                client
-                    .clone()
-                    .submit_nonce(&submission_params)
-                    .then(move |res| {
-                        match res {
-                            Ok(res) => {
-                                if res.verify_result{
-                                    println!("verify succeed!!!");
-                                } else {
-                                    warn!("verify failed: accountId = {}, height = {}, nonce = {}, deadline = {}",
-                                          &submission_params.account_id,
-                                          &submission_params.height,
-                                          &submission_params.nonce,
-                                          &submission_params.deadline,
-                                    )
-                                }
-                            }
-                            Err(err) => {
-                                error!("submit nonce error: {:?}", err)
-                            }
-                        };
-                        Ok(())
-                    })
+        let failed_journal = journal.clone();
+        let failed_outstanding = outstanding.clone();
+        let submissions = PrioRetry::new(
+            rx,
+            SUBMISSION_RETRY_DELAY,
+            SUBMISSION_MAX_RETRIES,
+            |submission_params: &SubmissionParameters| submission_params.account_id,
+        )
+        .on_give_up(move |submission_params| {
+            failed_journal.record(&submission_params, Status::Failed, None);
+            settle(&failed_outstanding, &submission_params);
+        });
+        let submitter = Submitter {
+            client,
+            tx_submit_data,
+            tx_settled: submissions.settler(),
+            journal,
+            metrics,
+            outstanding,
+        };
+        // Submissions of an account are sent one after another: extrinsics of the same signer
+        // must not race for the account nonce. Every account has a task of its own though, so
+        // waiting for the inclusion of one account's submission doesn't hold back the others.
+        let mut accounts: HashMap<u64, mpsc::UnboundedSender<SubmissionParameters>> =
+            HashMap::new();
+        let account_executor = executor.clone();
+        let stream = submissions
+            .for_each(move |submission_params| {
+                let tx = accounts
+                    .entry(submission_params.account_id)
+                    .or_insert_with(|| {
+                        let (tx, rx) = mpsc::unbounded();
+                        let submitter = submitter.clone();
+                        account_executor.spawn(rx.for_each(move |submission_params| {
+                            submitter.submit(submission_params)
+                        }));
+                        tx
+                    });
+                if let Err(e) = tx.unbounded_send(submission_params) {
+                    error!("can't hand over submission params: {}", e);
+                }
+                Ok(())
             })
-            .for_each(|_| Ok(()))
             .map_err(|e| error!("can't handle submission params: {:?}", e));
         executor.spawn(stream);
     }
 
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
     pub fn get_mining_info(&self) -> impl Future<Item = MiningInfoResponse, Error = FetchError> {
         self.client.get_mining_info()
     }
 
+    pub fn new_heads(&self) -> HeadStream {
+        self.client.new_heads()
+    }
+
     pub fn submit_nonce(
         &self,
         account_id: u64,
@@ -99,7 +175,7 @@ impl RequestHandler {
         deadline: u64,
         gen_sig: [u8; 32],
     ) {
-        let res = self.tx_submit_data.unbounded_send(SubmissionParameters {
+        let params = SubmissionParameters {
             account_id,
             nonce,
             height,
@@ -107,36 +183,361 @@ impl RequestHandler {
             deadline_unadjusted,
             deadline,
             gen_sig,
-        });
-        if let Err(e) = res {
+        };
+        self.journal.record(&params, Status::Pending, None);
+        self.metrics.submission(metrics::SUBMITTED);
+        self.queue(params);
+    }
+
+    /// Resubmits what was still pending in the journal when the miner stopped, unless its round
+    /// is over by now. Only the best deadline of every account is resubmitted, the others are
+    /// superseded by it. The resubmitted deadlines are returned.
+    ///
+    /// `block` is the current round of the miner, replayed submissions take part in it.
+    pub fn replay(
+        &self,
+        mining_info: &MiningInfoResponse,
+        block: u64,
+    ) -> Vec<SubmissionParameters> {
+        let records = self.journal.submissions();
+        let mut pending = Vec::new();
+        for record in records.iter().filter(|r| r.status == Status::Pending) {
+            let params = match record.submission() {
+                Some(params) => params,
+                None => continue,
+            };
+            if mining_info
+                .round_schedule
+                .is_expired(params.height, mining_info.height)
+            {
+                self.journal.record(&params, Status::Expired, None);
+                self.metrics.submission(metrics::EXPIRED);
+                continue;
+            }
+            pending.push(params);
+        }
+
+        // the latest round of an account wins, within it the best deadline
+        let mut best: HashMap<u64, &SubmissionParameters> = HashMap::new();
+        for params in &pending {
+            let replaces = match best.get(&params.account_id) {
+                Some(best) => {
+                    (params.height, Reverse(params.deadline))
+                        > (best.height, Reverse(best.deadline))
+                }
+                None => true,
+            };
+            if replaces {
+                best.insert(params.account_id, params);
+            }
+        }
+
+        let mut replayed = Vec::new();
+        for params in &pending {
+            if best[&params.account_id] != params {
+                self.journal.record(params, Status::Superseded, None);
+                continue;
+            }
+            info!(
+                "replaying submission: account={}, height={}, nonce={}, deadline={}",
+                params.account_id, params.height, params.nonce, params.deadline
+            );
+            let params = SubmissionParameters {
+                block,
+                ..params.clone()
+            };
+            self.metrics.submission(metrics::SUBMITTED);
+            self.queue(params.clone());
+            replayed.push(params);
+        }
+        replayed
+    }
+
+    fn queue(&self, params: SubmissionParameters) {
+        {
+            let mut outstanding = self.outstanding.lock().unwrap();
+            // PrioRetry drops params that aren't better than the ones of the account it has,
+            // whichever loses will never be submitted
+            match outstanding.get(&params.account_id) {
+                Some(queued) if *queued >= params => {
+                    self.journal.record(&params, Status::Superseded, None);
+                }
+                _ => {
+                    if let Some(replaced) = outstanding.insert(params.account_id, params.clone()) {
+                        self.journal.record(&replaced, Status::Superseded, None);
+                    }
+                }
+            }
+        }
+        if let Err(e) = self.tx_submit_data.unbounded_send(params) {
             error!("can't send submission params: {}", e);
         }
     }
+
+    /// Waits until all queued submissions are included, expired or given up on, at most
+    /// `timeout` or until `abort` receives. Failed submissions are retried meanwhile. Returns the
+    /// number of submissions that are still pending.
+    pub fn drain(&self, timeout: Duration, abort: &Receiver<()>) -> usize {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let pending = self.outstanding.lock().unwrap().len();
+            if pending == 0 || Instant::now() >= deadline {
+                return pending;
+            }
+            if abort.recv_timeout(Duration::from_millis(100)).is_ok() {
+                return pending;
+            }
+        }
+    }
+}
+
+// submits deadlines and records their outcome
+#[derive(Clone)]
+struct Submitter {
+    client: Client,
+    tx_submit_data: mpsc::UnboundedSender<SubmissionParameters>,
+    // frees the slot of the account in the PrioRetry stream
+    tx_settled: mpsc::UnboundedSender<SubmissionParameters>,
+    journal: Journal,
+    metrics: Arc<Metrics>,
+    outstanding: Arc<Mutex<HashMap<u64, SubmissionParameters>>>,
+}
+
+impl Submitter {
+    fn submit(
+        &self,
+        submission_params: SubmissionParameters,
+    ) -> impl Future<Item = (), Error = ()> {
+        let submitter = self.clone();
+        self.client
+            .submit_nonce(&submission_params)
+            .then(move |res| {
+                match res {
+                    Ok(res) => {
+                        submitter.journal.record(
+                            &submission_params,
+                            Status::Included,
+                            Some(res.verify_result),
+                        );
+                        submitter.settle(&submission_params);
+                        if res.verify_result{
+                            submitter.metrics.submission(metrics::VERIFIED);
+                            println!("verify succeed!!!");
+                        } else {
+                            submitter.metrics.submission(metrics::REJECTED);
+                            warn!("verify failed: accountId = {}, height = {}, nonce = {}, deadline = {}",
+                                  &submission_params.account_id,
+                                  &submission_params.height,
+                                  &submission_params.nonce,
+                                  &submission_params.deadline,
+                            )
+                        }
+                    }
+                    Err(err @ FetchError::Expired { .. }) => {
+                        warn!("{}", err);
+                        submitter.journal.record(&submission_params, Status::Expired, None);
+                        submitter.metrics.submission(metrics::EXPIRED);
+                        submitter.settle(&submission_params);
+                    }
+                    Err(err) => {
+                        error!("submit nonce error: {}", err);
+                        submitter.metrics.submission(metrics::FAILED);
+                        // requeued params are retried with a backoff unless they have been
+                        // replaced by better ones of the same account meanwhile
+                        if let Err(e) = submitter.tx_submit_data.unbounded_send(submission_params)
+                        {
+                            error!("can't requeue submission params: {}", e);
+                        }
+                    }
+                };
+                Ok(())
+            })
+    }
+
+    fn settle(&self, params: &SubmissionParameters) {
+        settle(&self.outstanding, params);
+        if let Err(e) = self.tx_settled.unbounded_send(params.clone()) {
+            error!("can't settle submission params: {}", e);
+        }
+    }
+}
+
+fn settle(
+    outstanding: &Mutex<HashMap<u64, SubmissionParameters>>,
+    params: &SubmissionParameters,
+) {
+    let mut outstanding = outstanding.lock().unwrap();
+    // better params of the account may have been queued meanwhile, they are still pending
+    if outstanding.get(&params.account_id) == Some(params) {
+        outstanding.remove(&params.account_id);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::com::mock_node::{MockNode, Mining};
+    use crate::com::round::RoundSchedule;
+    use crate::utils::test_dir;
+    use std::fs;
+    use std::thread;
     use tokio;
 
-    static BASE_URL: &str = "http://94.130.178.37:31000";
-
-    #[test]
-    fn test_submit_nonce() {
-        let rt = tokio::runtime::Runtime::new().expect("can't create runtime");
+    fn wait_for<F: Fn() -> bool>(f: F) {
+        let started = Instant::now();
+        while !f() {
+            assert!(started.elapsed() < Duration::from_secs(30), "timed out");
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
 
-        let request_handler = RequestHandler::new(
-            BASE_URL.parse().unwrap(),
+    // submits for the accounts 1337 and 1338 to `node`
+    fn request_handler(
+        node: &MockNode,
+        journal: &Journal,
+        rt: &tokio::runtime::Runtime,
+    ) -> RequestHandler {
+        let mut signers = HashMap::new();
+        signers.insert(1337, Signer::from_secret("//Alice").unwrap());
+        signers.insert(1338, Signer::from_secret("//Bob").unwrap());
+        RequestHandler::new(
+            node.url(),
+            Vec::new(),
+            signers,
             HashMap::new(),
+            false,
+            RoundScheduleOverride::default(),
+            None,
+            3000,
             3,
             12,
             true,
             HashMap::new(),
+            journal.clone(),
+            Arc::new(Metrics::default()),
             rt.executor(),
-        );
+        )
+    }
+
+    fn status(journal: &Journal, nonce: u64) -> Option<Status> {
+        journal
+            .submissions()
+            .iter()
+            .find(|r| r.nonce == nonce)
+            .map(|r| r.status)
+    }
+
+    #[test]
+    fn test_submit_nonce() {
+        let node = MockNode::start();
+        let rt = tokio::runtime::Runtime::new().expect("can't create runtime");
+
+        let dir = test_dir("requests");
+        let journal = Journal::open(dir.join("submissions.jsonl")).unwrap();
+        let request_handler = request_handler(&node, &journal, &rt);
+
+        request_handler.submit_nonce(1337, 12, 1, 0, 7123, 1193, [7; 32]);
+        let (_tx_abort, rx_abort) = crossbeam_channel::unbounded();
+        assert_eq!(request_handler.drain(Duration::from_secs(30), &rx_abort), 0);
+        assert_eq!(journal.submissions()[0].status, Status::Included);
+        let mining = Mining {
+            account_id: 1337,
+            height: 1,
+            sig: [7; 32],
+            nonce: 12,
+            deadline: 1193,
+        };
+        assert_eq!(node.minings(), vec![mining.clone()]);
+        assert_eq!(journal.submissions()[0].verify_result, Some(true));
+
+        // left pending by a previous run
+        let pending = |nonce, height| SubmissionParameters {
+            account_id: 1337,
+            nonce,
+            height,
+            block: 5,
+            deadline_unadjusted: 7000,
+            deadline: 1000,
+            gen_sig: [7; 32],
+        };
+        journal.record(&pending(13, 2), Status::Pending, None);
+        journal.record(&pending(14, 1), Status::Pending, None);
+        let worse = SubmissionParameters {
+            deadline: 1100,
+            ..pending(15, 2)
+        };
+        journal.record(&worse, Status::Pending, None);
+        let mining_info = MiningInfoResponse {
+            generation_signature: [8; 32],
+            base_target: 1,
+            height: 3,
+            target_deadline: u64::MAX,
+            duration_from_last_mining: 0,
+            round_schedule: RoundSchedule::per_block(),
+        };
+        let replayed = request_handler.replay(&mining_info, 1);
+        assert_eq!(replayed, vec![SubmissionParameters { block: 1, ..pending(13, 2) }]);
+
+        wait_for(|| node.minings().len() == 2);
+        assert_eq!((node.minings()[1].height, node.minings()[1].nonce), (2, 13));
+        let states: Vec<_> = journal
+            .submissions()
+            .iter()
+            .map(|r| (r.nonce, r.status))
+            .collect();
+        assert_eq!(states[2], (14, Status::Expired));
+        assert_eq!(states[3], (15, Status::Superseded));
+
+        rt.shutdown_now().wait().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_submit_accounts_concurrently() {
+        let node = MockNode::start();
+        let rt = tokio::runtime::Runtime::new().expect("can't create runtime");
+
+        let dir = test_dir("requests_concurrent");
+        let journal = Journal::open(dir.join("submissions.jsonl")).unwrap();
+        let request_handler = request_handler(&node, &journal, &rt);
+
+        // the submission of 1337 waits for its inclusion until it is released
+        node.hold(1337);
+        request_handler.submit_nonce(1337, 12, 1, 0, 7123, 1193, [7; 32]);
+        wait_for(|| node.minings().len() == 1);
+        request_handler.submit_nonce(1338, 13, 1, 0, 7000, 1000, [7; 32]);
+        wait_for(|| status(&journal, 13) == Some(Status::Included));
+        assert_eq!(status(&journal, 12), Some(Status::Pending));
+        // a worse deadline of the account is never submitted
+        request_handler.submit_nonce(1337, 14, 1, 0, 8000, 1300, [7; 32]);
+        assert_eq!(status(&journal, 14), Some(Status::Superseded));
+
+        node.release(1337);
+        let (_tx_abort, rx_abort) = crossbeam_channel::unbounded();
+        assert_eq!(request_handler.drain(Duration::from_secs(30), &rx_abort), 0);
+        assert_eq!(status(&journal, 12), Some(Status::Included));
+
+        rt.shutdown_now().wait().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_drain_gives_up() {
+        let node = MockNode::start();
+        let rt = tokio::runtime::Runtime::new().expect("can't create runtime");
+
+        let dir = test_dir("requests_give_up");
+        let journal = Journal::open(dir.join("submissions.jsonl")).unwrap();
+        let request_handler = request_handler(&node, &journal, &rt);
 
-        request_handler.submit_nonce(1337, 12, 111, 0, 7123, 1193, [0; 32]);
+        node.reject_submissions(true);
+        request_handler.submit_nonce(1337, 12, 1, 0, 7123, 1193, [7; 32]);
+        let (_tx_abort, rx_abort) = crossbeam_channel::unbounded();
+        assert_eq!(request_handler.drain(Duration::from_secs(30), &rx_abort), 0);
+        assert_eq!(node.minings().len(), 1 + SUBMISSION_MAX_RETRIES as usize);
+        assert_eq!(status(&journal, 12), Some(Status::Failed));
 
-        rt.shutdown_on_idle();
+        rt.shutdown_now().wait().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
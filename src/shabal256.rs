@@ -78,6 +78,22 @@ pub fn shabal256_hash_fast(data: &[u8], term: &[u32; 16]) -> [u8; 32] {
     unsafe { *(b[8..16].as_ptr() as *const [u8; 32]) }
 }
 
+/// Shabal256 of a message of arbitrary length, the trailing bytes are padded into the
+/// termination block.
+pub fn shabal256_hash(data: &[u8]) -> [u8; 32] {
+    let full_blocks = data.len() & !63;
+    let remainder = data.len() - full_blocks;
+    let mut last_block = [0u8; 64];
+    last_block[..remainder].clone_from_slice(&data[full_blocks..]);
+    last_block[remainder] = 0x80;
+
+    let mut term = [0u32; 16];
+    for (word, bytes) in term.iter_mut().zip(last_block.chunks(4)) {
+        *word = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    shabal256_hash_fast(&data[..full_blocks], &term)
+}
+
 #[inline(always)]
 fn input_block_add(b: &mut [u32; 16], data: &[u32]) {
     for (element, data) in b.iter_mut().zip(data.iter()) {
@@ -509,4 +525,19 @@ mod test {
         };
         assert_eq!(hash_b, TEST_B_RESULT);
     }
+
+    #[test]
+    fn shabal256_unaligned() {
+        // u32 backed, the hashing functions expect 4 byte aligned data
+        let words = [0u32; 16];
+        let msg_a = unsafe { from_raw_parts(words.as_ptr() as *const u8, 64) };
+        assert_eq!(shabal256_hash(msg_a), TEST_A_RESULT);
+
+        let mut words = [0u32; 32];
+        words[..16].clone_from_slice(&TEST_B_M1);
+        words[16..].clone_from_slice(&TEST_B_M2);
+        // message B ends two bytes into the 10th word of the second block
+        let msg_b = unsafe { from_raw_parts(words.as_ptr() as *const u8, 64 + 9 * 4 + 2) };
+        assert_eq!(shabal256_hash(msg_b), TEST_B_RESULT);
+    }
 }
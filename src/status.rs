@@ -0,0 +1,113 @@
+//! Local HTTP API to watch and control a running miner, enabled with `api_address`.
+//!
+//! `GET /status` returns a `MinerStatus` as json. `POST /pause`, `/resume`, `/rescan` and
+//! `/wakeup` control the reader. There is no authentication, anyone who can reach the address
+//! controls the miner.
+
+use crate::miner::MinerHandle;
+use futures::Future;
+use hyper::service::service_fn_ok;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::thread;
+use tokio::runtime::TaskExecutor;
+
+/// Recent submissions in a status.
+const STATUS_SUBMISSIONS: usize = 20;
+
+pub fn serve(address: SocketAddr, miner: MinerHandle, executor: &TaskExecutor) {
+    if !address.ip().is_loopback() {
+        warn!(
+            "status api: {} isn't a loopback address, the miner can be controlled remotely",
+            address
+        );
+    }
+    listen("status api", address, miner, handle, executor);
+}
+
+/// Serves `GET /metrics` for Prometheus, enabled with `metrics_address`. Read only, so unlike
+/// the status api it may listen on any address.
+pub fn serve_metrics(address: SocketAddr, miner: MinerHandle, executor: &TaskExecutor) {
+    listen("metrics", address, miner, handle_metrics, executor);
+}
+
+fn listen(
+    name: &'static str,
+    address: SocketAddr,
+    miner: MinerHandle,
+    handle: fn(&MinerHandle, &Request<Body>) -> Response<Body>,
+    executor: &TaskExecutor,
+) {
+    let builder = match Server::try_bind(&address) {
+        Ok(builder) => builder,
+        Err(e) => {
+            error!("{}: can't listen on {}: {}", name, address, e);
+            return;
+        }
+    };
+    let server = builder
+        .serve(move || {
+            let miner = miner.clone();
+            service_fn_ok(move |req| handle(&miner, &req))
+        })
+        .map_err(move |e| error!("{}: {}", name, e));
+    info!("{}: listening on http://{}", name, address);
+    executor.spawn(server);
+}
+
+fn handle(miner: &MinerHandle, req: &Request<Body>) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/status") => json(&miner.status(STATUS_SUBMISSIONS)),
+        (&Method::POST, "/pause") => {
+            miner.pause();
+            empty(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, "/resume") => {
+            miner.resume();
+            empty(StatusCode::NO_CONTENT)
+        }
+        (&Method::POST, "/rescan") => {
+            // opening all plot files takes a while, heads are handled meanwhile
+            let miner = miner.clone();
+            thread::spawn(move || {
+                let plots = miner.rescan();
+                info!("status api: rescan finished, plots={}", plots);
+            });
+            empty(StatusCode::ACCEPTED)
+        }
+        (&Method::POST, "/wakeup") => {
+            miner.wakeup();
+            empty(StatusCode::NO_CONTENT)
+        }
+        (_, "/status") | (_, "/pause") | (_, "/resume") | (_, "/rescan") | (_, "/wakeup") => {
+            empty(StatusCode::METHOD_NOT_ALLOWED)
+        }
+        _ => empty(StatusCode::NOT_FOUND),
+    }
+}
+
+fn handle_metrics(miner: &MinerHandle, req: &Request<Body>) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(miner.metrics()))
+            .unwrap(),
+        (_, "/metrics") => empty(StatusCode::METHOD_NOT_ALLOWED),
+        _ => empty(StatusCode::NOT_FOUND),
+    }
+}
+
+fn json<T: Serialize>(value: &T) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(value).unwrap()))
+        .unwrap()
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,266 @@
+//! Decides which of the deadlines found during a round are submitted.
+
+use crate::miner::NonceData;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fmt;
+use std::u64;
+
+/// Which deadlines below the target are submitted.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionMode {
+    /// Every deadline below the target.
+    BelowTarget,
+    /// Deadlines better than the best deadline of their account in this round.
+    AccountBest,
+    /// Deadlines better than the best deadline of all accounts in this round.
+    GlobalBest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reason {
+    /// Drive finished signal or empty buffer.
+    NoDeadline,
+    /// Found after the mining window of the round.
+    WindowClosed,
+    /// The deadline doesn't match the one recomputed from account id and nonce.
+    CorruptPlot,
+    /// Found after the miner was asked to stop.
+    ShuttingDown,
+    AboveTarget { target: u64 },
+    NotAccountBest { best: u64 },
+    NotGlobalBest { best: u64 },
+    BelowTarget { target: u64 },
+    AccountBest,
+    GlobalBest,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::NoDeadline => write!(f, "no deadline"),
+            Reason::WindowClosed => write!(f, "mining window closed"),
+            Reason::CorruptPlot => write!(f, "corrupt plot data"),
+            Reason::ShuttingDown => write!(f, "shutting down"),
+            Reason::AboveTarget { target } => write!(f, "not below target {}", target),
+            Reason::NotAccountBest { best } => write!(f, "account has {} already", best),
+            Reason::NotGlobalBest { best } => write!(f, "round has {} already", best),
+            Reason::BelowTarget { target } => write!(f, "below target {}", target),
+            Reason::AccountBest => write!(f, "best of account"),
+            Reason::GlobalBest => write!(f, "best of round"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    Submit(Reason),
+    Skip(Reason),
+}
+
+pub struct SubmissionPolicy {
+    mode: SubmissionMode,
+    target_deadline: u64,
+    account_id_to_target_deadline: HashMap<u64, u64>,
+    server_target_deadline: u64,
+    account_id_to_best_deadline: HashMap<u64, u64>,
+    best_deadline: u64,
+}
+
+impl SubmissionPolicy {
+    /// `target_deadline` applies to accounts without a target of their own. All targets are
+    /// in seconds.
+    pub fn new(
+        mode: SubmissionMode,
+        target_deadline: u64,
+        account_id_to_target_deadline: HashMap<u64, u64>,
+    ) -> Self {
+        Self {
+            mode,
+            target_deadline,
+            account_id_to_target_deadline,
+            server_target_deadline: u64::MAX,
+            account_id_to_best_deadline: HashMap::new(),
+            best_deadline: u64::MAX,
+        }
+    }
+
+    /// Replaces mode and targets, e.g. after a config reload. The best deadlines of the round
+    /// are kept.
+    pub fn reconfigure(
+        &mut self,
+        mode: SubmissionMode,
+        target_deadline: u64,
+        account_id_to_target_deadline: HashMap<u64, u64>,
+    ) {
+        self.mode = mode;
+        self.target_deadline = target_deadline;
+        self.account_id_to_target_deadline = account_id_to_target_deadline;
+    }
+
+    /// Forgets the best deadlines of the last round.
+    pub fn new_round(&mut self, server_target_deadline: u64) {
+        self.server_target_deadline = server_target_deadline;
+        self.account_id_to_best_deadline.clear();
+        self.best_deadline = u64::MAX;
+    }
+
+    /// Decides about the `deadline` (in seconds) of `nonce_data`.
+    pub fn decide(&self, nonce_data: &NonceData, deadline: u64) -> Decision {
+        if nonce_data.deadline == u64::MAX {
+            return Decision::Skip(Reason::NoDeadline);
+        }
+
+        let target = min(
+            self.server_target_deadline,
+            *self
+                .account_id_to_target_deadline
+                .get(&nonce_data.account_id)
+                .unwrap_or(&self.target_deadline),
+        );
+        if deadline >= target {
+            return Decision::Skip(Reason::AboveTarget { target });
+        }
+
+        let account_best = *self
+            .account_id_to_best_deadline
+            .get(&nonce_data.account_id)
+            .unwrap_or(&u64::MAX);
+        match self.mode {
+            SubmissionMode::BelowTarget => Decision::Submit(Reason::BelowTarget { target }),
+            SubmissionMode::AccountBest if deadline < account_best => {
+                Decision::Submit(Reason::AccountBest)
+            }
+            SubmissionMode::AccountBest => {
+                Decision::Skip(Reason::NotAccountBest { best: account_best })
+            }
+            SubmissionMode::GlobalBest if deadline < self.best_deadline => {
+                Decision::Submit(Reason::GlobalBest)
+            }
+            SubmissionMode::GlobalBest => Decision::Skip(Reason::NotGlobalBest {
+                best: self.best_deadline,
+            }),
+        }
+    }
+
+    /// Best submitted deadline of every account in this round.
+    pub fn best_deadlines(&self) -> &HashMap<u64, u64> {
+        &self.account_id_to_best_deadline
+    }
+
+    /// Remembers a submitted deadline, later ones of the round have to beat it.
+    pub fn submitted(&mut self, account_id: u64, deadline: u64) {
+        let account_best = self
+            .account_id_to_best_deadline
+            .entry(account_id)
+            .or_insert(u64::MAX);
+        *account_best = min(*account_best, deadline);
+        self.best_deadline = min(self.best_deadline, deadline);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nonce_data(account_id: u64, deadline: u64) -> NonceData {
+        NonceData {
+            height: 1,
+            block: 1,
+            base_target: 1,
+            deadline,
+            nonce: 7,
+            reader_task_processed: false,
+            account_id,
+        }
+    }
+
+    fn decide(policy: &mut SubmissionPolicy, account_id: u64, deadline: u64) -> Decision {
+        let decision = policy.decide(&nonce_data(account_id, deadline), deadline);
+        if let Decision::Submit(_) = decision {
+            policy.submitted(account_id, deadline);
+        }
+        decision
+    }
+
+    #[test]
+    fn test_account_best() {
+        let mut policy = SubmissionPolicy::new(SubmissionMode::AccountBest, 1000, HashMap::new());
+        assert_eq!(decide(&mut policy, 1, 500), Decision::Submit(Reason::AccountBest));
+        assert_eq!(
+            decide(&mut policy, 1, 600),
+            Decision::Skip(Reason::NotAccountBest { best: 500 })
+        );
+        assert_eq!(decide(&mut policy, 2, 600), Decision::Submit(Reason::AccountBest));
+        assert_eq!(decide(&mut policy, 1, 400), Decision::Submit(Reason::AccountBest));
+
+        policy.new_round(u64::MAX);
+        assert_eq!(decide(&mut policy, 1, 600), Decision::Submit(Reason::AccountBest));
+
+        // a reload keeps the bests of the round
+        policy.reconfigure(SubmissionMode::AccountBest, 700, HashMap::new());
+        assert_eq!(
+            decide(&mut policy, 1, 650),
+            Decision::Skip(Reason::NotAccountBest { best: 600 })
+        );
+        assert_eq!(
+            decide(&mut policy, 2, 800),
+            Decision::Skip(Reason::AboveTarget { target: 700 })
+        );
+    }
+
+    #[test]
+    fn test_global_best() {
+        let mut policy = SubmissionPolicy::new(SubmissionMode::GlobalBest, 1000, HashMap::new());
+        assert_eq!(decide(&mut policy, 1, 500), Decision::Submit(Reason::GlobalBest));
+        assert_eq!(
+            decide(&mut policy, 2, 600),
+            Decision::Skip(Reason::NotGlobalBest { best: 500 })
+        );
+        assert_eq!(decide(&mut policy, 2, 300), Decision::Submit(Reason::GlobalBest));
+        assert_eq!(
+            decide(&mut policy, 1, 400),
+            Decision::Skip(Reason::NotGlobalBest { best: 300 })
+        );
+    }
+
+    #[test]
+    fn test_targets() {
+        let mut account_id_to_target_deadline = HashMap::new();
+        account_id_to_target_deadline.insert(2, 100);
+        let mut policy = SubmissionPolicy::new(
+            SubmissionMode::BelowTarget,
+            1000,
+            account_id_to_target_deadline,
+        );
+
+        assert_eq!(
+            decide(&mut policy, 1, 1000),
+            Decision::Skip(Reason::AboveTarget { target: 1000 })
+        );
+        assert_eq!(
+            decide(&mut policy, 1, 900),
+            Decision::Submit(Reason::BelowTarget { target: 1000 })
+        );
+        // below target doesn't care about improvements
+        assert_eq!(
+            decide(&mut policy, 1, 950),
+            Decision::Submit(Reason::BelowTarget { target: 1000 })
+        );
+        assert_eq!(
+            decide(&mut policy, 2, 200),
+            Decision::Skip(Reason::AboveTarget { target: 100 })
+        );
+
+        policy.new_round(50);
+        assert_eq!(
+            decide(&mut policy, 2, 60),
+            Decision::Skip(Reason::AboveTarget { target: 50 })
+        );
+        assert_eq!(
+            policy.decide(&nonce_data(1, u64::MAX), 0),
+            Decision::Skip(Reason::NoDeadline)
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Reads the plots of a drive through io_uring, keeping several reads in flight instead of
+//! one blocking read at a time.
+
+use crate::miner::Buffer;
+use crate::reader::{read_next, Delivery, DrivePlots};
+use crossbeam_channel;
+use crossbeam_channel::{Receiver, Sender};
+use io_uring::opcode::{self, types::Target};
+use io_uring::IoUring;
+use std::io;
+use std::mem;
+use stopwatch::Stopwatch;
+
+/// Reads in flight per drive.
+const QUEUE_DEPTH: usize = 8;
+
+/// Fails if the kernel doesn't support io_uring.
+pub fn probe() -> io::Result<()> {
+    IoUring::new(1).map(|_| ())
+}
+
+// a read submitted to the ring, the kernel owns its buffer until it completes
+struct InFlight {
+    buffer: Box<Buffer + Send>,
+    _iovec: Box<libc::iovec>,
+    plot: usize,
+    len: usize,
+    start_nonce: u64,
+}
+
+pub fn create_read_task(
+    mut delivery: Delivery,
+    plots: DrivePlots,
+    scoop: u32,
+    rx_empty_buffers: Receiver<Box<Buffer + Send>>,
+    tx_empty_buffers: Sender<Box<Buffer + Send>>,
+) -> (Sender<()>, impl FnOnce()) {
+    let (tx_interupt, rx_interupt) = crossbeam_channel::unbounded();
+
+    (tx_interupt, move || {
+        let mut ring = match IoUring::new(QUEUE_DEPTH as u32) {
+            Ok(ring) => ring,
+            Err(e) => {
+                error!(
+                    "reader: can't set up io_uring for drive {}: {} -> skip one round",
+                    delivery.drive(),
+                    e
+                );
+                return;
+            }
+        };
+
+        // plots stay locked for the whole round, reads in flight need their files open
+        let mut plots: Vec<_> = plots.iter().map(|p| p.lock().unwrap()).collect();
+        let mut failed: Vec<bool> = plots
+            .iter_mut()
+            .map(|p| match p.prepare(scoop) {
+                Ok(()) => false,
+                Err(e) => {
+                    error!(
+                        "reader: error preparing {} for reading: {} -> skip one round",
+                        p.meta().name,
+                        e
+                    );
+                    true
+                }
+            })
+            .collect();
+
+        let mut slots: Vec<Option<InFlight>> = (0..QUEUE_DEPTH).map(|_| None).collect();
+        let mut in_flight = 0;
+        // plot the next chunk is read from, failed plots are skipped
+        let mut current = 0;
+        let mut interrupted = false;
+        let mut delivered_finished = false;
+        let mut sw = Stopwatch::start_new();
+        loop {
+            // fill up the queue, only wait for a buffer if nothing is in flight
+            while !interrupted && in_flight < QUEUE_DEPTH {
+                while current < plots.len() && failed[current] {
+                    current += 1;
+                }
+                if current == plots.len() {
+                    break;
+                }
+                let mut buffer = if in_flight == 0 {
+                    match rx_empty_buffers.recv() {
+                        Ok(buffer) => buffer,
+                        Err(_) => return,
+                    }
+                } else {
+                    match rx_empty_buffers.try_recv() {
+                        Ok(buffer) => buffer,
+                        Err(_) => break,
+                    }
+                };
+                if rx_interupt.try_recv().is_ok() {
+                    tx_empty_buffers.send(buffer).unwrap();
+                    interrupted = true;
+                    break;
+                }
+
+                let mut_bs = buffer.get_buffer_for_writing();
+                let mut bs = mut_bs.lock().unwrap();
+                let p = &mut plots[current];
+                match p.next_chunk(scoop, bs.capacity() as u64) {
+                    Some(chunk) => {
+                        let slot = slots.iter().position(Option::is_none).unwrap();
+                        let iovec = Box::new(libc::iovec {
+                            iov_base: bs.as_mut_ptr() as *mut libc::c_void,
+                            iov_len: chunk.len,
+                        });
+                        let entry = opcode::Readv::new(Target::Fd(chunk.fd), &*iovec, 1)
+                            .offset(chunk.offset as i64)
+                            .build()
+                            .user_data(slot as u64);
+                        // the buffer and the iovec live in the slot until the read completes
+                        unsafe {
+                            if ring.submission().available().push(entry).is_err() {
+                                panic!("io_uring submission queue full");
+                            }
+                        }
+                        drop(bs);
+                        slots[slot] = Some(InFlight {
+                            buffer,
+                            _iovec: iovec,
+                            plot: current,
+                            len: chunk.len,
+                            start_nonce: chunk.start_nonce,
+                        });
+                        in_flight += 1;
+                        if chunk.finished {
+                            current += 1;
+                        }
+                    }
+                    None => {
+                        // not backed by a file or mapped, read it right away
+                        drop(bs);
+                        let (bytes_read, start_nonce, next_plot, mapped) =
+                            read_next(p, &mut buffer, scoop);
+                        let account_id = p.meta().account_id;
+                        if next_plot {
+                            current += 1;
+                        }
+                        while current < plots.len() && failed[current] {
+                            current += 1;
+                        }
+                        let finished = in_flight == 0 && current == plots.len();
+                        delivered_finished |= finished;
+                        delivery.deliver(
+                            buffer,
+                            bytes_read,
+                            start_nonce,
+                            finished,
+                            account_id,
+                            sw.elapsed_ms(),
+                            mapped,
+                        );
+                        sw.restart();
+                    }
+                }
+            }
+            if in_flight == 0 {
+                break;
+            }
+
+            if let Err(e) = ring.submit_and_wait(1) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!(
+                    "reader: io_uring of drive {} failed: {} -> skip one round",
+                    delivery.drive(),
+                    e
+                );
+                // the kernel might still write to the buffers in flight, never reuse them
+                mem::forget(slots);
+                mem::forget(ring);
+                return;
+            }
+
+            let completions: Vec<(u64, i32)> = ring
+                .completion()
+                .available()
+                .map(|cqe| (cqe.user_data(), cqe.result()))
+                .collect();
+            for (slot, result) in completions {
+                let read = slots[slot as usize].take().unwrap();
+                in_flight -= 1;
+                if interrupted || rx_interupt.try_recv().is_ok() {
+                    interrupted = true;
+                    read.buffer.unmap();
+                    tx_empty_buffers.send(read.buffer).unwrap();
+                    continue;
+                }
+
+                let p = &plots[read.plot];
+                let bytes_read = if result as usize == read.len {
+                    read.len
+                } else {
+                    let e = if result < 0 {
+                        io::Error::from_raw_os_error(-result)
+                    } else {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "short read")
+                    };
+                    error!(
+                        "reader: error reading chunk from {}: {} -> skip one round",
+                        p.meta().name,
+                        e
+                    );
+                    read.buffer.unmap();
+                    failed[read.plot] = true;
+                    0
+                };
+                while current < plots.len() && failed[current] {
+                    current += 1;
+                }
+                // the last buffer delivered finishes the drive, whichever read it was
+                let finished = in_flight == 0 && current == plots.len();
+                delivered_finished |= finished;
+                delivery.deliver(
+                    read.buffer,
+                    bytes_read,
+                    if bytes_read == 0 { 0 } else { read.start_nonce },
+                    finished,
+                    p.meta().account_id,
+                    sw.elapsed_ms(),
+                    None,
+                );
+                sw.restart();
+            }
+        }
+
+        // the last plots couldn't be prepared, the workers still have to learn that the drive
+        // is done
+        if !interrupted && !delivered_finished {
+            if let Ok(buffer) = rx_empty_buffers.recv() {
+                let account_id = plots[plots.len() - 1].meta().account_id;
+                delivery.deliver(buffer, 0, 0, true, account_id, 0, None);
+            }
+        }
+    })
+}
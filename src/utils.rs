@@ -25,8 +25,31 @@ pub fn new_thread_pool(num_threads: usize, thread_pinning: bool) -> rayon::Threa
 
 cfg_if! {
     if #[cfg(unix)] {
+        use std::io;
         use std::process::Command;
 
+        /// Raises the soft limit of open files to the hard one and returns the limit in effect.
+        pub fn raise_open_files_limit() -> io::Result<u64> {
+            let mut limit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let raised = libc::rlimit {
+                rlim_cur: limit.rlim_max,
+                rlim_max: limit.rlim_max,
+            };
+            // macOS refuses limits above OPEN_MAX, the current one stays then
+            if limit.rlim_cur < limit.rlim_max
+                && unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0
+            {
+                return Ok(raised.rlim_cur as u64);
+            }
+            Ok(limit.rlim_cur as u64)
+        }
+
         pub fn get_device_id(path: &str) -> String {
             let output = Command::new("stat")
                 .arg(path)
@@ -107,8 +130,14 @@ cfg_if! {
         use std::ffi::OsStr;
         use std::iter::once;
         use std::ffi::CString;
+        use std::io;
         use std::path::Path;
 
+        /// Handles aren't limited per process on windows.
+        pub fn raise_open_files_limit() -> io::Result<u64> {
+            Ok(u64::max_value())
+        }
+
         pub fn get_device_id(path: &str) -> String {
             let path_encoded: Vec<u16> = OsStr::new(path).encode_wide().chain(once(0)).collect();
             let mut volume_encoded: Vec<u16> = OsStr::new(path)
@@ -165,6 +194,20 @@ cfg_if! {
     }
 }
 
+/// A fresh temp dir for the test `name`, unique per process so that concurrent test runs don't
+/// clean up each other's files.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "poc_mining_test_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,335 @@
+use crate::plot::{open, Meta, Plot, NONCE_SIZE, SCOOPS_IN_NONCE, SCOOP_SIZE};
+use crate::plotter::{noncegen, NONCES_PER_CALL};
+use crate::utils::new_thread_pool;
+use rand::prelude::*;
+use rand::seq::index;
+use rayon::prelude::*;
+use std::fs::read_dir;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Plot files (or directories of plot files) to verify and how thoroughly.
+pub struct VerifyTask {
+    pub paths: Vec<PathBuf>,
+    /// Number of nonces checked per plot, `None` checks all of them.
+    pub sample: Option<u64>,
+    pub threads: usize,
+    pub nonces_per_chunk: u64,
+}
+
+/// Inclusive range of absolute nonce numbers.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NonceRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    pub file: String,
+    pub passed: bool,
+    pub nonces_checked: u64,
+    pub nonces_corrupt: u64,
+    pub corrupt_ranges: Vec<NonceRange>,
+    pub error: Option<String>,
+}
+
+impl VerifyReport {
+    fn failed(file: String, error: String) -> Self {
+        VerifyReport {
+            file,
+            passed: false,
+            nonces_checked: 0,
+            nonces_corrupt: 0,
+            corrupt_ranges: Vec::new(),
+            error: Some(error),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!("FAIL {}: {}", self.file, error);
+        }
+        if self.passed {
+            format!("PASS {}: {} nonces ok", self.file, self.nonces_checked)
+        } else {
+            let ranges: Vec<String> = self
+                .corrupt_ranges
+                .iter()
+                .map(|r| format!("{}-{}", r.start, r.end))
+                .collect();
+            format!(
+                "FAIL {}: {}/{} nonces corrupt, ranges={}",
+                self.file,
+                self.nonces_corrupt,
+                self.nonces_checked,
+                ranges.join(",")
+            )
+        }
+    }
+}
+
+/// Verifies all plot files of the task and returns one report per file.
+pub fn verify(task: &VerifyTask) -> Vec<VerifyReport> {
+    let pool = new_thread_pool(task.threads, false);
+    plot_files(&task.paths)
+        .iter()
+        .map(|path| verify_plot(path, task.sample, task.nonces_per_chunk, &pool))
+        .collect()
+}
+
+fn plot_files(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            match read_dir(path) {
+                Ok(entries) => {
+                    let mut entries: Vec<PathBuf> = entries
+                        .filter_map(|e| e.ok())
+                        .map(|e| e.path())
+                        .filter(|p| p.is_file())
+                        .filter(|p| {
+                            let plot = is_plot_name(p);
+                            if !plot {
+                                info!("skipping {}: not a plot file", p.to_str().unwrap());
+                            }
+                            plot
+                        })
+                        .collect();
+                    entries.sort();
+                    files.append(&mut entries);
+                }
+                Err(e) => warn!("can't read {}: {}", path.to_str().unwrap(), e),
+            }
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+// plot files are named `{account}_{start}_{nonces}`, other files in a plot dir (e.g. unfinished
+// plotter output) are skipped like the miner does
+fn is_plot_name(path: &PathBuf) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let parts: Vec<&str> = name.split('_').collect();
+    parts.len() == 3 && parts.iter().all(|p| p.parse::<u64>().is_ok())
+}
+
+/// Regenerates the sampled nonces of a plot and compares every scoop with the data on disk.
+pub fn verify_plot(
+    path: &PathBuf,
+    sample: Option<u64>,
+    nonces_per_chunk: u64,
+    pool: &rayon::ThreadPool,
+) -> VerifyReport {
+    let file = path.to_str().unwrap().to_owned();
+    let meta = match Plot::new(path, false, false, false) {
+        Ok(p) => p.meta,
+        Err(e) => return VerifyReport::failed(file, e.to_string()),
+    };
+
+    let offsets = match sample {
+        Some(sample) if sample < meta.nonces => sample_offsets(meta.nonces, sample),
+        _ => (0..meta.nonces).collect(),
+    };
+
+    let corrupt = match find_corrupt_nonces(path, &meta, &offsets, nonces_per_chunk, pool) {
+        Ok(corrupt) => corrupt,
+        Err(e) => return VerifyReport::failed(file, e.to_string()),
+    };
+
+    VerifyReport {
+        file,
+        passed: corrupt.is_empty(),
+        nonces_checked: offsets.len() as u64,
+        nonces_corrupt: corrupt.len() as u64,
+        corrupt_ranges: to_ranges(&corrupt),
+        error: None,
+    }
+}
+
+// first and last nonce are always checked since half-written plots are missing their tail
+fn sample_offsets(nonces: u64, sample: u64) -> Vec<u64> {
+    let mut rng = thread_rng();
+    let mut offsets: Vec<u64> = index::sample(&mut rng, nonces as usize, sample as usize)
+        .into_iter()
+        .map(|i| i as u64)
+        .collect();
+    offsets.push(0);
+    offsets.push(nonces - 1);
+    offsets.sort();
+    offsets.dedup();
+    offsets
+}
+
+fn find_corrupt_nonces(
+    path: &PathBuf,
+    meta: &Meta,
+    offsets: &[u64],
+    nonces_per_chunk: u64,
+    pool: &rayon::ThreadPool,
+) -> io::Result<Vec<u64>> {
+    let mut fh = open(path)?;
+    let mut corrupt = Vec::new();
+
+    // consecutive offsets are verified together so that each scoop is a single read
+    let mut i = 0;
+    while i < offsets.len() {
+        let start = offsets[i];
+        let mut len = 1;
+        while i + len < offsets.len()
+            && (len as u64) < nonces_per_chunk
+            && offsets[i + len] == start + len as u64
+        {
+            len += 1;
+        }
+
+        let mut expected = vec![0u8; len * NONCE_SIZE as usize];
+        pool.install(|| {
+            expected
+                .par_chunks_mut(NONCES_PER_CALL * NONCE_SIZE as usize)
+                .enumerate()
+                .for_each(|(j, cache)| {
+                    noncegen(
+                        meta.account_id,
+                        meta.start_nonce + start + (j * NONCES_PER_CALL) as u64,
+                        cache.len() as u64 / NONCE_SIZE,
+                        cache,
+                    )
+                });
+        });
+
+        let mut bad = vec![false; len];
+        let mut on_disk = vec![0u8; len * SCOOP_SIZE as usize];
+        for scoop in 0..SCOOPS_IN_NONCE {
+            fh.seek(SeekFrom::Start(
+                scoop * meta.nonces * SCOOP_SIZE + start * SCOOP_SIZE,
+            ))?;
+            fh.read_exact(&mut on_disk)?;
+            for (j, bad) in bad.iter_mut().enumerate() {
+                let disk = j * SCOOP_SIZE as usize;
+                let exp = j * NONCE_SIZE as usize + (scoop * SCOOP_SIZE) as usize;
+                if on_disk[disk..disk + SCOOP_SIZE as usize]
+                    != expected[exp..exp + SCOOP_SIZE as usize]
+                {
+                    *bad = true;
+                }
+            }
+        }
+
+        corrupt.extend(
+            bad.iter()
+                .enumerate()
+                .filter(|(_, &bad)| bad)
+                .map(|(j, _)| meta.start_nonce + start + j as u64),
+        );
+        i += len;
+    }
+
+    Ok(corrupt)
+}
+
+fn to_ranges(nonces: &[u64]) -> Vec<NonceRange> {
+    let mut ranges: Vec<NonceRange> = Vec::new();
+    for &nonce in nonces {
+        if let Some(range) = ranges.last_mut() {
+            if range.end + 1 == nonce {
+                range.end = nonce;
+                continue;
+            }
+        }
+        ranges.push(NonceRange {
+            start: nonce,
+            end: nonce,
+        });
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plotter::{plot, PlotterTask};
+    use crate::utils::test_dir;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn test_to_ranges() {
+        assert_eq!(to_ranges(&[]), vec![]);
+        assert_eq!(
+            to_ranges(&[3, 4, 5, 9, 11, 12]),
+            vec![
+                NonceRange { start: 3, end: 5 },
+                NonceRange { start: 9, end: 9 },
+                NonceRange { start: 11, end: 12 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plot_files() {
+        let dir = test_dir("plot_files");
+        for name in &["1337_0_8", "1337_8_8.tmp", "notes.txt", "1337_x_8"] {
+            fs::File::create(dir.join(name)).unwrap();
+        }
+
+        assert_eq!(plot_files(&[dir.clone()]), vec![dir.join("1337_0_8")]);
+        // files named explicitly are always verified
+        let stray = dir.join("notes.txt");
+        assert_eq!(plot_files(&[stray.clone()]), vec![stray]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify_plot() {
+        let output_path = test_dir("verify");
+
+        let path = plot(&PlotterTask {
+            account_id: 7,
+            start_nonce: 100,
+            nonces: 3,
+            output_path: output_path.clone(),
+            use_direct_io: false,
+            threads: 2,
+            nonces_per_chunk: 3,
+            show_progress: false,
+        })
+        .unwrap();
+        let pool = new_thread_pool(2, false);
+
+        let report = verify_plot(&path, None, 2, &pool);
+        assert!(report.passed);
+        assert_eq!(report.nonces_checked, 3);
+
+        // flip a byte of nonce 101 in scoop 17
+        let pos = SeekFrom::Start(17 * 3 * SCOOP_SIZE + SCOOP_SIZE + 5);
+        let mut fh = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut byte = [0u8; 1];
+        fh.seek(pos).unwrap();
+        fh.read_exact(&mut byte).unwrap();
+        fh.seek(pos).unwrap();
+        fh.write_all(&[!byte[0]]).unwrap();
+        drop(fh);
+
+        let report = verify_plot(&path, None, 2, &pool);
+        assert!(!report.passed);
+        assert_eq!(report.nonces_corrupt, 1);
+        assert_eq!(
+            report.corrupt_ranges,
+            vec![NonceRange {
+                start: 101,
+                end: 101
+            }]
+        );
+
+        fs::remove_dir_all(&output_path).unwrap();
+    }
+}